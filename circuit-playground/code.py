@@ -8,7 +8,16 @@ import neopixel
 from rainbowio import colorwheel
 import adafruit_fancyled.adafruit_fancyled as fancy
 
-touch_a1 = touchio.TouchIn(board.A1)
+# Every input with capacitive touch, a soil sensor can go on any of them
+touch_inputs = [
+    ('a1', touchio.TouchIn(board.A1)),
+    ('a2', touchio.TouchIn(board.A2)),
+    ('a3', touchio.TouchIn(board.A3)),
+    ('a4', touchio.TouchIn(board.A4)),
+    ('a5', touchio.TouchIn(board.A5)),
+    ('a6', touchio.TouchIn(board.A6)),
+]
+touch_a1 = touch_inputs[0][1]
 button = digitalio.DigitalInOut(board.BUTTON_A)
 button.switch_to_input(pull=digitalio.Pull.DOWN)
 pixels = neopixel.NeoPixel(board.NEOPIXEL, 10, brightness=1.0, auto_write=False)
@@ -20,7 +29,8 @@ def send_heartbeat():
 
 def send_readings():
     print('->', 'readings')
-    usb_cdc.data.write(bytes('ra1' + str(touch_a1.raw_value) + '\n', 'utf-8'))
+    for name, touch in touch_inputs:
+        usb_cdc.data.write(bytes('r' + name + ':' + str(touch.raw_value) + '\n', 'utf-8'))
     usb_cdc.data.flush()
 
 show_readings = False
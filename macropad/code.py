@@ -82,6 +82,15 @@ def send_message(message):
     usb_cdc.data.write(bytes(message_json + '\n', 'utf-8'))
     usb_cdc.data.flush()
 
+PROTOCOL_VERSION = 1
+
+def send_hello():
+    send_message(dict(kind='hello', version=PROTOCOL_VERSION))
+
+def send_heartbeat():
+    usb_cdc.data.write(bytes('h\n', 'utf-8'))
+    usb_cdc.data.flush()
+
 def send_potentiometer(value):
     print('->', 'potentiometer')
     usb_cdc.data.write(bytes('p' + str(value) + '\n', 'utf-8'))
@@ -99,9 +108,17 @@ def send_y_axis(value):
 
 message_buffer = ''
 usb_cdc.data.timeout = 0
+last_heartbeat = 0
+pending_idle_prompt = None
 
 def get_message():
     global message_buffer
+    global last_heartbeat
+    global pending_idle_prompt
+
+    if time.monotonic() - last_heartbeat >= 1:
+        last_heartbeat = time.monotonic()
+        send_heartbeat()
 
     message_buffer += usb_cdc.data.read(100).decode('utf-8')
 
@@ -122,9 +139,19 @@ def get_message():
     in_waiting = usb_cdc.data.in_waiting
     print(f'<- {in_waiting:3}', json.dumps(message))
 
+    # The hub greets on every connect and waits for the answer before accepting requests
+    if message['kind'] == 'hello':
+        send_hello()
+        return None
+
     if message['kind'] == 'heartbeat':
         return None
 
+    # Pushed by the hub at any time; shown once the current app is back at its main screen
+    if message['kind'] == 'idleTimeEntry':
+        pending_idle_prompt = message['idle']
+        return None
+
     return message
 
 last_activity = time.time()
@@ -383,7 +410,7 @@ def wait_for_reply_animated(index, gradient, timeout=8000):
 
 def check_response(message, pixel_index, success_color):
     if not message or message['kind'] != 'success':
-        show_error()
+        show_error(message)
     else:
         macropad.pixels[pixel_index] = success_color.pack()
         macropad.pixels.show()
@@ -391,8 +418,17 @@ def check_response(message, pixel_index, success_color):
         clear_pixels()
         time.sleep(0.1)
 
-def show_error():
-    macropad.pixels.fill(colors['magenta'].pack())
+error_colors = dict(
+    unauthorized='red',
+    rateLimited='amber',
+    notFound='gray',
+    unavailable='light_blue',
+    conflict='yellow',
+)
+
+def show_error(message=None):
+    reason = message.get('reason') if message and message['kind'] == 'error' else None
+    macropad.pixels.fill(colors[error_colors.get(reason, 'magenta')].pack())
     macropad.pixels.show()
     time.sleep(0.3)
     clear_pixels()
@@ -411,6 +447,7 @@ apps = [
     dict(name='unicorn', color=colors_50['cream']),
     dict(name='bluetooth', color=colors_50['light_blue']),
     dict(name='servo', color=colors_50['yellow']),
+    dict(name='focus', color=colors_50['peach']),
 ]
 
 def app_switch():
@@ -484,7 +521,7 @@ def sleep():
         get_message()
         key_event = get_key_event(peek=True)
 
-        if key_event:
+        if key_event or pending_idle_prompt:
             break
 
     reset_activity_timer()
@@ -515,6 +552,7 @@ def toggl():
     macropad.pixels[1] = colors_50['red'].pack()
     macropad.pixels[2] = colors_50['green'].pack()
     macropad.pixels[3] = colors_50['light_blue'].pack()
+    macropad.pixels[4] = colors_50['amber'].pack()
 
     macropad.pixels[5] = colors_50['white'].pack()
     macropad.pixels[8] = colors_50['white'].pack()
@@ -561,6 +599,10 @@ def toggl():
                 state['name'] = 'toggl_adjust_time'
                 break
 
+            if key_event.key_number == 4:
+                state['name'] = 'toggl_select_project'
+                break
+
             if key_state[10]:
                 if key_event.key_number == 5:
                     state['toggl_index'] -= 4 + (state['toggl_index'] % 4)
@@ -631,7 +673,7 @@ def toggl_get_time_entries():
 
         if message:
             if message['kind'] == 'timeEntry':
-                entries.append(message['timeEntry']['description'])
+                entries.append(message['timeEntry'])
             else:
                 break
 
@@ -639,53 +681,206 @@ def toggl_get_time_entries():
             break
 
     if not message or message['kind'] != 'success':
-        show_error()
+        show_error(message)
         reset_activity_timer()
         state['name'] = 'toggl'
         return
 
-    options = list(set(entries))
-    options.sort()
+    options = sorted(entries, key=lambda entry: entry['description'])
 
     if not len(options):
-        options.append('')
+        options.append(dict(description='', projectId=None, tagIds=[]))
 
     clear_pixels()
     reset_activity_timer()
 
     state['toggl_index'] = 0
     state['toggl_options'] = options
+    state['toggl_options_group'] = create_options_group(
+        [option['description'] for option in options],
+        state['toggl_index'],
+    )
 
-    state['toggl_options_group'] = displayio.Group()
+    state['name'] = 'toggl'
 
-    for i, option in enumerate(options):
-        label = display_text.bitmap_label.Label(
-            font=terminalio.FONT,
-            text=option
-        )
+def create_option_tile_grid(text, i, selected):
+    label = display_text.bitmap_label.Label(
+        font=terminalio.FONT,
+        text=text or ' '
+    )
+
+    label_tile_grid = displayio.TileGrid(
+        bitmap=label.bitmap,
+        pixel_shader=palettes['normal'],
+        y=i*16,
+    )
 
-        palette = palettes['normal']
+    set_option_tile_grid_selected(label_tile_grid, selected)
 
-        label_tile_grid = displayio.TileGrid(
-            bitmap=label.bitmap,
-            pixel_shader=palette,
-            y=i*16,
-        )
+    return label_tile_grid
+
+def create_options_group(labels, index):
+    group = displayio.Group()
+
+    for i, text in enumerate(labels):
+        group.append(create_option_tile_grid(text, i, i == index))
+
+    return group
+
+def fetch_list(request_kind, item_kind):
+    send_message(dict(kind=request_kind))
+
+    items = []
+
+    clear_pixels()
+    start = supervisor.ticks_ms()
+
+    while True:
+        position = supervisor.ticks_ms() - start
+
+        color = fancy.palette_lookup(gradients_50['yellow'], position / 1000)
+        macropad.pixels[4] = color.pack()
+        macropad.pixels.show()
+
+        message = get_message()
+
+        if message:
+            if message['kind'] == item_kind:
+                items.append(message[item_kind])
+            else:
+                break
+
+        if position > 5000:
+            break
+
+    if not message or message['kind'] != 'success':
+        show_error(message)
+        return None
+
+    return items
+
+# Lets the user scroll through labels with 5/8, returns the chosen indexes once 0 is pressed.
+# With multiple, 2 toggles the highlighted label, otherwise 2 picks it right away.
+def choose_from_list(labels, multiple=False):
+    index = 0
+    chosen = set()
+
+    def label_text(i):
+        if not multiple:
+            return labels[i]
+        return ('* ' if i in chosen else '  ') + labels[i]
+
+    group = create_options_group([label_text(i) for i in range(len(labels))], index)
+
+    clear_pixels()
+    macropad.pixels[0] = colors_50['white'].pack()
+    macropad.pixels[2] = colors_50['green'].pack()
+    macropad.pixels[5] = colors_50['white'].pack()
+    macropad.pixels[8] = colors_50['white'].pack()
+    macropad.pixels.show()
+
+    macropad.display.show(group)
+    macropad.display.refresh()
+
+    while True:
+        if check_activity_timeout('toggl'):
+            return None
+
+        get_message()
+
+        key_event = get_key_event()
+
+        if not key_event or not key_event.pressed:
+            continue
+
+        previous_index = index
 
-        state['toggl_options_group'].append(label_tile_grid)
+        if key_event.key_number == 0:
+            return sorted(chosen)
 
-        selected = i == state['toggl_index']
-        set_option_tile_grid_selected(label_tile_grid, selected)
+        if key_event.key_number == 2:
+            if not multiple:
+                return [index]
+
+            chosen ^= {index}
+            group[index] = create_option_tile_grid(label_text(index), index, True)
+            macropad.display.refresh()
+
+        if key_event.key_number == 5:
+            index = (index - 1) % len(labels)
+
+        if key_event.key_number == 8:
+            index = (index + 1) % len(labels)
+
+        if index != previous_index:
+            set_option_tile_grid_selected(group[previous_index], False)
+            set_option_tile_grid_selected(group[index], True)
+            group.y = -int(index / 4)*64
+            macropad.display.refresh()
+
+def toggl_select_project():
+    global state
 
+    projects = fetch_list('getProjects', 'project')
+
+    if projects is None:
+        reset_activity_timer()
+        state['name'] = 'toggl'
+        return
+
+    reset_activity_timer()
+
+    chosen = choose_from_list(['(default project)'] + [project['name'] for project in projects])
+
+    if not chosen:
+        state['name'] = 'toggl'
+        return
+
+    state['toggl_project_id'] = projects[chosen[0] - 1]['id'] if chosen[0] else None
+    state['name'] = 'toggl_select_tags'
+
+def toggl_select_tags():
+    global state
+
+    tags = fetch_list('getTags', 'tag')
+
+    if tags is None:
+        reset_activity_timer()
+        state['name'] = 'toggl'
+        return
+
+    reset_activity_timer()
+
+    chosen = choose_from_list([tag['name'] for tag in tags], multiple=True) if tags else []
+
+    if chosen is None:
+        state['name'] = 'toggl'
+        return
+
+    state['toggl_tag_ids'] = [tags[i]['id'] for i in chosen]
+    state['toggl_selection_override'] = True
     state['name'] = 'toggl'
 
 def toggl_send_start():
     global state
 
+    option = state['toggl_options'][state['toggl_index']]
+
+    # A project/tags pick from key 4 applies to the next start, otherwise reuse the entry's own
+    if state['toggl_selection_override']:
+        project_id = state['toggl_project_id']
+        tag_ids = state['toggl_tag_ids']
+        state['toggl_selection_override'] = False
+    else:
+        project_id = option.get('projectId')
+        tag_ids = option.get('tagIds', [])
+
     send_message(dict(
         kind='startTimeEntry',
         timeEntry=dict(
-            description=state['toggl_options'][state['toggl_index']],
+            description=option['description'],
+            projectId=project_id,
+            tagIds=tag_ids,
         ),
     ))
 
@@ -1150,6 +1345,239 @@ def servo():
             break
 
 
+focus_settings = dict(
+    work=dict(key='focus_work_minutes', label='work', minimum=5, maximum=90),
+    rest=dict(key='focus_break_minutes', label='break', minimum=1, maximum=30),
+    rounds=dict(key='focus_rounds', label='rounds', minimum=1, maximum=8),
+)
+
+def focus():
+    global state
+
+    clear_pixels()
+    macropad.pixels[0] = colors_50['green'].pack()
+    macropad.pixels[1] = colors_50['red'].pack()
+    macropad.pixels[2] = colors_50['white'].pack()
+    macropad.pixels[3] = colors_50['yellow'].pack()
+    macropad.pixels[4] = colors_50['light_blue'].pack()
+
+    set_toolbar_pixels()
+    macropad.pixels.show()
+
+    option = state['toggl_options'][state['toggl_index']]
+
+    group = displayio.Group()
+    group.append(display_text.bitmap_label.Label(
+        font=terminalio.FONT,
+        text='{}m / {}m x{}'.format(
+            state['focus_work_minutes'],
+            state['focus_break_minutes'],
+            state['focus_rounds'],
+        ),
+        y=10,
+    ))
+    group.append(display_text.bitmap_label.Label(
+        font=terminalio.FONT,
+        text=option['description'] or '(continue last)',
+        y=30,
+    ))
+    macropad.display.show(group)
+    macropad.display.refresh()
+
+    while True:
+        if check_activity_timeout('focus'):
+            break
+
+        get_message()
+        key_event = get_key_event()
+
+        if key_event and key_event.pressed:
+            if key_event.key_number == 11:
+                state['name'] = 'app_switch'
+                state['app_switch_source'] = 'focus'
+                break
+
+            if key_event.key_number == 0:
+                state['name'] = 'focus_send_start'
+                break
+
+            if key_event.key_number == 1:
+                state['name'] = 'focus_send_stop'
+                break
+
+            if key_event.key_number == 2:
+                state['name'] = 'focus_adjust'
+                state['focus_setting'] = 'rounds'
+                break
+
+            if key_event.key_number == 3:
+                state['name'] = 'focus_adjust'
+                state['focus_setting'] = 'work'
+                break
+
+            if key_event.key_number == 4:
+                state['name'] = 'focus_adjust'
+                state['focus_setting'] = 'rest'
+                break
+
+def focus_adjust():
+    global state
+
+    setting = focus_settings[state['focus_setting']]
+
+    clear_pixels()
+    macropad.pixels[1] = colors_50['red'].pack()
+    macropad.pixels[2] = colors_50['green'].pack()
+    macropad.pixels[5] = colors_50['white'].pack()
+    macropad.pixels[8] = colors_50['white'].pack()
+    macropad.pixels[9] = colors_50['white'].pack()
+    macropad.pixels.show()
+
+    group = displayio.Group(
+        x=macropad.display.width//2,
+        y=macropad.display.height//2,
+    )
+
+    label = display_text.label.Label(
+        text='',
+        font=terminalio.FONT,
+        anchor_point=(0.5, 0.5),
+        anchored_position=(0, 0),
+    )
+    group.append(label)
+
+    macropad.display.show(group)
+
+    value = state[setting['key']]
+    while True:
+        key_event = get_key_event()
+
+        if key_event and key_event.pressed:
+            step = 5 if key_state[9] else 1
+
+            if key_event.key_number == 5:
+                value += step
+
+            if key_event.key_number == 8:
+                value -= step
+
+            if key_event.key_number == 2:
+                state[setting['key']] = value
+                state['name'] = 'focus'
+                return
+
+            if key_event.key_number == 1:
+                state['name'] = 'focus'
+                return
+
+        value = min(setting['maximum'], max(setting['minimum'], value))
+
+        label.text = '{} {}'.format(value, setting['label'])
+        macropad.display.refresh()
+
+def focus_send_start():
+    global state
+
+    option = state['toggl_options'][state['toggl_index']]
+
+    # Without a description the hub continues the last time entry each work phase
+    send_message(dict(
+        kind='startFocusSession',
+        session=dict(
+            timeEntry=option if option['description'] else None,
+            workMinutes=state['focus_work_minutes'],
+            breakMinutes=state['focus_break_minutes'],
+            rounds=state['focus_rounds'],
+        ),
+    ))
+
+    message = wait_for_reply_animated(0, gradients_50['green'])
+    check_response(message, 0, colors_50['green'])
+
+    reset_activity_timer()
+    state['name'] = 'focus'
+
+def focus_send_stop():
+    global state
+
+    send_message(dict(kind='stopFocusSession'))
+
+    message = wait_for_reply_animated(1, gradients_50['red'])
+    check_response(message, 1, colors_50['red'])
+
+    reset_activity_timer()
+    state['name'] = 'focus'
+
+
+def format_minutes(minutes):
+    return '{}h{:02}m'.format(minutes // 60, minutes % 60)
+
+def idle_prompt():
+    global state
+    global pending_idle_prompt
+
+    prompt = pending_idle_prompt
+
+    group = displayio.Group()
+    group.append(display_text.bitmap_label.Label(
+        font=terminalio.FONT,
+        text=prompt['description'] or '(no description)',
+        y=10,
+    ))
+    group.append(display_text.bitmap_label.Label(
+        font=terminalio.FONT,
+        text='running ' + format_minutes(prompt['runningMinutes']),
+        y=30,
+    ))
+    group.append(display_text.bitmap_label.Label(
+        font=terminalio.FONT,
+        text='idle ' + format_minutes(prompt['idleMinutes']),
+        y=45,
+    ))
+    macropad.display.show(group)
+    macropad.display.refresh()
+
+    clear_pixels()
+    start = supervisor.ticks_ms()
+
+    resolutions = {1: 'stop', 2: 'keep', 3: 'trim'}
+    resolution_colors = {1: 'red', 2: 'green', 3: 'yellow'}
+
+    while True:
+        position = supervisor.ticks_ms() - start
+
+        for index, color in resolution_colors.items():
+            macropad.pixels[index] = fancy.palette_lookup(
+                gradients_50[color],
+                (position % 1000) / 1000,
+            ).pack()
+        macropad.pixels.show()
+
+        get_message()
+        key_event = get_key_event()
+
+        if key_event and key_event.pressed and key_event.key_number in resolutions:
+            break
+
+    send_message(dict(
+        kind='resolveIdleTimeEntry',
+        resolution=resolutions[key_event.key_number],
+    ))
+
+    message = wait_for_reply_animated(
+        key_event.key_number,
+        gradients_50[resolution_colors[key_event.key_number]],
+    )
+    check_response(
+        message,
+        key_event.key_number,
+        colors_50[resolution_colors[key_event.key_number]],
+    )
+
+    pending_idle_prompt = None
+    reset_activity_timer()
+    state['name'] = state['idle_prompt_next_name']
+
 initial_state = dict(
     name='startup',
     options=[''],
@@ -1160,10 +1588,20 @@ initial_state = dict(
     sleep_next_name=None,
 
     toggl_index=0,
-    toggl_options=[''],
+    toggl_options=[dict(description='', projectId=None, tagIds=[])],
     toggl_options_loaded=False,
     toggl_options_group=displayio.Group(),
     toggl_adjust_minutes=0,
+    toggl_project_id=None,
+    toggl_tag_ids=[],
+    toggl_selection_override=False,
+
+    focus_work_minutes=25,
+    focus_break_minutes=5,
+    focus_rounds=4,
+    focus_setting='work',
+
+    idle_prompt_next_name='toggl',
 )
 
 state_handlers = dict(
@@ -1178,6 +1616,8 @@ state_handlers = dict(
     toggl_adjust_time=toggl_adjust_time,
     toggl_send_adjust_time=toggl_send_adjust_time,
     toggl_send_continue=toggl_send_continue,
+    toggl_select_project=toggl_select_project,
+    toggl_select_tags=toggl_select_tags,
 
     unicorn=unicorn,
     unicorn_send_read_inbox=unicorn_send_read_inbox,
@@ -1191,6 +1631,13 @@ state_handlers = dict(
     bluetooth_send_switch_bose_fractal=bluetooth_send_switch_bose_fractal,
 
     servo=servo,
+
+    focus=focus,
+    focus_adjust=focus_adjust,
+    focus_send_start=focus_send_start,
+    focus_send_stop=focus_send_stop,
+
+    idle_prompt=idle_prompt,
 )
 
 display_wake()
@@ -1199,6 +1646,12 @@ macropad.pixels.auto_write = False
 
 state = initial_state
 
+send_hello()
+
 while True:
+    if pending_idle_prompt and state['name'] in [app['name'] for app in apps]:
+        state['idle_prompt_next_name'] = state['name']
+        state['name'] = 'idle_prompt'
+
     print('#', state['name'])
     state_handlers[str(state['name'])]()
@@ -0,0 +1,439 @@
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::ConfigError;
+use crate::state_file;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertsConfig {
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    // Which alerts are raised, kept across restarts so they still clear afterwards
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    "alerts.json".to_string()
+}
+
+// At least one of `above` or `below`, both for a range. The alert clears once the reading is back
+// past the threshold by `hysteresis`, so a value hovering around it doesn't keep alerting.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    // `<source>.<reading>`, e.g. `urban.ec02` or `soil.a1`
+    pub series: String,
+    #[serde(default)]
+    pub above: Option<f64>,
+    #[serde(default)]
+    pub below: Option<f64>,
+    #[serde(default)]
+    pub hysteresis: f64,
+    // How long the reading has to stay out of range before alerting
+    #[serde(default)]
+    pub sustained_secs: u64,
+    // `{value}` is replaced with the reading; without a message the Unicorn isn't told
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub effects: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    name: String,
+    series: String,
+    above: Option<f64>,
+    below: Option<f64>,
+    hysteresis: f64,
+    sustained: chrono::TimeDelta,
+    message: Option<String>,
+    effects: Vec<String>,
+}
+
+impl Rule {
+    pub fn compile(rules: &[RuleConfig]) -> Result<Vec<Self>, ConfigError> {
+        rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                // Alerts are raised and cleared by name
+                if rules[..index].iter().any(|other| other.name == rule.name) {
+                    return Err(ConfigError::Invalid(
+                        "alerts".to_string(),
+                        format!("more than one rule is named {}", rule.name),
+                    ));
+                }
+
+                if rule.above.is_none() && rule.below.is_none() {
+                    return Err(ConfigError::Invalid(
+                        "alerts".to_string(),
+                        format!("rule {} needs above or below", rule.name),
+                    ));
+                }
+
+                if rule.hysteresis < 0.0 {
+                    return Err(ConfigError::Invalid(
+                        "alerts".to_string(),
+                        format!("rule {} has a negative hysteresis", rule.name),
+                    ));
+                }
+
+                Ok(Self {
+                    name: rule.name.clone(),
+                    series: rule.series.clone(),
+                    above: rule.above,
+                    below: rule.below,
+                    hysteresis: rule.hysteresis,
+                    sustained: chrono::TimeDelta::seconds(rule.sustained_secs as i64),
+                    message: rule.message.clone(),
+                    effects: rule.effects.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn out_of_range(&self, value: f64) -> bool {
+        self.above.is_some_and(|above| value > above)
+            || self.below.is_some_and(|below| value < below)
+    }
+
+    fn back_in_range(&self, value: f64) -> bool {
+        self.above
+            .is_none_or(|above| value <= above - self.hysteresis)
+            && self
+                .below
+                .is_none_or(|below| value >= below + self.hysteresis)
+    }
+
+    // Raising or clearing is up to the caller, this only moves between the states
+    fn next(&self, state: State, value: f64, now: chrono::DateTime<chrono::Utc>) -> State {
+        let state = match state {
+            State::Clear | State::Pending(_) if !self.out_of_range(value) => State::Clear,
+            State::Clear => State::Pending(now),
+            State::Raised(_) if self.back_in_range(value) => State::Clear,
+            state => state,
+        };
+
+        match state {
+            State::Pending(since) if now - since >= self.sustained => State::Raised(value),
+            state => state,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum State {
+    #[default]
+    Clear,
+    Pending(chrono::DateTime<chrono::Utc>),
+    // With the reading it was raised at
+    Raised(f64),
+}
+
+// Evaluates every sensor reading against the rules; an alert is raised on the "alert" topic for
+// the displays, and as a message for the Unicorn, once and cleared once
+pub struct Alerts {
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    rules: Vec<Rule>,
+    path: String,
+    states: BTreeMap<String, State>,
+}
+
+impl Actor for Alerts {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        Vec<Rule>,
+        String,
+    );
+    type Error = state_file::StateFileError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, rules, path) = state;
+
+        let mut states: BTreeMap<String, State> = state_file::load(&path).await?;
+
+        // A rule that was removed or renamed would otherwise stay raised forever
+        states.retain(|name, _| rules.iter().any(|rule| rule.name == *name));
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "sensors".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "alert".parse().unwrap(),
+                recipient: actor_ref.recipient(),
+            })
+            .await
+            .unwrap();
+
+        let alerts = Self {
+            broker_ref,
+            rules,
+            path,
+            states,
+        };
+
+        // The ThinkInk asks again with AlertsRequested whenever it restarts itself
+        alerts.republish().await;
+
+        Ok(alerts)
+    }
+}
+
+impl Message<crate::BrokerMessage> for Alerts {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let sensor_readings = match message {
+            crate::BrokerMessage::SensorReadings(sensor_readings) => sensor_readings,
+            crate::BrokerMessage::AlertsRequested => {
+                self.republish().await;
+                return;
+            }
+            _ => return,
+        };
+
+        // Sustained readings are timed by the sensor, not by when the readings arrived
+        let now = sensor_readings.at;
+
+        let mut changed = false;
+
+        for (key, value) in sensor_readings.readings {
+            let series = format!("{}.{}", sensor_readings.source, key);
+
+            let rules = self
+                .rules
+                .iter()
+                .filter(|rule| rule.series == series)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            for rule in rules {
+                changed |= self.evaluate(&rule, value, now).await;
+            }
+        }
+
+        if changed && let Err(e) = self.save().await {
+            tracing::error!("! could not save alerts to {}: {}", self.path, e);
+        }
+    }
+}
+
+impl Alerts {
+    // Returns whether the rule's state changed
+    async fn evaluate(
+        &mut self,
+        rule: &Rule,
+        value: f64,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> bool {
+        let state = self.states.get(&rule.name).copied().unwrap_or_default();
+        let next = rule.next(state, value, now);
+
+        match (state, next) {
+            (State::Raised(_), State::Clear) => {
+                tracing::info!("alert {} cleared at {}", rule.name, value);
+                self.publish(crate::BrokerMessage::AlertCleared(rule.name.clone()))
+                    .await;
+            }
+            (State::Clear | State::Pending(_), State::Raised(_)) => {
+                self.raise(rule, value).await;
+            }
+            _ => {}
+        }
+
+        self.states.insert(rule.name.clone(), next);
+
+        next != state
+    }
+
+    async fn raise(&self, rule: &Rule, value: f64) {
+        tracing::warn!("! alert {} raised at {}", rule.name, value);
+
+        self.publish(crate::BrokerMessage::AlertRaised(crate::Alert {
+            name: rule.name.clone(),
+            series: rule.series.clone(),
+            value,
+        }))
+        .await;
+
+        let Some(message) = &rule.message else {
+            return;
+        };
+
+        let _ = self
+            .broker_ref
+            .tell(broker::Publish {
+                topic: "message".parse().unwrap(),
+                message: crate::BrokerMessage::Message(crate::Message {
+                    text: message.replace("{value}", &value.round().to_string()),
+                    effects: rule.effects.clone(),
+                    read: false,
+                    source: "alerts".to_string(),
+                }),
+            })
+            .await;
+    }
+
+    // No new Unicorn message, the one sent when the alert was raised is still in the inbox
+    async fn republish(&self) {
+        for rule in &self.rules {
+            if let Some(State::Raised(value)) = self.states.get(&rule.name) {
+                self.publish(crate::BrokerMessage::AlertRaised(crate::Alert {
+                    name: rule.name.clone(),
+                    series: rule.series.clone(),
+                    value: *value,
+                }))
+                .await;
+            }
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save(&self.path, &self.states).await
+    }
+
+    async fn publish(&self, message: crate::BrokerMessage) {
+        let _ = self
+            .broker_ref
+            .tell(broker::Publish {
+                topic: "alert".parse().unwrap(),
+                message,
+            })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(above: Option<f64>, below: Option<f64>, hysteresis: f64, sustained_secs: u64) -> Rule {
+        Rule::compile(&[RuleConfig {
+            name: "co2".to_string(),
+            series: "urban.ec02".to_string(),
+            above,
+            below,
+            hysteresis,
+            sustained_secs,
+            message: None,
+            effects: vec![],
+        }])
+        .unwrap()
+        .remove(0)
+    }
+
+    fn at(secs: i64) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn compile_needs_a_threshold_and_no_negative_hysteresis() {
+        let config = RuleConfig {
+            name: "co2".to_string(),
+            series: "urban.ec02".to_string(),
+            above: None,
+            below: None,
+            hysteresis: 0.0,
+            sustained_secs: 0,
+            message: None,
+            effects: vec![],
+        };
+
+        assert!(Rule::compile(std::slice::from_ref(&config)).is_err());
+        assert!(
+            Rule::compile(&[RuleConfig {
+                above: Some(1000.0),
+                hysteresis: -1.0,
+                ..config
+            }])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn compile_rejects_duplicate_names() {
+        let config = RuleConfig {
+            name: "co2".to_string(),
+            series: "urban.ec02".to_string(),
+            above: Some(1000.0),
+            below: None,
+            hysteresis: 0.0,
+            sustained_secs: 0,
+            message: None,
+            effects: vec![],
+        };
+
+        assert!(
+            Rule::compile(&[
+                config.clone(),
+                RuleConfig {
+                    series: "urban.tvoc".to_string(),
+                    ..config
+                }
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn raises_at_once_without_sustained_secs() {
+        let rule = rule(Some(1000.0), None, 0.0, 0);
+
+        assert_eq!(rule.next(State::Clear, 900.0, at(0)), State::Clear);
+        assert_eq!(rule.next(State::Clear, 1000.0, at(0)), State::Clear);
+        assert_eq!(
+            rule.next(State::Clear, 1001.0, at(0)),
+            State::Raised(1001.0)
+        );
+    }
+
+    #[test]
+    fn raises_once_out_of_range_for_sustained_secs() {
+        let rule = rule(Some(1000.0), None, 0.0, 60);
+
+        let state = rule.next(State::Clear, 1100.0, at(0));
+        assert_eq!(state, State::Pending(at(0)));
+
+        let state = rule.next(state, 1100.0, at(59));
+        assert_eq!(state, State::Pending(at(0)));
+        assert_eq!(rule.next(state, 1100.0, at(60)), State::Raised(1100.0));
+
+        // Dipping back in range starts the wait over
+        let state = rule.next(state, 900.0, at(30));
+        assert_eq!(state, State::Clear);
+        assert_eq!(rule.next(state, 1100.0, at(60)), State::Pending(at(60)));
+    }
+
+    #[test]
+    fn clears_past_the_hysteresis() {
+        let rule = rule(Some(1000.0), Some(400.0), 50.0, 0);
+
+        assert_eq!(
+            rule.next(State::Raised(1100.0), 999.0, at(0)),
+            State::Raised(1100.0)
+        );
+        assert_eq!(rule.next(State::Raised(1100.0), 950.0, at(0)), State::Clear);
+
+        assert_eq!(rule.next(State::Clear, 399.0, at(0)), State::Raised(399.0));
+        assert_eq!(
+            rule.next(State::Raised(399.0), 449.0, at(0)),
+            State::Raised(399.0)
+        );
+        assert_eq!(rule.next(State::Raised(399.0), 450.0, at(0)), State::Clear);
+    }
+}
@@ -0,0 +1,836 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use kameo::error::SendError;
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::Deserialize;
+
+use crate::calendar::{self, Calendar};
+use crate::focus::{self, Focus};
+use crate::history::{self, History};
+use crate::idle::{self, Idle};
+use crate::inbox::{self, Inbox};
+use crate::restarting_manager::{self, RestartingManager};
+use crate::toggl;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiConfig {
+    #[serde(
+        default = "default_listen_address",
+        deserialize_with = "crate::config::deserialize_socket_addr"
+    )]
+    pub listen_address: std::net::SocketAddr,
+    // Required on every route
+    #[serde(default)]
+    pub auth: crate::webhook::EndpointAuth,
+}
+
+fn default_listen_address() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([127, 0, 0, 1], 9000))
+}
+
+pub struct Api {
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    toggl_manager_ref: Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+    inbox_manager_ref: Option<ActorRef<RestartingManager<Inbox>>>,
+    focus_manager_ref: Option<ActorRef<RestartingManager<Focus>>>,
+    idle_manager_ref: Option<ActorRef<RestartingManager<Idle>>>,
+    calendar_manager_ref: Option<ActorRef<RestartingManager<Calendar>>>,
+    history_manager_ref: Option<ActorRef<RestartingManager<History>>>,
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+#[derive(Clone)]
+struct ApiState {
+    actor_ref: ActorRef<Api>,
+    registry: restarting_manager::Registry,
+}
+
+impl Actor for Api {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+        Option<ActorRef<RestartingManager<Inbox>>>,
+        Option<ActorRef<RestartingManager<Focus>>>,
+        Option<ActorRef<RestartingManager<Idle>>>,
+        Option<ActorRef<RestartingManager<Calendar>>>,
+        Option<ActorRef<RestartingManager<History>>>,
+        restarting_manager::Registry,
+        ApiConfig,
+    );
+    type Error = ApiError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (
+            broker_ref,
+            toggl_manager_ref,
+            inbox_manager_ref,
+            focus_manager_ref,
+            idle_manager_ref,
+            calendar_manager_ref,
+            history_manager_ref,
+            registry,
+            config,
+        ) = state;
+
+        let (shutdown, shutdown_receive) = tokio::sync::oneshot::channel();
+
+        let state = ApiState {
+            actor_ref,
+            registry,
+        };
+
+        let app = Router::new()
+            .route("/status", get(status))
+            .route("/toggl/start", post(start_time_entry))
+            .route("/toggl/stop", post(stop_time_entry))
+            .route("/toggl/continue", post(continue_time_entry))
+            .route("/toggl/adjust", post(adjust_time))
+            .route("/toggl/entries", get(toggl_entries))
+            .route("/toggl/projects", get(toggl_projects))
+            .route("/toggl/tags", get(toggl_tags))
+            .route("/toggl/workspace", get(toggl_workspace))
+            .route("/countdown", post(start_countdown))
+            .route("/animation/cancel", post(cancel_animation))
+            .route("/clock", post(start_clock))
+            .route("/inbox", get(get_inbox))
+            .route("/inbox/read", post(read_inbox))
+            .route("/inbox/clear", post(clear_inbox))
+            .route("/servo/x", post(servo_x))
+            .route("/servo/y", post(servo_y))
+            .route("/fireworks/start", post(start_fireworks))
+            .route("/fireworks/stop", post(stop_fireworks))
+            .route("/focus", get(get_focus))
+            .route("/focus/start", post(start_focus_session))
+            .route("/focus/stop", post(stop_focus_session))
+            .route("/idle/resolve", post(resolve_idle_time_entry))
+            .route("/calendar", get(get_calendar))
+            .route("/history", get(get_latest_history))
+            .route("/history/{series}", get(get_history));
+
+        let app = crate::webhook::guard_router(app, "api", &config.auth).with_state(state);
+
+        let listener = crate::webhook::bind(config.listen_address)
+            .await
+            .map_err(ApiError::Bind)?;
+
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(async move {
+                let _ = shutdown_receive.await;
+            })
+            .await
+            {
+                tracing::error!("! api stopped: {}", e);
+            }
+        });
+
+        Ok(Self {
+            broker_ref,
+            toggl_manager_ref,
+            inbox_manager_ref,
+            focus_manager_ref,
+            idle_manager_ref,
+            calendar_manager_ref,
+            history_manager_ref,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    async fn on_stop(
+        &mut self,
+        _actor_ref: WeakActorRef<Self>,
+        _reason: ActorStopReason,
+    ) -> Result<(), Self::Error> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Action {
+    StartTimeEntry(toggl::TimeEntryDetails),
+    StopTimeEntry,
+    ContinueTimeEntry,
+    AdjustTime { minutes: i64 },
+    StartCountdown { minutes: i64 },
+    CancelAnimation,
+    StartClock,
+    ReadInbox,
+    ClearInbox,
+    ServoX(u32),
+    ServoY(u32),
+    StartFireworks,
+    StopFireworks,
+    StartFocusSession(focus::StartSession),
+    StopFocusSession,
+    ResolveIdleTimeEntry(crate::protocol::IdleResolution),
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Unavailable,
+    Failed,
+    NotFound,
+    BadRequest,
+    Toggl(toggl::TogglError),
+    Focus(focus::SessionError),
+    Bind(crate::webhook::BindError),
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Unavailable => write!(f, "unavailable"),
+            ApiError::Failed => write!(f, "failed"),
+            ApiError::NotFound => write!(f, "not found"),
+            ApiError::BadRequest => write!(f, "bad request"),
+            ApiError::Toggl(e) => write!(f, "toggl failed: {}", e),
+            ApiError::Focus(e) => write!(f, "focus failed: {}", e),
+            ApiError::Bind(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Failed => StatusCode::BAD_GATEWAY,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::BadRequest => StatusCode::BAD_REQUEST,
+            ApiError::Toggl(toggl::TogglError::NotFound) => StatusCode::NOT_FOUND,
+            ApiError::Toggl(toggl::TogglError::Conflict(_)) => StatusCode::CONFLICT,
+            ApiError::Toggl(toggl::TogglError::RateLimited) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Toggl(toggl::TogglError::Network(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Toggl(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Focus(focus::SessionError::AlreadyRunning) => StatusCode::CONFLICT,
+            ApiError::Focus(focus::SessionError::NotRunning) => StatusCode::NOT_FOUND,
+            ApiError::Focus(focus::SessionError::Invalid(_)) => StatusCode::BAD_REQUEST,
+            ApiError::Bind(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+fn toggl_error<M>(e: SendError<M, toggl::TogglError>) -> ApiError {
+    match e {
+        SendError::HandlerError(e) => ApiError::Toggl(e),
+        _ => ApiError::Unavailable,
+    }
+}
+
+fn focus_error<M>(e: SendError<M, focus::SessionError>) -> ApiError {
+    match e {
+        SendError::HandlerError(e) => ApiError::Focus(e),
+        _ => ApiError::Unavailable,
+    }
+}
+
+impl Message<Action> for Api {
+    type Reply = Result<(), ApiError>;
+
+    async fn handle(
+        &mut self,
+        message: Action,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!("action {:?}", message);
+
+        match message {
+            Action::StartTimeEntry(details) => self
+                .toggl()
+                .await?
+                .ask(toggl::StartTimeEntry { details })
+                .await
+                .map_err(toggl_error),
+            Action::StopTimeEntry => self
+                .toggl()
+                .await?
+                .ask(toggl::StopTimeEntry)
+                .await
+                .map_err(toggl_error),
+            Action::ContinueTimeEntry => self
+                .toggl()
+                .await?
+                .ask(toggl::ContinueTimeEntry)
+                .await
+                .map_err(toggl_error),
+            Action::AdjustTime { minutes } => self
+                .toggl()
+                .await?
+                .ask(toggl::AdjustTime { minutes })
+                .await
+                .map_err(toggl_error),
+            Action::StartCountdown { minutes } => {
+                self.publish("countdown", crate::BrokerMessage::StartCountdown(minutes))
+                    .await
+            }
+            Action::CancelAnimation => {
+                self.publish("countdown", crate::BrokerMessage::CancelAnimation)
+                    .await
+            }
+            Action::StartClock => {
+                self.publish("clock", crate::BrokerMessage::StartClock)
+                    .await
+            }
+            Action::ReadInbox => {
+                self.publish("message", crate::BrokerMessage::ReadInbox)
+                    .await
+            }
+            Action::ClearInbox => {
+                self.publish("message", crate::BrokerMessage::ClearInbox)
+                    .await
+            }
+            Action::ServoX(value) => {
+                self.publish("servo", crate::BrokerMessage::ServoX(value))
+                    .await
+            }
+            Action::ServoY(value) => {
+                self.publish("servo", crate::BrokerMessage::ServoY(value))
+                    .await
+            }
+            Action::StartFireworks => {
+                self.publish("fireworks", crate::BrokerMessage::StartFireworks)
+                    .await
+            }
+            Action::StopFireworks => {
+                self.publish("fireworks", crate::BrokerMessage::StopFireworks)
+                    .await
+            }
+            Action::StartFocusSession(start_session) => self
+                .focus()
+                .await?
+                .ask(start_session)
+                .await
+                .map_err(focus_error),
+            Action::StopFocusSession => self
+                .focus()
+                .await?
+                .ask(focus::StopSession)
+                .await
+                .map_err(focus_error),
+            Action::ResolveIdleTimeEntry(resolution) => self
+                .idle_manager_ref
+                .as_ref()
+                .ok_or(ApiError::Unavailable)?
+                .ask(restarting_manager::GetChild)
+                .await
+                .map_err(|_| ApiError::Unavailable)?
+                .ask(idle::Resolve { resolution })
+                .await
+                .map_err(toggl_error),
+        }
+    }
+}
+
+pub struct GetInbox {
+    pub pending_only: bool,
+}
+
+impl Message<GetInbox> for Api {
+    type Reply = Result<Vec<inbox::InboxEntry>, ApiError>;
+
+    async fn handle(
+        &mut self,
+        message: GetInbox,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.inbox_manager_ref
+            .as_ref()
+            .ok_or(ApiError::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ApiError::Unavailable)?
+            .ask(inbox::GetEntries {
+                pending_only: message.pending_only,
+            })
+            .await
+            .map_err(|_| ApiError::Failed)
+    }
+}
+
+pub struct GetFocus;
+
+impl Message<GetFocus> for Api {
+    type Reply = Result<serde_json::Value, ApiError>;
+
+    async fn handle(
+        &mut self,
+        _message: GetFocus,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let focus_ref = self.focus().await?;
+
+        let session = focus_ref
+            .ask(focus::GetSession)
+            .await
+            .map_err(|_| ApiError::Failed)?;
+        let sessions = focus_ref
+            .ask(focus::GetRecords)
+            .await
+            .map_err(|_| ApiError::Failed)?;
+
+        Ok(serde_json::json!({ "session": session, "sessions": sessions }))
+    }
+}
+
+pub struct GetCalendar;
+
+impl Message<GetCalendar> for Api {
+    type Reply = Result<Vec<calendar::ScheduledEvent>, ApiError>;
+
+    async fn handle(
+        &mut self,
+        _message: GetCalendar,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.calendar_manager_ref
+            .as_ref()
+            .ok_or(ApiError::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ApiError::Unavailable)?
+            .ask(calendar::GetSchedule)
+            .await
+            .map_err(|_| ApiError::Failed)
+    }
+}
+
+pub struct GetHistory {
+    pub series: String,
+    pub hours: i64,
+    pub resolution: history::Resolution,
+}
+
+impl Message<GetHistory> for Api {
+    type Reply = Result<Vec<history::Bucket>, ApiError>;
+
+    async fn handle(
+        &mut self,
+        message: GetHistory,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        // `?hours=` comes straight from the query string, so anything out of range is the
+        // caller's mistake rather than a panic
+        let since = chrono::TimeDelta::try_hours(message.hours)
+            .filter(|hours| *hours >= chrono::TimeDelta::zero())
+            .and_then(|hours| chrono::Utc::now().checked_sub_signed(hours))
+            .ok_or(ApiError::BadRequest)?;
+
+        self.history()
+            .await?
+            .ask(history::GetHistory {
+                series: message.series,
+                since,
+                resolution: message.resolution,
+            })
+            .await
+            .map_err(|_| ApiError::Failed)?
+            .ok_or(ApiError::NotFound)
+    }
+}
+
+pub struct GetLatestHistory;
+
+impl Message<GetLatestHistory> for Api {
+    type Reply = Result<std::collections::BTreeMap<String, history::Bucket>, ApiError>;
+
+    async fn handle(
+        &mut self,
+        _message: GetLatestHistory,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.history()
+            .await?
+            .ask(history::GetLatest)
+            .await
+            .map_err(|_| ApiError::Failed)
+    }
+}
+
+#[derive(Debug)]
+pub enum TogglQuery {
+    TimeEntries,
+    Projects,
+    Tags,
+    Workspace,
+}
+
+impl Message<TogglQuery> for Api {
+    type Reply = Result<serde_json::Value, ApiError>;
+
+    async fn handle(
+        &mut self,
+        message: TogglQuery,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let toggl_ref = self.toggl().await?;
+
+        let value = match message {
+            TogglQuery::TimeEntries => serde_json::to_value(
+                toggl_ref
+                    .ask(toggl::GetTimeEntries)
+                    .await
+                    .map_err(toggl_error)?,
+            ),
+            TogglQuery::Projects => serde_json::to_value(
+                toggl_ref
+                    .ask(toggl::GetProjects)
+                    .await
+                    .map_err(toggl_error)?,
+            ),
+            TogglQuery::Tags => {
+                serde_json::to_value(toggl_ref.ask(toggl::GetTags).await.map_err(toggl_error)?)
+            }
+            TogglQuery::Workspace => serde_json::to_value(
+                toggl_ref
+                    .ask(toggl::GetWorkspace)
+                    .await
+                    .map_err(toggl_error)?,
+            ),
+        };
+
+        value.map_err(|_| ApiError::Failed)
+    }
+}
+
+impl Api {
+    async fn toggl(&self) -> Result<ActorRef<toggl::Toggl>, ApiError> {
+        let toggl_manager_ref = self
+            .toggl_manager_ref
+            .as_ref()
+            .ok_or(ApiError::Unavailable)?;
+
+        toggl_manager_ref
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ApiError::Unavailable)
+    }
+
+    async fn focus(&self) -> Result<ActorRef<Focus>, ApiError> {
+        self.focus_manager_ref
+            .as_ref()
+            .ok_or(ApiError::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ApiError::Unavailable)
+    }
+
+    async fn history(&self) -> Result<ActorRef<History>, ApiError> {
+        self.history_manager_ref
+            .as_ref()
+            .ok_or(ApiError::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ApiError::Unavailable)
+    }
+
+    async fn publish(&self, topic: &str, message: crate::BrokerMessage) -> Result<(), ApiError> {
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: topic.parse().unwrap(),
+                message,
+            })
+            .await
+            .map_err(|_| ApiError::Failed)
+    }
+}
+
+async fn perform(state: &ApiState, action: Action) -> StatusCode {
+    match state.actor_ref.ask(action).await {
+        Ok(()) => StatusCode::OK,
+        Err(SendError::HandlerError(e)) => {
+            tracing::warn!("! action failed: {:?}", e);
+            e.status()
+        }
+        Err(e) => {
+            tracing::warn!("! action failed: {:?}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MinutesPayload {
+    minutes: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValuePayload {
+    value: u32,
+}
+
+async fn start_time_entry(
+    State(state): State<ApiState>,
+    Json(payload): Json<toggl::TimeEntryDetails>,
+) -> StatusCode {
+    perform(&state, Action::StartTimeEntry(payload)).await
+}
+
+async fn stop_time_entry(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::StopTimeEntry).await
+}
+
+async fn continue_time_entry(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::ContinueTimeEntry).await
+}
+
+async fn adjust_time(
+    State(state): State<ApiState>,
+    Json(payload): Json<MinutesPayload>,
+) -> StatusCode {
+    perform(
+        &state,
+        Action::AdjustTime {
+            minutes: payload.minutes,
+        },
+    )
+    .await
+}
+
+async fn start_countdown(
+    State(state): State<ApiState>,
+    Json(payload): Json<MinutesPayload>,
+) -> StatusCode {
+    perform(
+        &state,
+        Action::StartCountdown {
+            minutes: payload.minutes,
+        },
+    )
+    .await
+}
+
+async fn cancel_animation(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::CancelAnimation).await
+}
+
+async fn start_clock(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::StartClock).await
+}
+
+async fn read_inbox(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::ReadInbox).await
+}
+
+async fn clear_inbox(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::ClearInbox).await
+}
+
+async fn servo_x(State(state): State<ApiState>, Json(payload): Json<ValuePayload>) -> StatusCode {
+    perform(&state, Action::ServoX(payload.value)).await
+}
+
+async fn servo_y(State(state): State<ApiState>, Json(payload): Json<ValuePayload>) -> StatusCode {
+    perform(&state, Action::ServoY(payload.value)).await
+}
+
+async fn start_fireworks(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::StartFireworks).await
+}
+
+async fn stop_fireworks(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::StopFireworks).await
+}
+
+async fn start_focus_session(
+    State(state): State<ApiState>,
+    Json(payload): Json<focus::StartSession>,
+) -> StatusCode {
+    if payload.validate().is_err() {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    perform(&state, Action::StartFocusSession(payload)).await
+}
+
+async fn stop_focus_session(State(state): State<ApiState>) -> StatusCode {
+    perform(&state, Action::StopFocusSession).await
+}
+
+#[derive(Debug, Deserialize)]
+struct IdlePayload {
+    resolution: crate::protocol::IdleResolution,
+}
+
+async fn resolve_idle_time_entry(
+    State(state): State<ApiState>,
+    Json(payload): Json<IdlePayload>,
+) -> StatusCode {
+    perform(&state, Action::ResolveIdleTimeEntry(payload.resolution)).await
+}
+
+async fn get_focus(State(state): State<ApiState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    match state.actor_ref.ask(GetFocus).await {
+        Ok(value) => Ok(Json(value)),
+        Err(SendError::HandlerError(e)) => Err(e.status()),
+        Err(e) => {
+            tracing::warn!("! focus query failed: {:?}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+async fn get_calendar(
+    State(state): State<ApiState>,
+) -> Result<Json<Vec<calendar::ScheduledEvent>>, StatusCode> {
+    match state.actor_ref.ask(GetCalendar).await {
+        Ok(events) => Ok(Json(events)),
+        Err(SendError::HandlerError(e)) => Err(e.status()),
+        Err(e) => {
+            tracing::warn!("! calendar query failed: {:?}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    #[serde(default = "default_history_hours")]
+    hours: i64,
+    #[serde(default = "default_history_resolution")]
+    resolution: history::Resolution,
+}
+
+fn default_history_hours() -> i64 {
+    24
+}
+
+fn default_history_resolution() -> history::Resolution {
+    history::Resolution::Minute
+}
+
+async fn get_history(
+    State(state): State<ApiState>,
+    Path(series): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<history::Bucket>>, StatusCode> {
+    match state
+        .actor_ref
+        .ask(GetHistory {
+            series,
+            hours: query.hours,
+            resolution: query.resolution,
+        })
+        .await
+    {
+        Ok(buckets) => Ok(Json(buckets)),
+        Err(SendError::HandlerError(e)) => Err(e.status()),
+        Err(e) => {
+            tracing::warn!("! history query failed: {:?}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+async fn get_latest_history(
+    State(state): State<ApiState>,
+) -> Result<Json<std::collections::BTreeMap<String, history::Bucket>>, StatusCode> {
+    match state.actor_ref.ask(GetLatestHistory).await {
+        Ok(latest) => Ok(Json(latest)),
+        Err(SendError::HandlerError(e)) => Err(e.status()),
+        Err(e) => {
+            tracing::warn!("! history query failed: {:?}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+async fn query_toggl(
+    state: &ApiState,
+    query: TogglQuery,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    match state.actor_ref.ask(query).await {
+        Ok(value) => Ok(Json(value)),
+        Err(SendError::HandlerError(e)) => {
+            tracing::warn!("! toggl query failed: {:?}", e);
+            Err(e.status())
+        }
+        Err(e) => {
+            tracing::warn!("! toggl query failed: {:?}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+async fn toggl_entries(
+    State(state): State<ApiState>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    query_toggl(&state, TogglQuery::TimeEntries).await
+}
+
+async fn toggl_projects(
+    State(state): State<ApiState>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    query_toggl(&state, TogglQuery::Projects).await
+}
+
+async fn toggl_tags(State(state): State<ApiState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    query_toggl(&state, TogglQuery::Tags).await
+}
+
+async fn toggl_workspace(
+    State(state): State<ApiState>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    query_toggl(&state, TogglQuery::Workspace).await
+}
+
+#[derive(Debug, Deserialize)]
+struct InboxQuery {
+    #[serde(default)]
+    pending: bool,
+}
+
+async fn get_inbox(
+    State(state): State<ApiState>,
+    Query(query): Query<InboxQuery>,
+) -> Result<Json<Vec<inbox::InboxEntry>>, StatusCode> {
+    match state
+        .actor_ref
+        .ask(GetInbox {
+            pending_only: query.pending,
+        })
+        .await
+    {
+        Ok(entries) => Ok(Json(entries)),
+        Err(SendError::HandlerError(e)) => Err(e.status()),
+        Err(e) => {
+            tracing::warn!("! inbox query failed: {:?}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+async fn status(State(state): State<ApiState>) -> Json<serde_json::Value> {
+    let actors: serde_json::Map<String, serde_json::Value> = state
+        .registry
+        .snapshot()
+        .into_iter()
+        .map(|(name, status)| {
+            (
+                name,
+                serde_json::json!({
+                    "alive": status.alive,
+                    "restarts": status.restarts,
+                    "lastStopReason": status.last_stop_reason,
+                    "lastStoppedAt": status.last_stopped_at.map(|at| at.to_rfc3339()),
+                    "crashLooping": status.crash_looping,
+                    "gaveUp": status.gave_up,
+                }),
+            )
+        })
+        .collect();
+
+    Json(serde_json::json!({ "actors": actors }))
+}
@@ -1,14 +1,25 @@
 use crate::RaylibResponse;
 use raylib::prelude::*;
 
+mod alerts;
 mod game_of_life;
+mod next_event;
 mod solar_system;
+mod time_summary;
 mod weather_forecast;
 
+pub use time_summary::{TimeSummary, start_of_week};
+pub use weather_forecast::WeatherConfig;
+
 const IMAGE_WIDTH: u32 = 296;
 const IMAGE_HEIGHT: u32 = 128;
 
-pub fn thinkink_image(raylib_actor_transmit: &tokio::sync::mpsc::Sender<RaylibResponse>) {
+pub fn thinkink_image(
+    raylib_actor_transmit: &tokio::sync::mpsc::Sender<RaylibResponse>,
+    weather_config: &WeatherConfig,
+    next_event: Option<&crate::CalendarEventUpcoming>,
+    alerts: &[crate::Alert],
+) {
     let current_date = chrono::Local::now();
 
     let (mut rl, thread) = raylib::init().size(240, 240).title("Desk").build();
@@ -47,7 +58,7 @@ pub fn thinkink_image(raylib_actor_transmit: &tokio::sync::mpsc::Sender<RaylibRe
         Color::WHITE,
     );
 
-    if let Err(_) = weather_forecast::weather_forecast(&mut image, &font_solid) {
+    if let Err(_) = weather_forecast::weather_forecast(&mut image, &font_solid, weather_config) {
         image.draw_text_ex(
             &font_solid,
             "Weather error",
@@ -94,6 +105,37 @@ pub fn thinkink_image(raylib_actor_transmit: &tokio::sync::mpsc::Sender<RaylibRe
         Color::BLACK,
     );
 
+    if let Some(event) = next_event {
+        next_event::next_event(&mut image, &font_solid, event, current_date);
+    }
+
+    alerts::alerts(&mut image, &font_solid, alerts);
+
+    transmit_image(raylib_actor_transmit, image);
+}
+
+pub fn time_summary_image(
+    raylib_actor_transmit: &tokio::sync::mpsc::Sender<RaylibResponse>,
+    summary: &TimeSummary,
+) {
+    let (mut rl, thread) = raylib::init().size(240, 240).title("Desk").build();
+
+    let font_solid = rl
+        .load_font_from_memory(&thread, ".ttf", FONT_SOLID_DATA, 30, FontLoadEx::Default(0))
+        .unwrap();
+
+    let mut image = Image::gen_image_color(IMAGE_WIDTH as i32, IMAGE_HEIGHT as i32, Color::WHITE);
+
+    time_summary::time_summary(&mut image, &font_solid, summary);
+
+    transmit_image(raylib_actor_transmit, image);
+}
+
+// Packs the image into the ThinkInk's 2-bit grayscale format
+fn transmit_image(
+    raylib_actor_transmit: &tokio::sync::mpsc::Sender<RaylibResponse>,
+    mut image: Image,
+) {
     image.color_grayscale();
     image.color_brightness(-30);
     image.dither(2, 2, 2, 2);
@@ -0,0 +1,33 @@
+use raylib::prelude::*;
+
+use crate::Alert;
+
+const MAXIMUM_WIDTH: f32 = 120.0;
+
+// Stacked up from the bottom right corner, each a warning sign with the rule's name and the reading
+// that raised it
+pub fn alerts(image: &mut Image, font_solid: &Font, alerts: &[Alert]) {
+    let mut y = image.height() - 20;
+
+    for alert in alerts {
+        let text = format!("{} {}", alert.name, alert.value.round());
+        let text = super::time_summary::truncate(font_solid, &text, MAXIMUM_WIDTH);
+        let width = measure_text_ex(font_solid, &text, 14.0, 0.0).x as i32;
+        let x = image.width() - width - 5;
+
+        image.draw_rectangle(x - 22, y, width + 27, 20, Color::WHITE);
+        image.draw_circle(x - 11, y + 10, 7, Color::BLACK);
+        image.draw_rectangle(x - 12, y + 5, 2, 6, Color::WHITE);
+        image.draw_rectangle(x - 12, y + 13, 2, 2, Color::WHITE);
+        image.draw_text_ex(
+            font_solid,
+            &text,
+            Vector2::new(x as f32, (y + 3) as f32),
+            14.0,
+            0.0,
+            Color::BLACK,
+        );
+
+        y -= 20;
+    }
+}
@@ -0,0 +1,46 @@
+use raylib::prelude::*;
+
+use crate::CalendarEventUpcoming;
+
+const MAXIMUM_WIDTH: f32 = 150.0;
+
+// Drawn in the top right corner over a white box so it stays readable on top of the solar system
+pub fn next_event(
+    image: &mut Image,
+    font_solid: &Font,
+    event: &CalendarEventUpcoming,
+    now: chrono::DateTime<chrono::Local>,
+) {
+    let day = if event.start_at.date_naive() <= now.date_naive() {
+        "Today".to_string()
+    } else {
+        event.start_at.format("%a").to_string()
+    };
+
+    let text = if event.all_day {
+        format!("{}: {}", day, event.description)
+    } else if event.start_at.date_naive() == now.date_naive() {
+        format!("{} {}", event.start_at.format("%H:%M"), event.description)
+    } else {
+        format!(
+            "{} {} {}",
+            day,
+            event.start_at.format("%H:%M"),
+            event.description
+        )
+    };
+
+    let text = super::time_summary::truncate(font_solid, &text, MAXIMUM_WIDTH);
+    let width = measure_text_ex(font_solid, &text, 14.0, 0.0).x as i32;
+    let x = image.width() - width - 5;
+
+    image.draw_rectangle(x - 4, 0, width + 9, 20, Color::WHITE);
+    image.draw_text_ex(
+        font_solid,
+        &text,
+        Vector2::new(x as f32, 3.0),
+        14.0,
+        0.0,
+        Color::BLACK,
+    );
+}
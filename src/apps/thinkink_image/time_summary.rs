@@ -0,0 +1,353 @@
+use chrono::Datelike;
+use raylib::prelude::*;
+
+use crate::toggl::{Project, TimeEntry};
+
+const ROW_LIMIT: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct TimeSummary {
+    pub generated_at: chrono::DateTime<chrono::Local>,
+    pub today: Vec<SummaryRow>,
+    pub week: Vec<SummaryRow>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SummaryRow {
+    pub label: String,
+    pub duration: chrono::Duration,
+}
+
+impl TimeSummary {
+    pub fn new(
+        time_entries: &[TimeEntry],
+        projects: &[Project],
+        now: chrono::DateTime<chrono::Local>,
+    ) -> Self {
+        let start_of_day = start_of_day(now);
+        let start_of_week = start_of_week(now);
+
+        Self {
+            generated_at: now,
+            today: summarize(time_entries, projects, start_of_day, now),
+            week: summarize(time_entries, projects, start_of_week, now),
+        }
+    }
+}
+
+pub fn start_of_day(now: chrono::DateTime<chrono::Local>) -> chrono::DateTime<chrono::Local> {
+    now.with_time(chrono::NaiveTime::MIN)
+        .earliest()
+        .unwrap_or(now)
+}
+
+// Weeks start on Monday, like Toggl's own reports
+pub fn start_of_week(now: chrono::DateTime<chrono::Local>) -> chrono::DateTime<chrono::Local> {
+    let days_since_monday = now.weekday().num_days_from_monday() as i64;
+
+    start_of_day(now - chrono::Duration::days(days_since_monday))
+}
+
+// Totals per project and description, clipped to the window so entries spanning midnight only
+// count the part inside it, and a running entry counts up to now
+fn summarize(
+    time_entries: &[TimeEntry],
+    projects: &[Project],
+    since: chrono::DateTime<chrono::Local>,
+    now: chrono::DateTime<chrono::Local>,
+) -> Vec<SummaryRow> {
+    let since = since.with_timezone(&chrono::Utc);
+    let now = now.with_timezone(&chrono::Utc);
+
+    let mut totals: Vec<((Option<i64>, String), chrono::Duration)> = vec![];
+
+    for time_entry in time_entries {
+        let start = time_entry.start.max(since);
+        let stop = time_entry.stop.unwrap_or(now).min(now);
+
+        if stop <= start {
+            continue;
+        }
+
+        let key = (time_entry.project_id, time_entry.description().to_string());
+
+        match totals.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, duration)) => *duration += stop - start,
+            None => totals.push((key, stop - start)),
+        }
+    }
+
+    totals.sort_by_key(|total| std::cmp::Reverse(total.1));
+
+    totals
+        .into_iter()
+        .map(|((project_id, description), duration)| {
+            let project_name = project_id.and_then(|project_id| {
+                projects
+                    .iter()
+                    .find(|project| project.id == project_id)
+                    .map(|project| project.name.as_str())
+            });
+
+            let label = match (project_name, description.is_empty()) {
+                (Some(project_name), false) => format!("{} - {}", project_name, description),
+                (Some(project_name), true) => project_name.to_string(),
+                (None, false) => description,
+                (None, true) => "(no description)".to_string(),
+            };
+
+            SummaryRow { label, duration }
+        })
+        .collect()
+}
+
+// Anything past the row limit is folded into one "Other" bar so the totals still add up
+fn visible_rows(rows: &[SummaryRow]) -> Vec<SummaryRow> {
+    let mut visible_rows = rows.iter().take(ROW_LIMIT - 1).cloned().collect::<Vec<_>>();
+
+    match rows.len() {
+        length if length == ROW_LIMIT => visible_rows.push(rows[ROW_LIMIT - 1].clone()),
+        length if length > ROW_LIMIT => visible_rows.push(SummaryRow {
+            label: "Other".to_string(),
+            duration: rows[ROW_LIMIT - 1..]
+                .iter()
+                .fold(chrono::Duration::zero(), |total, row| total + row.duration),
+        }),
+        _ => {}
+    }
+
+    visible_rows
+}
+
+pub fn time_summary(image: &mut Image, font_solid: &Font, summary: &TimeSummary) {
+    let column_width = image.width() / 2;
+
+    draw_column(
+        image,
+        font_solid,
+        &summary.generated_at.format("%a %m-%d").to_string(),
+        &summary.today,
+        0,
+        column_width,
+    );
+    draw_column(
+        image,
+        font_solid,
+        "This week",
+        &summary.week,
+        column_width,
+        column_width,
+    );
+}
+
+fn draw_column(
+    image: &mut Image,
+    font_solid: &Font,
+    title: &str,
+    rows: &[SummaryRow],
+    x: i32,
+    width: i32,
+) {
+    let total = rows
+        .iter()
+        .fold(chrono::Duration::zero(), |total, row| total + row.duration);
+
+    image.draw_text_ex(
+        font_solid,
+        title,
+        Vector2::new((x + 5) as f32, 2.0),
+        20.0,
+        0.0,
+        Color::BLACK,
+    );
+
+    draw_text_right(
+        image,
+        font_solid,
+        &format_duration(total),
+        x + width - 5,
+        2,
+        20.0,
+    );
+
+    image.draw_line(x + 5, 23, x + width - 5, 23, Color::BLACK);
+
+    if rows.is_empty() {
+        image.draw_text_ex(
+            font_solid,
+            "Nothing tracked",
+            Vector2::new((x + 5) as f32, 30.0),
+            14.0,
+            0.0,
+            Color::DARKGRAY,
+        );
+
+        return;
+    }
+
+    let longest = rows[0].duration.num_seconds().max(1);
+
+    let visible_rows = visible_rows(rows);
+
+    let bar_width = width - 10;
+
+    for (index, row) in visible_rows.iter().enumerate() {
+        let y = 27 + index as i32 * 25;
+
+        let duration_string = format_duration(row.duration);
+        let duration_width = measure_text_ex(font_solid, &duration_string, 14.0, 0.0).x as i32;
+
+        image.draw_text_ex(
+            font_solid,
+            &truncate(
+                font_solid,
+                &row.label,
+                (bar_width - duration_width - 4) as f32,
+            ),
+            Vector2::new((x + 5) as f32, y as f32),
+            14.0,
+            0.0,
+            Color::BLACK,
+        );
+
+        draw_text_right(image, font_solid, &duration_string, x + width - 5, y, 14.0);
+
+        let filled_width =
+            ((row.duration.num_seconds().min(longest) * bar_width as i64) / longest) as i32;
+
+        image.draw_rectangle(x + 5, y + 14, bar_width, 7, Color::LIGHTGRAY);
+        image.draw_rectangle(x + 5, y + 14, filled_width.max(1), 7, Color::BLACK);
+    }
+}
+
+fn draw_text_right(
+    image: &mut Image,
+    font_solid: &Font,
+    text: &str,
+    right: i32,
+    y: i32,
+    size: f32,
+) {
+    let width = measure_text_ex(font_solid, text, size, 0.0).x;
+
+    image.draw_text_ex(
+        font_solid,
+        text,
+        Vector2::new(right as f32 - width, y as f32),
+        size,
+        0.0,
+        Color::BLACK,
+    );
+}
+
+pub(super) fn truncate(font_solid: &Font, text: &str, max_width: f32) -> String {
+    if measure_text_ex(font_solid, text, 14.0, 0.0).x <= max_width {
+        return text.to_string();
+    }
+
+    let mut truncated = text.to_string();
+
+    while !truncated.is_empty()
+        && measure_text_ex(font_solid, &format!("{}..", truncated), 14.0, 0.0).x > max_width
+    {
+        truncated.pop();
+    }
+
+    format!("{}..", truncated.trim_end())
+}
+
+fn format_duration(duration: chrono::Duration) -> String {
+    let minutes = duration.num_minutes();
+
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> chrono::DateTime<chrono::Local> {
+        chrono::Local
+            .with_ymd_and_hms(2025, 9, 3, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn time_entry(
+        description: &str,
+        start: chrono::DateTime<chrono::Local>,
+        stop: Option<chrono::DateTime<chrono::Local>>,
+    ) -> TimeEntry {
+        TimeEntry {
+            id: 1,
+            workspace_id: 1,
+            project_id: None,
+            description: Some(description.to_string()),
+            tag_ids: None,
+            start: start.with_timezone(&chrono::Utc),
+            stop: stop.map(|stop| stop.with_timezone(&chrono::Utc)),
+            duration: -1,
+        }
+    }
+
+    fn row(label: &str, minutes: i64) -> SummaryRow {
+        SummaryRow {
+            label: label.to_string(),
+            duration: chrono::Duration::minutes(minutes),
+        }
+    }
+
+    fn minutes(rows: &[SummaryRow]) -> Vec<(&str, i64)> {
+        rows.iter()
+            .map(|row| (row.label.as_str(), row.duration.num_minutes()))
+            .collect()
+    }
+
+    #[test]
+    fn entries_crossing_the_window_start_only_count_the_part_inside() {
+        let time_entries = [time_entry("Review", at(8, 0), Some(at(10, 0)))];
+
+        let rows = summarize(&time_entries, &[], at(9, 30), at(12, 0));
+
+        assert_eq!(minutes(&rows), vec![("Review", 30)]);
+    }
+
+    #[test]
+    fn running_entries_count_up_to_now() {
+        let time_entries = [
+            time_entry("Coding", at(9, 0), None),
+            time_entry("Email", at(8, 0), Some(at(8, 15))),
+        ];
+
+        let rows = summarize(&time_entries, &[], at(0, 0), at(10, 0));
+
+        assert_eq!(minutes(&rows), vec![("Coding", 60), ("Email", 15)]);
+    }
+
+    #[test]
+    fn entries_outside_the_window_are_left_out() {
+        let time_entries = [time_entry("Yesterday", at(1, 0), Some(at(2, 0)))];
+
+        assert!(summarize(&time_entries, &[], at(3, 0), at(10, 0)).is_empty());
+    }
+
+    #[test]
+    fn rows_past_the_limit_are_folded_into_other() {
+        let rows = [
+            row("A", 50),
+            row("B", 40),
+            row("C", 30),
+            row("D", 20),
+            row("E", 10),
+        ];
+
+        assert_eq!(
+            minutes(&visible_rows(&rows)),
+            vec![("A", 50), ("B", 40), ("C", 30), ("Other", 30)]
+        );
+        assert_eq!(
+            minutes(&visible_rows(&rows[..ROW_LIMIT])),
+            vec![("A", 50), ("B", 40), ("C", 30), ("D", 20)]
+        );
+    }
+}
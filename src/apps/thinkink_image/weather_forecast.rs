@@ -1,8 +1,22 @@
 use raylib::prelude::*;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WeatherConfig {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+fn default_timezone() -> String {
+    "America/Los_Angeles".to_string()
+}
 
 pub fn weather_forecast(
     image: &mut Image,
     font_solid: &Font,
+    config: &WeatherConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let now = chrono::Local::now();
 
@@ -17,15 +31,15 @@ pub fn weather_forecast(
     let request = client
         .get("https://api.open-meteo.com/v1/forecast")
         .query(&[
-            ("latitude", std::env::var("LATITUDE").unwrap()),
-            ("longitude", std::env::var("LONGITUDE").unwrap()),
+            ("latitude", config.latitude.to_string()),
+            ("longitude", config.longitude.to_string()),
         ])
         .query(&[
             ("hourly", "temperature_2m,precipitation_probability"),
             ("temperature_unit", "fahrenheit"),
             ("wind_speed_unit", "mph"),
             ("precipitation_unit", "inch"),
-            ("timezone", "America/Los_Angeles"),
+            ("timezone", config.timezone.as_str()),
             ("forecast_days", "2"),
         ])
         .headers(headers)
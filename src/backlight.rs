@@ -5,8 +5,11 @@ use std::process::Command;
 
 use kameo::error::Infallible;
 use kameo::prelude::*;
+use kameo_actors::broker;
 
 pub struct Backlight {
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+
     #[cfg(feature = "pi")]
     pwm: pwm::Pwm,
 
@@ -19,10 +22,21 @@ pub struct Release;
 pub struct SetEnabled(pub bool);
 
 impl Actor for Backlight {
-    type Args = ();
+    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>,);
     type Error = Infallible;
 
-    async fn on_start(_state: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+    async fn on_start(state: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let broker_ref = state.0;
+
+        // Starts switched off
+        broker_ref
+            .tell(broker::Publish {
+                topic: "backlight".parse().unwrap(),
+                message: crate::BrokerMessage::BacklightChanged(false),
+            })
+            .await
+            .unwrap();
+
         #[cfg(feature = "pi")]
         {
             let pwm = pwm::Pwm::with_frequency(
@@ -35,6 +49,7 @@ impl Actor for Backlight {
             .unwrap();
 
             Ok(Self {
+                broker_ref,
                 pwm,
                 handle_count: 0,
             })
@@ -42,7 +57,10 @@ impl Actor for Backlight {
 
         #[cfg(not(feature = "pi"))]
         {
-            Ok(Self { handle_count: 0 })
+            Ok(Self {
+                broker_ref,
+                handle_count: 0,
+            })
         }
     }
 }
@@ -101,5 +119,13 @@ impl Message<SetEnabled> for Backlight {
                 .set_duty_cycle(if enabled { 0.5 } else { 0.0 })
                 .unwrap();
         }
+
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "backlight".parse().unwrap(),
+                message: crate::BrokerMessage::BacklightChanged(enabled),
+            })
+            .await
+            .unwrap();
     }
 }
@@ -0,0 +1,384 @@
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+
+use crate::state_file;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalendarConfig {
+    #[serde(default = "default_reminder_minutes")]
+    pub reminder_minutes: Vec<i64>,
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_reminder_minutes() -> Vec<i64> {
+    vec![10, 1]
+}
+
+fn default_path() -> String {
+    "calendar.json".to_string()
+}
+
+// Reminders more than this late, e.g. after a restart, are skipped instead of fired
+const REMINDER_GRACE: chrono::Duration = chrono::Duration::minutes(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledEvent {
+    pub uid: String,
+    pub description: String,
+    pub start_at: chrono::DateTime<chrono::Local>,
+    pub end_at: chrono::DateTime<chrono::Local>,
+    pub all_day: bool,
+    pub reminder_minutes: Vec<i64>,
+    pub fired_reminders: Vec<i64>,
+    #[serde(default)]
+    pub started: bool,
+}
+
+impl ScheduledEvent {
+    fn event(&self) -> crate::CalendarEventUpcoming {
+        crate::CalendarEventUpcoming {
+            uid: self.uid.clone(),
+            description: self.description.clone(),
+            start_at: self.start_at,
+            end_at: self.end_at,
+            all_day: self.all_day,
+            reminder_minutes: Some(self.reminder_minutes.clone()),
+        }
+    }
+
+    // All-day events are shown but never counted down to
+    fn pending_reminders(&self) -> Vec<i64> {
+        if self.all_day {
+            return vec![];
+        }
+
+        self.reminder_minutes
+            .iter()
+            .filter(|minutes| !self.fired_reminders.contains(minutes))
+            .copied()
+            .collect()
+    }
+}
+
+// Calendar sources publish every event they see on "calendar"; this keeps them ordered by start,
+// replaced or cancelled by `uid`, and turns them into reminders on "reminder"
+pub struct Calendar {
+    config: CalendarConfig,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    events: Vec<ScheduledEvent>,
+    next_event_key: Option<(String, chrono::DateTime<chrono::Local>)>,
+}
+
+impl Actor for Calendar {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        CalendarConfig,
+    );
+    type Error = state_file::StateFileError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
+
+        let events = state_file::load(&config.path).await?;
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "calendar".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
+
+            loop {
+                interval.tick().await;
+
+                if actor_ref.tell(Tick).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            broker_ref,
+            events,
+            next_event_key: None,
+        })
+    }
+}
+
+impl Message<crate::BrokerMessage> for Calendar {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match message {
+            crate::BrokerMessage::CalendarEventUpcoming(event) => self.update(event).await,
+            crate::BrokerMessage::CalendarEventCancelled(uid) => self.cancel(&uid).await,
+            _ => return,
+        }
+
+        self.save_or_log().await;
+        self.check_next_event().await;
+    }
+}
+
+struct Tick;
+
+impl Message<Tick> for Calendar {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Tick,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let now = chrono::Local::now();
+
+        let mut changed = false;
+        let mut reminders = vec![];
+        let mut started = vec![];
+
+        for event in self.events.iter_mut() {
+            if !event.all_day && !event.started && now >= event.start_at {
+                event.started = true;
+                changed = true;
+
+                if now - event.start_at <= REMINDER_GRACE {
+                    started.push(event.event());
+                }
+            }
+
+            // Only the latest due reminder is worth firing, earlier ones are covered by it
+            let Some(minutes) = event
+                .pending_reminders()
+                .into_iter()
+                .filter(|minutes| now >= event.start_at - chrono::Duration::minutes(*minutes))
+                .min()
+            else {
+                continue;
+            };
+
+            event.fired_reminders.extend(
+                event
+                    .pending_reminders()
+                    .into_iter()
+                    .filter(|pending| *pending >= minutes),
+            );
+            changed = true;
+
+            if now - (event.start_at - chrono::Duration::minutes(minutes)) <= REMINDER_GRACE {
+                reminders.push(crate::CalendarReminder {
+                    event: event.event(),
+                    minutes_before: minutes,
+                });
+            }
+        }
+
+        let (ended, events): (Vec<_>, Vec<_>) = std::mem::take(&mut self.events)
+            .into_iter()
+            .partition(|event| event.end_at <= now);
+
+        self.events = events;
+        changed |= !ended.is_empty();
+
+        for reminder in reminders {
+            tracing::info!(
+                "calendar: {:?} in {} minutes",
+                reminder.event.description,
+                reminder.minutes_before
+            );
+
+            self.publish("reminder", crate::BrokerMessage::CalendarReminder(reminder))
+                .await;
+        }
+
+        for event in started {
+            tracing::info!("calendar: {:?} started", event.description);
+
+            self.publish(
+                "schedule",
+                crate::BrokerMessage::CalendarEventStarted(event),
+            )
+            .await;
+        }
+
+        for event in ended.into_iter().filter(|event| event.started) {
+            tracing::info!("calendar: {:?} ended", event.description);
+
+            self.publish(
+                "schedule",
+                crate::BrokerMessage::CalendarEventEnded(event.event()),
+            )
+            .await;
+        }
+
+        if changed {
+            self.save_or_log().await;
+        }
+
+        self.check_next_event().await;
+    }
+}
+
+pub struct GetSchedule;
+
+impl Message<GetSchedule> for Calendar {
+    type Reply = Vec<ScheduledEvent>;
+
+    async fn handle(
+        &mut self,
+        _message: GetSchedule,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.events.clone()
+    }
+}
+
+// The next event is only published when it changes, so a display that (re)starts asks for it
+pub struct GetNextEvent;
+
+impl Message<GetNextEvent> for Calendar {
+    type Reply = Option<crate::CalendarEventUpcoming>;
+
+    async fn handle(
+        &mut self,
+        _message: GetNextEvent,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.next_event().map(|event| event.event())
+    }
+}
+
+impl Calendar {
+    async fn update(&mut self, event: crate::CalendarEventUpcoming) {
+        if event.end_at <= chrono::Local::now() {
+            return;
+        }
+
+        let reminder_minutes = event
+            .reminder_minutes
+            .unwrap_or_else(|| self.config.reminder_minutes.clone());
+
+        // An unchanged event keeps its fired reminders, a moved one starts over
+        let existing = self
+            .events
+            .iter()
+            .find(|existing| existing.uid == event.uid)
+            .map(|existing| {
+                (
+                    existing.start_at == event.start_at
+                        && existing.reminder_minutes == reminder_minutes,
+                    existing.fired_reminders.clone(),
+                    existing.started,
+                )
+            });
+
+        let (fired_reminders, started) = match existing {
+            Some((true, fired_reminders, started)) => (fired_reminders, started),
+            Some((false, _, _)) => {
+                self.cancel(&event.uid).await;
+                (vec![], false)
+            }
+            None => (vec![], false),
+        };
+
+        tracing::info!("calendar: scheduling {:?}", event.description);
+
+        self.events.retain(|existing| existing.uid != event.uid);
+        self.events.push(ScheduledEvent {
+            uid: event.uid,
+            description: event.description,
+            start_at: event.start_at,
+            end_at: event.end_at,
+            all_day: event.all_day,
+            reminder_minutes,
+            fired_reminders,
+            started,
+        });
+        self.events.sort_by_key(|event| event.start_at);
+    }
+
+    // A countdown already running for the event is stopped along with it, and an event that's
+    // in progress ends early
+    async fn cancel(&mut self, uid: &str) {
+        let Some(index) = self.events.iter().position(|event| event.uid == uid) else {
+            return;
+        };
+
+        let event = self.events.remove(index);
+
+        tracing::info!("calendar: cancelling {:?}", event.description);
+
+        if !event.fired_reminders.is_empty() && event.start_at > chrono::Local::now() {
+            self.publish("countdown", crate::BrokerMessage::CancelAnimation)
+                .await;
+        }
+
+        if event.started {
+            self.publish(
+                "schedule",
+                crate::BrokerMessage::CalendarEventEnded(event.event()),
+            )
+            .await;
+        }
+    }
+
+    // Timed events come first, an all-day event only shows when nothing else is left
+    fn next_event(&self) -> Option<&ScheduledEvent> {
+        let now = chrono::Local::now();
+
+        self.events
+            .iter()
+            .find(|event| !event.all_day && event.start_at > now)
+            .or_else(|| self.events.iter().find(|event| event.all_day))
+    }
+
+    async fn check_next_event(&mut self) {
+        let next_event = self.next_event().cloned();
+        let key = next_event
+            .as_ref()
+            .map(|event| (event.uid.clone(), event.start_at));
+
+        if key == self.next_event_key {
+            return;
+        }
+
+        self.next_event_key = key;
+
+        self.publish(
+            "schedule",
+            crate::BrokerMessage::NextCalendarEvent(next_event.map(|event| event.event())),
+        )
+        .await;
+    }
+
+    async fn publish(&self, topic: &str, message: crate::BrokerMessage) {
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: topic.parse().unwrap(),
+                message,
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn save_or_log(&self) {
+        if let Err(e) = self.save().await {
+            tracing::error!("! could not save calendar to {}: {}", self.config.path, e);
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save_pretty(&self.config.path, &self.events).await
+    }
+}
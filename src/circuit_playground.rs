@@ -1,97 +1,84 @@
 use kameo::error::Infallible;
 use kameo::message::StreamMessage;
 use kameo::prelude::*;
-
-#[cfg(feature = "pi")]
-use futures::stream::{StreamExt};
-#[cfg(feature = "pi")]
-use tokio_serial::SerialPortBuilderExt;
-#[cfg(feature = "pi")]
-use tokio_util::codec::{Framed, LinesCodec};
+use kameo_actors::broker;
+use serde::Deserialize;
+
+use crate::transport;
+
+pub const DEVICE: transport::Device = transport::Device {
+    name: "circuit_playground",
+    baud_rate: 115200,
+    usb_vid: 0x239A,
+    usb_pid: 0x8019,
+    heartbeat: "c",
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitPlaygroundConfig {
+    #[serde(flatten)]
+    pub transport: transport::TransportSettings,
+}
 
 pub struct CircuitPlayground {
-    client: reqwest::Client,
-    last_metrics_submitted_at: Option<chrono::DateTime<chrono::Utc>>,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    // Per input
+    last_metrics_submitted_at: std::collections::HashMap<String, chrono::DateTime<chrono::Utc>>,
 }
 
 impl Actor for CircuitPlayground {
-    type Args = ();
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        transport::TransportConfig,
+    );
     type Error = Infallible;
 
-    async fn on_start(_state: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let mut headers = reqwest::header::HeaderMap::new();
-
-        headers.insert(
-            "DD-API-KEY",
-            reqwest::header::HeaderValue::from_str(&std::env::var("DATADOG_API_KEY").unwrap())
-                .unwrap(),
-        );
-
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .unwrap();
-
-        #[cfg(feature = "pi")]
-        {
-            let serial_port = tokio_serial::new(
-                std::env::var("CIRCUIT_PLAYGROUND_SERIAL_PATH").unwrap(),
-                115200,
-            )
-            .open_native_async()
-            .unwrap();
-            tracing::info!("serial opened");
-
-            let device = Framed::new(serial_port, LinesCodec::new());
-            let (_transmit, receive) = device.split::<String>();
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, transport_config) = state;
 
-            _actor_ref.attach_stream(receive, (), ());
+        let link = transport_config.connect(None);
 
-            Ok(Self {
-                last_metrics_submitted_at: None,
-                client,
-            })
-        }
+        actor_ref.attach_stream(link.receive, (), ());
 
-        #[cfg(not(feature = "pi"))]
-        {
-            Ok(Self {
-                last_metrics_submitted_at: None,
-                client,
-            })
-        }
+        Ok(Self {
+            broker_ref,
+            last_metrics_submitted_at: std::collections::HashMap::new(),
+        })
     }
 }
 
-impl Message<StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (), ()>>
-    for CircuitPlayground
-{
+impl Message<StreamMessage<transport::Received, (), ()>> for CircuitPlayground {
     type Reply = ();
 
     async fn handle(
         &mut self,
-        message: StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (), ()>,
+        message: StreamMessage<transport::Received, (), ()>,
         context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         match message {
-            StreamMessage::Next(Ok(line)) => {
+            // It only ever sends readings
+            StreamMessage::Next(transport::Received::Connected) => {}
+            StreamMessage::Next(transport::Received::Line(line)) => {
                 if line == "c" {
                     return;
                 }
 
-                if line.starts_with("ra1") {
-                    let value = line["ra1".len()..].parse::<u32>().unwrap();
+                if let Some(reading) = line.strip_prefix('r') {
+                    let Some((input, value)) = parse_reading(reading) else {
+                        tracing::warn!("! rejected reading {:?}", line);
+                        return;
+                    };
 
                     context
                         .actor_ref()
-                        .tell(ReadingUpdated { value })
+                        .tell(ReadingUpdated {
+                            input: input.to_string(),
+                            value,
+                        })
                         .await
                         .unwrap();
                 }
             }
-            StreamMessage::Next(Err(e)) => {
-                tracing::error!("! serial error: {}", e);
-            }
             StreamMessage::Started(_) => {
                 tracing::info!("= serial started");
             }
@@ -103,6 +90,7 @@ impl Message<StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (
 }
 
 struct ReadingUpdated {
+    input: String,
     value: u32,
 }
 
@@ -118,33 +106,68 @@ impl Message<ReadingUpdated> for CircuitPlayground {
 
         let current_date = chrono::Utc::now();
 
-        if let Some(last_metrics_submitted_at) = self.last_metrics_submitted_at
-            && current_date - last_metrics_submitted_at < chrono::Duration::minutes(1)
+        if let Some(last_metrics_submitted_at) = self.last_metrics_submitted_at.get(&message.input)
+            && current_date - *last_metrics_submitted_at < chrono::Duration::minutes(1)
         {
             return;
         }
 
-        self.client
-            .post("https://api.datadoghq.com/api/v2/series")
-            .json(&serde_json::json!({
-                "series": [
-                    {
-                        "metric": "soil.capacitance",
-                        "type": 3,
-                        "points": [{ "timestamp": current_date.timestamp(), "value": value, }],
-                        "resources": [{ "name": "deskpi", "type": "host" }],
-                        "tags": ["input:a1"],
-                    }
-                ]
-            }))
-            .send()
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "sensors".parse().unwrap(),
+                message: crate::BrokerMessage::SensorReadings(crate::SensorReadings {
+                    source: "soil".to_string(),
+                    readings: [(message.input.clone(), value as f64)].into(),
+                    at: current_date,
+                }),
+            })
             .await
-            .unwrap()
-            .error_for_status()
             .unwrap();
 
-        tracing::info!("submitted metrics: {}", value);
+        crate::metrics::Point::gauge("soil.capacitance", value as f64)
+            .tag(format!("input:{}", message.input))
+            .at(current_date)
+            .report(&self.broker_ref)
+            .await;
+
+        tracing::info!("reported soil capacitance on {}: {}", message.input, value);
+
+        self.last_metrics_submitted_at
+            .insert(message.input, current_date);
+    }
+}
+
+// `r<input>:<value>`, e.g. `ra1:512` for 512 on A1. Firmware from before the delimiter sends
+// `ra1512`, which only works because its input names are always two characters.
+fn parse_reading(reading: &str) -> Option<(&str, u32)> {
+    let (input, value) = match reading.split_once(':') {
+        Some((input, value)) => (input, value),
+        None => reading.split_at_checked(2)?,
+    };
+
+    if input.is_empty() {
+        return None;
+    }
+
+    Some((input, value.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readings_name_their_input() {
+        assert_eq!(parse_reading("a1:512"), Some(("a1", 512)));
+        assert_eq!(parse_reading("a6:0"), Some(("a6", 0)));
+        assert_eq!(parse_reading("a1512"), Some(("a1", 512)));
+    }
 
-        self.last_metrics_submitted_at = Some(current_date);
+    #[test]
+    fn malformed_readings_are_rejected() {
+        assert_eq!(parse_reading(":512"), None);
+        assert_eq!(parse_reading("a1:"), None);
+        assert_eq!(parse_reading("a1:-3"), None);
+        assert_eq!(parse_reading("a"), None);
     }
 }
@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+const DEFAULT_PATH: &str = "desk.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Section(String, toml::de::Error),
+    Invalid(String, String),
+    Env(&'static str, String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path, e),
+            ConfigError::Section(section, e) => write!(f, "invalid [{}] config: {}", section, e),
+            ConfigError::Invalid(section, e) => write!(f, "invalid [{}] config: {}", section, e),
+            ConfigError::Env(name, value) => write!(f, "invalid {}: {:?}", name, value),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SwarmConfig {
+    #[serde(default = "default_swarm_listen_address")]
+    pub listen_address: String,
+}
+
+fn default_swarm_listen_address() -> String {
+    "/ip4/0.0.0.0/udp/8020/quic-v1".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatadogConfig {
+    pub api_key: String,
+    // Another Datadog site such as https://api.datadoghq.eu, or a local stand-in
+    #[serde(default = "default_datadog_url", deserialize_with = "deserialize_url")]
+    pub url: reqwest::Url,
+}
+
+fn default_datadog_url() -> reqwest::Url {
+    reqwest::Url::parse("https://api.datadoghq.com").unwrap()
+}
+
+pub fn deserialize_url<'de, D>(deserializer: D) -> Result<reqwest::Url, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    reqwest::Url::parse(&value).map_err(serde::de::Error::custom)
+}
+
+// `host:port` for the HTTP servers, so a typo fails at startup rather than when the server binds
+pub fn deserialize_socket_addr<'de, D>(deserializer: D) -> Result<std::net::SocketAddr, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    value
+        .parse()
+        .map_err(|e| serde::de::Error::custom(format!("listen address {:?}: {}", value, e)))
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Float,
+}
+
+// Environment variables that predate the config file and still take precedence over it
+const ENV_OVERRIDES: &[(&str, &str, Kind)] = &[
+    ("SWARM_LISTEN_ADDRESS", "swarm.listen_address", Kind::String),
+    ("TOGGL_AUTH", "toggl.auth", Kind::String),
+    ("TOGGL_WORKSPACE_ID", "toggl.workspace_id", Kind::Integer),
+    ("TOGGL_PROJECT_ID", "toggl.project_id", Kind::Integer),
+    ("UNICORN_BASE_URL", "unicorn.base_url", Kind::String),
+    ("DATADOG_API_KEY", "datadog.api_key", Kind::String),
+    ("LATITUDE", "weather.latitude", Kind::Float),
+    ("LONGITUDE", "weather.longitude", Kind::Float),
+    ("WEATHER_TIMEZONE", "weather.timezone", Kind::String),
+    ("MACROPAD_TRANSPORT", "macropad.transport", Kind::String),
+    ("MACROPAD_SERIAL_PATH", "macropad.serial_path", Kind::String),
+    ("THINKINK_TRANSPORT", "thinkink.transport", Kind::String),
+    ("THINKINK_SERIAL_PATH", "thinkink.serial_path", Kind::String),
+    (
+        "CIRCUIT_PLAYGROUND_TRANSPORT",
+        "circuit_playground.transport",
+        Kind::String,
+    ),
+    (
+        "CIRCUIT_PLAYGROUND_SERIAL_PATH",
+        "circuit_playground.serial_path",
+        Kind::String,
+    ),
+    (
+        "HOME_ASSISTANT_LISTEN_ADDRESS",
+        "home_assistant.listen_address",
+        Kind::String,
+    ),
+    ("URBAN_LISTEN_ADDRESS", "urban.listen_address", Kind::String),
+    ("API_LISTEN_ADDRESS", "api.listen_address", Kind::String),
+    ("INBOX_PATH", "inbox.path", Kind::String),
+];
+
+pub struct Config {
+    table: toml::Table,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = std::env::var("DESK_CONFIG").ok();
+
+        let mut table = match &path {
+            Some(path) => Self::read(path)?,
+            None if std::path::Path::new(DEFAULT_PATH).exists() => Self::read(DEFAULT_PATH)?,
+            None => toml::Table::new(),
+        };
+
+        for &(name, path, kind) in ENV_OVERRIDES {
+            let Ok(value) = std::env::var(name) else {
+                continue;
+            };
+
+            let (section, key) = path.split_once('.').unwrap();
+
+            let value = match kind {
+                Kind::String => toml::Value::String(value),
+                Kind::Integer => toml::Value::Integer(
+                    value
+                        .parse()
+                        .map_err(|_| ConfigError::Env(name, value.clone()))?,
+                ),
+                Kind::Float => toml::Value::Float(
+                    value
+                        .parse()
+                        .map_err(|_| ConfigError::Env(name, value.clone()))?,
+                ),
+            };
+
+            table
+                .entry(section.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| ConfigError::Env(name, format!("[{}] is not a table", section)))?
+                .insert(key.to_string(), value);
+        }
+
+        Ok(Self { table })
+    }
+
+    fn read(path: &str) -> Result<toml::Table, ConfigError> {
+        let data =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_string(), e))?;
+
+        toml::from_str(&data).map_err(|e| ConfigError::Parse(path.to_string(), e))
+    }
+
+    // Whether the section is in the file at all, for the ones that stay off unless configured
+    pub fn present(&self, section: &str) -> bool {
+        self.table.contains_key(section)
+    }
+
+    pub fn enabled(&self, section: &str) -> bool {
+        self.table
+            .get(section)
+            .and_then(|section| section.get("enabled"))
+            .and_then(|enabled| enabled.as_bool())
+            .unwrap_or(true)
+    }
+
+    // Deserializes a section, or returns `None` when the section sets `enabled = false`
+    pub fn section<T: DeserializeOwned>(&self, section: &str) -> Result<Option<T>, ConfigError> {
+        if !self.enabled(section) {
+            tracing::info!("[{}] disabled", section);
+            return Ok(None);
+        }
+
+        self.required(section).map(Some)
+    }
+
+    pub fn required<T: DeserializeOwned>(&self, section: &str) -> Result<T, ConfigError> {
+        let table = match self.table.get(section) {
+            Some(toml::Value::Table(table)) => table.clone(),
+            _ => toml::Table::new(),
+        };
+
+        table
+            .try_into()
+            .map_err(|e| ConfigError::Section(section.to_string(), e))
+    }
+}
@@ -0,0 +1,442 @@
+use kameo::error::Infallible;
+use kameo::prelude::*;
+use kameo_actors::broker;
+use rumqttc::QoS;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::inbox::{self, Inbox};
+use crate::mqtt::{self, MqttConfig, MqttEvent};
+use crate::restarting_manager::{self, RestartingManager};
+use crate::toggl::{self, Toggl};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeskStateConfig {
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+    // Also the prefix of the state topics, e.g. `desk/inbox_unread`
+    #[serde(default = "default_node_id")]
+    pub node_id: String,
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_node_id() -> String {
+    "desk".to_string()
+}
+
+#[derive(Debug, Clone)]
+struct Entity {
+    component: &'static str,
+    name: String,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    icon: Option<&'static str>,
+    measurement: bool,
+}
+
+impl Entity {
+    fn sensor(name: &str, icon: &'static str) -> Self {
+        Self {
+            component: "sensor",
+            name: name.to_string(),
+            unit: None,
+            device_class: None,
+            icon: Some(icon),
+            measurement: false,
+        }
+    }
+
+    fn measurement(name: &str, unit: Option<&'static str>, device_class: &'static str) -> Self {
+        Self {
+            unit,
+            device_class: Some(device_class),
+            icon: None,
+            measurement: true,
+            ..Self::sensor(name, "")
+        }
+    }
+
+    fn binary_sensor(name: &str, icon: &'static str) -> Self {
+        Self {
+            component: "binary_sensor",
+            ..Self::sensor(name, icon)
+        }
+    }
+
+    fn counter(name: &str, icon: &'static str) -> Self {
+        Self {
+            measurement: true,
+            ..Self::sensor(name, icon)
+        }
+    }
+
+    // Readings Home Assistant has a device class for get their units; anything else is a plain
+    // number
+    fn reading(source: &str, key: &str) -> Self {
+        let name = format!("{} {}", source, key.replace('_', "."));
+
+        if source == "plants" {
+            return Self::measurement(&name, Some("%"), "moisture");
+        }
+
+        match key {
+            "ec02" => Self::measurement(&name, Some("ppm"), "carbon_dioxide"),
+            "temperature" => Self::measurement(&name, Some("°C"), "temperature"),
+            "humidity" => Self::measurement(&name, Some("%"), "humidity"),
+            "pressure" => Self::measurement(&name, Some("hPa"), "atmospheric_pressure"),
+            "pm1" => Self::measurement(&name, Some("µg/m³"), "pm1"),
+            "pm2_5" => Self::measurement(&name, Some("µg/m³"), "pm25"),
+            "pm10" => Self::measurement(&name, Some("µg/m³"), "pm10"),
+            _ => Self::counter(&name, "mdi:gauge"),
+        }
+    }
+}
+
+struct State {
+    entity: Entity,
+    value: String,
+    announced: bool,
+}
+
+// Mirrors the desk to Home Assistant: each value is a sensor announced through MQTT discovery the
+// first time it's known, and announced again whenever Home Assistant or the broker restarts
+pub struct DeskState {
+    config: DeskStateConfig,
+    client: rumqttc::AsyncClient,
+    toggl_manager_ref: Option<ActorRef<RestartingManager<Toggl>>>,
+    inbox_manager_ref: Option<ActorRef<RestartingManager<Inbox>>>,
+    states: BTreeMap<String, State>,
+}
+
+impl Actor for DeskState {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        Option<ActorRef<RestartingManager<Toggl>>>,
+        Option<ActorRef<RestartingManager<Inbox>>>,
+        MqttConfig,
+        DeskStateConfig,
+    );
+    type Error = Infallible;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, toggl_manager_ref, inbox_manager_ref, mqtt_config, config) = state;
+
+        // Home Assistant marks every entity unavailable when the hub drops off
+        let last_will = rumqttc::LastWill::new(
+            availability_topic(&config),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        );
+
+        let client = mqtt::connect(&mqtt_config, "state", Some(last_will), actor_ref.clone());
+
+        for topic in ["toggl", "inbox", "sensors", "backlight", "light"] {
+            broker_ref
+                .tell(broker::Subscribe {
+                    topic: topic.parse().unwrap(),
+                    recipient: actor_ref.clone().recipient(),
+                })
+                .await
+                .unwrap();
+        }
+
+        // Keeps the elapsed minutes current
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+
+                if actor_ref.tell(Refresh).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            client,
+            toggl_manager_ref,
+            inbox_manager_ref,
+            states: BTreeMap::new(),
+        })
+    }
+}
+
+impl Message<MqttEvent> for DeskState {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: MqttEvent,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match message {
+            MqttEvent::Connected => {
+                if let Err(e) = self.client.try_subscribe(
+                    format!("{}/status", self.config.discovery_prefix),
+                    QoS::AtLeastOnce,
+                ) {
+                    tracing::warn!("! could not subscribe to home assistant status: {}", e);
+                }
+
+                self.publish(availability_topic(&self.config), "online".to_string());
+
+                self.announce_all();
+            }
+            // Home Assistant forgets entities that aren't retained when it restarts
+            MqttEvent::Received { topic, payload }
+                if topic == format!("{}/status", self.config.discovery_prefix)
+                    && payload == b"online" =>
+            {
+                self.announce_all();
+            }
+            MqttEvent::Received { .. } => {}
+        }
+    }
+}
+
+impl Message<crate::BrokerMessage> for DeskState {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match message {
+            crate::BrokerMessage::TimeEntryStarted(_)
+            | crate::BrokerMessage::TimeEntryStopped
+            | crate::BrokerMessage::TimeEntryTimeUpdated(_) => self.refresh_toggl().await,
+            crate::BrokerMessage::InboxUpdated(pending) => {
+                self.set(
+                    "inbox_unread",
+                    Entity::counter("Unread messages", "mdi:email"),
+                    pending.to_string(),
+                );
+            }
+            crate::BrokerMessage::SensorReadings(sensor_readings) => {
+                for (key, value) in sensor_readings.readings {
+                    self.set(
+                        &format!("{}_{}", sensor_readings.source, key),
+                        Entity::reading(&sensor_readings.source, &key),
+                        value.to_string(),
+                    );
+                }
+            }
+            crate::BrokerMessage::BacklightChanged(enabled) => {
+                self.set(
+                    "backlight",
+                    Entity::binary_sensor("Backlight", "mdi:monitor"),
+                    on_off(enabled),
+                );
+            }
+            crate::BrokerMessage::LightLevelChanged(value) => {
+                self.set(
+                    "light_level",
+                    Entity::counter("Light level", "mdi:lightbulb"),
+                    value.to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct Refresh;
+
+impl Message<Refresh> for DeskState {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Refresh,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.refresh_toggl().await;
+
+        let Some(inbox_manager_ref) = &self.inbox_manager_ref else {
+            return;
+        };
+
+        let pending = match inbox_manager_ref.ask(restarting_manager::GetChild).await {
+            Ok(inbox_ref) => {
+                inbox_ref
+                    .ask(inbox::GetEntries { pending_only: true })
+                    .await
+            }
+            Err(e) => {
+                tracing::warn!("! desk state could not reach inbox: {}", e);
+                return;
+            }
+        };
+
+        match pending {
+            Ok(pending) => {
+                self.set(
+                    "inbox_unread",
+                    Entity::counter("Unread messages", "mdi:email"),
+                    pending.len().to_string(),
+                );
+            }
+            Err(e) => tracing::warn!("! desk state could not read inbox: {}", e),
+        }
+    }
+}
+
+impl DeskState {
+    async fn refresh_toggl(&mut self) {
+        let Some(toggl_manager_ref) = &self.toggl_manager_ref else {
+            return;
+        };
+
+        let running = match toggl_manager_ref.ask(restarting_manager::GetChild).await {
+            Ok(toggl_ref) => toggl_ref.ask(toggl::GetRunningTimeEntry).await,
+            Err(e) => {
+                tracing::warn!("! desk state could not reach toggl: {}", e);
+                return;
+            }
+        };
+
+        let running = match running {
+            Ok(running) => running,
+            Err(e) => {
+                tracing::warn!("! desk state could not read toggl: {}", e);
+                return;
+            }
+        };
+
+        let (description, minutes) = match &running {
+            Some(time_entry) => (
+                time_entry.description().to_string(),
+                (chrono::Utc::now() - time_entry.start)
+                    .num_minutes()
+                    .to_string(),
+            ),
+            None => (String::new(), "0".to_string()),
+        };
+
+        self.set(
+            "toggl_running",
+            Entity::binary_sensor("Time entry running", "mdi:timer"),
+            on_off(running.is_some()),
+        );
+        self.set(
+            "toggl_entry",
+            Entity::sensor("Time entry", "mdi:timer-outline"),
+            description,
+        );
+        self.set(
+            "toggl_minutes",
+            Entity::measurement("Time entry elapsed", Some("min"), "duration"),
+            minutes,
+        );
+    }
+
+    // Only publishes when the value changed, announcing the entity first if needed
+    fn set(&mut self, object_id: &str, entity: Entity, value: String) {
+        match self.states.get_mut(object_id) {
+            Some(state) if state.announced => {
+                if state.value != value {
+                    state.value = value.clone();
+                    self.publish(format!("{}/{}", self.config.node_id, object_id), value);
+                }
+            }
+            _ => {
+                self.states.insert(
+                    object_id.to_string(),
+                    State {
+                        entity,
+                        value,
+                        announced: false,
+                    },
+                );
+
+                self.announce(object_id);
+            }
+        }
+    }
+
+    fn announce_all(&mut self) {
+        let object_ids = self.states.keys().cloned().collect::<Vec<_>>();
+
+        for object_id in object_ids {
+            self.announce(&object_id);
+        }
+    }
+
+    fn announce(&mut self, object_id: &str) {
+        let Some(state) = self.states.get(object_id) else {
+            return;
+        };
+
+        let unique_id = format!("{}_{}", self.config.node_id, object_id);
+        let state_topic = format!("{}/{}", self.config.node_id, object_id);
+
+        let mut discovery = serde_json::json!({
+            "name": state.entity.name,
+            "unique_id": unique_id,
+            "object_id": unique_id,
+            "state_topic": state_topic,
+            "availability_topic": availability_topic(&self.config),
+            "device": {
+                "identifiers": [self.config.node_id],
+                "name": "Desk",
+            },
+        });
+
+        if let Some(unit) = state.entity.unit {
+            discovery["unit_of_measurement"] = unit.into();
+        }
+        if let Some(device_class) = state.entity.device_class {
+            discovery["device_class"] = device_class.into();
+        }
+        if let Some(icon) = state.entity.icon {
+            discovery["icon"] = icon.into();
+        }
+        if state.entity.measurement {
+            discovery["state_class"] = "measurement".into();
+        }
+
+        let discovery_topic = format!(
+            "{}/{}/{}/{}/config",
+            self.config.discovery_prefix, state.entity.component, self.config.node_id, object_id
+        );
+        let value = state.value.clone();
+
+        let announced = self.publish(discovery_topic, discovery.to_string())
+            && self.publish(state_topic, value);
+
+        if let Some(state) = self.states.get_mut(object_id) {
+            state.announced = announced;
+        }
+    }
+
+    // Everything is retained so Home Assistant has the current state as soon as it subscribes.
+    // Doesn't wait for room in the client's queue, whatever is dropped while the broker is away
+    // gets announced again on reconnect.
+    fn publish(&self, topic: String, payload: String) -> bool {
+        match self
+            .client
+            .try_publish(&topic, QoS::AtLeastOnce, true, payload)
+        {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("! could not publish {}: {}", topic, e);
+                false
+            }
+        }
+    }
+}
+
+fn availability_topic(config: &DeskStateConfig) -> String {
+    format!("{}/availability", config.node_id)
+}
+
+fn on_off(value: bool) -> String {
+    if value { "ON" } else { "OFF" }.to_string()
+}
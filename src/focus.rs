@@ -0,0 +1,622 @@
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::protocol::ErrorReason;
+use crate::restarting_manager::{self, RestartingManager};
+use crate::state_file;
+use crate::toggl;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FocusConfig {
+    #[serde(default = "default_work_minutes")]
+    pub work_minutes: i64,
+    #[serde(default = "default_short_break_minutes")]
+    pub short_break_minutes: i64,
+    #[serde(default = "default_long_break_minutes")]
+    pub long_break_minutes: i64,
+    #[serde(default = "default_rounds")]
+    pub rounds: u32,
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_work_minutes() -> i64 {
+    25
+}
+
+fn default_short_break_minutes() -> i64 {
+    5
+}
+
+fn default_long_break_minutes() -> i64 {
+    15
+}
+
+fn default_rounds() -> u32 {
+    4
+}
+
+fn default_path() -> String {
+    "focus-sessions.json".to_string()
+}
+
+impl FocusConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_lengths(
+            self.work_minutes,
+            self.short_break_minutes,
+            self.long_break_minutes,
+            self.rounds,
+        )
+        .map_err(|e| ConfigError::Invalid("focus".to_string(), e))
+    }
+}
+
+// A zero-minute phase would end as soon as it starts and skip straight through the session
+fn check_lengths(
+    work_minutes: i64,
+    short_break_minutes: i64,
+    long_break_minutes: i64,
+    rounds: u32,
+) -> Result<(), String> {
+    for (name, minutes) in [
+        ("work_minutes", work_minutes),
+        ("short_break_minutes", short_break_minutes),
+        ("long_break_minutes", long_break_minutes),
+    ] {
+        if minutes < 1 {
+            return Err(format!("{} must be at least 1", name));
+        }
+    }
+
+    if rounds < 1 {
+        return Err("rounds must be at least 1".to_string());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Phase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+// A session is `rounds` work phases with short breaks between them and a long break at the end
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub details: Option<toggl::TimeEntryDetails>,
+    pub work_minutes: i64,
+    pub short_break_minutes: i64,
+    pub long_break_minutes: i64,
+    pub rounds: u32,
+    pub round: u32,
+    pub completed_rounds: u32,
+    pub phase: Phase,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub phase_ends_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Session {
+    fn phase_minutes(&self) -> i64 {
+        match self.phase {
+            Phase::Work => self.work_minutes,
+            Phase::ShortBreak => self.short_break_minutes,
+            Phase::LongBreak => self.long_break_minutes,
+        }
+    }
+
+    // Moves on to the next phase; false once the long break is over
+    fn advance(&mut self) -> bool {
+        match self.phase {
+            Phase::Work => {
+                self.completed_rounds += 1;
+                self.phase = if self.round >= self.rounds {
+                    Phase::LongBreak
+                } else {
+                    Phase::ShortBreak
+                };
+            }
+            Phase::ShortBreak => {
+                self.round += 1;
+                self.phase = Phase::Work;
+            }
+            Phase::LongBreak => return false,
+        }
+
+        true
+    }
+}
+
+// The running session is kept too, so a restart picks it up where it was
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedFocus {
+    #[serde(default)]
+    session: Option<Session>,
+    #[serde(default)]
+    records: Vec<SessionRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRecord {
+    pub description: Option<String>,
+    pub project_id: Option<i64>,
+    pub work_minutes: i64,
+    pub rounds: u32,
+    pub completed_rounds: u32,
+    pub completed: bool,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub ended_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct Focus {
+    config: FocusConfig,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    toggl_manager_ref: Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+    session: Option<Session>,
+    // Bumped on every phase change so timers from an earlier phase are ignored
+    generation: u64,
+    records: Vec<SessionRecord>,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    AlreadyRunning,
+    NotRunning,
+    Invalid(String),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::AlreadyRunning => write!(f, "a focus session is already running"),
+            SessionError::NotRunning => write!(f, "no focus session is running"),
+            SessionError::Invalid(e) => write!(f, "invalid focus session: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl SessionError {
+    pub fn reason(&self) -> ErrorReason {
+        match self {
+            SessionError::AlreadyRunning => ErrorReason::Conflict,
+            SessionError::NotRunning => ErrorReason::NotFound,
+            SessionError::Invalid(_) => ErrorReason::Malformed,
+        }
+    }
+}
+
+impl Actor for Focus {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+        FocusConfig,
+    );
+    type Error = state_file::StateFileError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, toggl_manager_ref, config) = state;
+
+        let SavedFocus { session, records } = state_file::load(&config.path).await?;
+
+        let focus = Self {
+            config,
+            broker_ref,
+            toggl_manager_ref,
+            session,
+            generation: 0,
+            records,
+        };
+
+        // Toggl kept the entry running meanwhile; a phase that ended while this was down ends now
+        if let Some(session) = &focus.session {
+            let remaining =
+                (session.phase_ends_at - chrono::Utc::now()).max(chrono::Duration::zero());
+
+            tracing::info!(
+                "focus: resuming {:?} with {:?} left",
+                session.phase,
+                remaining
+            );
+
+            focus.elapse_after(remaining.to_std().unwrap_or_default(), actor_ref);
+            focus
+                .publish(
+                    "countdown",
+                    crate::BrokerMessage::StartCountdown(remaining.num_minutes().max(1)),
+                )
+                .await;
+        }
+
+        Ok(focus)
+    }
+}
+
+// Unset fields fall back to the [focus] section
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartSession {
+    #[serde(default)]
+    pub details: Option<toggl::TimeEntryDetails>,
+    #[serde(default)]
+    pub work_minutes: Option<i64>,
+    #[serde(default)]
+    pub break_minutes: Option<i64>,
+    #[serde(default)]
+    pub rounds: Option<u32>,
+}
+
+impl StartSession {
+    pub fn validate(&self) -> Result<(), SessionError> {
+        check_lengths(
+            self.work_minutes.unwrap_or(1),
+            self.break_minutes.unwrap_or(1),
+            1,
+            self.rounds.unwrap_or(1),
+        )
+        .map_err(SessionError::Invalid)
+    }
+}
+
+impl Message<StartSession> for Focus {
+    type Reply = Result<(), SessionError>;
+
+    async fn handle(
+        &mut self,
+        message: StartSession,
+        context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if self.session.is_some() {
+            return Err(SessionError::AlreadyRunning);
+        }
+
+        message.validate()?;
+
+        let now = chrono::Utc::now();
+
+        self.session = Some(Session {
+            details: message.details,
+            work_minutes: message.work_minutes.unwrap_or(self.config.work_minutes),
+            short_break_minutes: message
+                .break_minutes
+                .unwrap_or(self.config.short_break_minutes),
+            long_break_minutes: self.config.long_break_minutes,
+            rounds: message.rounds.unwrap_or(self.config.rounds),
+            round: 1,
+            completed_rounds: 0,
+            phase: Phase::Work,
+            started_at: now,
+            phase_ends_at: now,
+        });
+
+        self.begin_phase(context.actor_ref().clone()).await;
+
+        Ok(())
+    }
+}
+
+pub struct StopSession;
+
+impl Message<StopSession> for Focus {
+    type Reply = Result<(), SessionError>;
+
+    async fn handle(
+        &mut self,
+        _message: StopSession,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let session = self.session.as_ref().ok_or(SessionError::NotRunning)?;
+
+        if session.phase == Phase::Work {
+            self.stop_time_entry().await;
+        }
+
+        self.publish("countdown", crate::BrokerMessage::CancelAnimation)
+            .await;
+
+        self.finish(false).await;
+
+        Ok(())
+    }
+}
+
+pub struct GetSession;
+
+impl Message<GetSession> for Focus {
+    type Reply = Option<Session>;
+
+    async fn handle(
+        &mut self,
+        _message: GetSession,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.session.clone()
+    }
+}
+
+pub struct GetRecords;
+
+impl Message<GetRecords> for Focus {
+    type Reply = Vec<SessionRecord>;
+
+    async fn handle(
+        &mut self,
+        _message: GetRecords,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.records.clone()
+    }
+}
+
+struct PhaseElapsed {
+    generation: u64,
+}
+
+impl Message<PhaseElapsed> for Focus {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: PhaseElapsed,
+        context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if message.generation != self.generation {
+            return;
+        }
+
+        let Some(session) = self.session.as_mut() else {
+            return;
+        };
+
+        if !session.advance() {
+            self.finish(true).await;
+            return;
+        }
+
+        self.begin_phase(context.actor_ref().clone()).await;
+    }
+}
+
+impl Focus {
+    async fn begin_phase(&mut self, actor_ref: ActorRef<Self>) {
+        let Some(session) = self.session.as_mut() else {
+            return;
+        };
+
+        let phase = session.phase;
+        let minutes = session.phase_minutes();
+
+        session.phase_ends_at = chrono::Utc::now() + chrono::Duration::minutes(minutes);
+
+        tracing::info!(
+            "focus: {:?} for {} minutes (round {}/{})",
+            phase,
+            minutes,
+            session.round,
+            session.rounds
+        );
+
+        self.generation += 1;
+        self.elapse_after(
+            std::time::Duration::from_secs(minutes as u64 * 60),
+            actor_ref,
+        );
+        self.save_or_log().await;
+
+        match phase {
+            Phase::Work => self.start_time_entry().await,
+            Phase::ShortBreak | Phase::LongBreak => self.stop_time_entry().await,
+        }
+
+        self.publish("countdown", crate::BrokerMessage::StartCountdown(minutes))
+            .await;
+        self.publish(
+            "focus",
+            crate::BrokerMessage::FocusPhaseStarted(crate::FocusPhaseStarted { phase, minutes }),
+        )
+        .await;
+    }
+
+    async fn finish(&mut self, completed: bool) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+
+        self.generation += 1;
+
+        tracing::info!(
+            "focus: session {} after {}/{} rounds",
+            if completed { "completed" } else { "stopped" },
+            session.completed_rounds,
+            session.rounds
+        );
+
+        self.records.push(SessionRecord {
+            description: session
+                .details
+                .as_ref()
+                .map(|details| details.description.clone()),
+            project_id: session
+                .details
+                .as_ref()
+                .and_then(|details| details.project_id),
+            work_minutes: session.work_minutes,
+            rounds: session.rounds,
+            completed_rounds: session.completed_rounds,
+            completed,
+            started_at: session.started_at,
+            ended_at: chrono::Utc::now(),
+        });
+
+        self.save_or_log().await;
+
+        self.publish("focus", crate::BrokerMessage::FocusSessionEnded)
+            .await;
+    }
+
+    fn elapse_after(&self, delay: std::time::Duration, actor_ref: ActorRef<Self>) {
+        let generation = self.generation;
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+
+            let _ = actor_ref.tell(PhaseElapsed { generation }).await;
+        });
+    }
+
+    // Toggl failures don't interrupt the session; offline changes are queued by Toggl itself
+    async fn start_time_entry(&mut self) {
+        let Some(toggl_ref) = self.toggl().await else {
+            return;
+        };
+
+        let details = self
+            .session
+            .as_ref()
+            .and_then(|session| session.details.clone());
+
+        let result = match details {
+            Some(details) => toggl_ref
+                .ask(toggl::StartTimeEntry { details })
+                .await
+                .map_err(|e| e.to_string()),
+            None => toggl_ref
+                .ask(toggl::ContinueTimeEntry)
+                .await
+                .map_err(|e| e.to_string()),
+        };
+
+        if let Err(e) = result {
+            tracing::warn!("! focus could not start time entry: {}", e);
+        }
+    }
+
+    async fn stop_time_entry(&mut self) {
+        let Some(toggl_ref) = self.toggl().await else {
+            return;
+        };
+
+        if let Err(e) = toggl_ref.ask(toggl::StopTimeEntry).await {
+            tracing::warn!("! focus could not stop time entry: {}", e);
+        }
+    }
+
+    async fn toggl(&self) -> Option<ActorRef<toggl::Toggl>> {
+        self.toggl_manager_ref
+            .as_ref()?
+            .ask(restarting_manager::GetChild)
+            .await
+            .ok()
+    }
+
+    async fn publish(&self, topic: &str, message: crate::BrokerMessage) {
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: topic.parse().unwrap(),
+                message,
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn save_or_log(&self) {
+        let saved = SavedFocus {
+            session: self.session.clone(),
+            records: self.records.clone(),
+        };
+
+        if let Err(e) = state_file::save_pretty(&self.config.path, &saved).await {
+            tracing::error!(
+                "! could not save focus sessions to {}: {}",
+                self.config.path,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(rounds: u32) -> Session {
+        let now = chrono::Utc::now();
+
+        Session {
+            details: None,
+            work_minutes: 25,
+            short_break_minutes: 5,
+            long_break_minutes: 15,
+            rounds,
+            round: 1,
+            completed_rounds: 0,
+            phase: Phase::Work,
+            started_at: now,
+            phase_ends_at: now,
+        }
+    }
+
+    fn phases(mut session: Session) -> Vec<(Phase, u32)> {
+        let mut phases = vec![(session.phase, session.round)];
+
+        while session.advance() {
+            phases.push((session.phase, session.round));
+        }
+
+        phases
+    }
+
+    #[test]
+    fn rounds_alternate_work_and_short_breaks_then_end_on_a_long_break() {
+        assert_eq!(
+            phases(session(3)),
+            vec![
+                (Phase::Work, 1),
+                (Phase::ShortBreak, 1),
+                (Phase::Work, 2),
+                (Phase::ShortBreak, 2),
+                (Phase::Work, 3),
+                (Phase::LongBreak, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_single_round_goes_straight_to_the_long_break() {
+        let mut session = session(1);
+
+        assert!(session.advance());
+        assert_eq!(session.phase, Phase::LongBreak);
+        assert_eq!(session.completed_rounds, 1);
+        assert_eq!(session.phase_minutes(), 15);
+        assert!(!session.advance());
+    }
+
+    #[test]
+    fn zero_lengths_and_rounds_are_rejected() {
+        assert!(check_lengths(25, 5, 15, 4).is_ok());
+        assert!(check_lengths(0, 5, 15, 4).is_err());
+        assert!(check_lengths(25, 0, 15, 4).is_err());
+        assert!(check_lengths(25, 5, -1, 4).is_err());
+        assert!(check_lengths(25, 5, 15, 0).is_err());
+
+        assert!(StartSession::default().validate().is_ok());
+        assert!(
+            StartSession {
+                rounds: Some(0),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::DurationRound;
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::state_file;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryConfig {
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[serde(default = "default_minute_retention_hours")]
+    pub minute_retention_hours: i64,
+    #[serde(default = "default_hour_retention_days")]
+    pub hour_retention_days: i64,
+    // Readings since the last save are lost if the hub stops in between
+    #[serde(default = "default_save_interval_secs")]
+    pub save_interval_secs: u64,
+}
+
+fn default_path() -> String {
+    "history.json".to_string()
+}
+
+fn default_minute_retention_hours() -> i64 {
+    24
+}
+
+fn default_hour_retention_days() -> i64 {
+    30
+}
+
+fn default_save_interval_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Minute,
+    Hour,
+}
+
+// Every reading within a minute or an hour, from `start`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bucket {
+    pub start: chrono::DateTime<chrono::Utc>,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Series {
+    minutes: VecDeque<Bucket>,
+    hours: VecDeque<Bucket>,
+}
+
+impl Series {
+    fn record(&mut self, at: chrono::DateTime<chrono::Utc>, value: f64) {
+        let minute = at.duration_trunc(chrono::TimeDelta::minutes(1)).unwrap();
+        let hour = at.duration_trunc(chrono::TimeDelta::hours(1)).unwrap();
+
+        record(&mut self.minutes, minute, value);
+        record(&mut self.hours, hour, value);
+    }
+
+    fn buckets(&self, resolution: Resolution) -> &VecDeque<Bucket> {
+        match resolution {
+            Resolution::Minute => &self.minutes,
+            Resolution::Hour => &self.hours,
+        }
+    }
+}
+
+// Readings carry their own time, so a late one can still land in an earlier bucket
+fn record(buckets: &mut VecDeque<Bucket>, start: chrono::DateTime<chrono::Utc>, value: f64) {
+    match buckets.binary_search_by_key(&start, |bucket| bucket.start) {
+        Ok(index) => {
+            let bucket = &mut buckets[index];
+            bucket.count += 1;
+            bucket.mean += (value - bucket.mean) / bucket.count as f64;
+            bucket.min = bucket.min.min(value);
+            bucket.max = bucket.max.max(value);
+        }
+        Err(index) => buckets.insert(
+            index,
+            Bucket {
+                start,
+                mean: value,
+                min: value,
+                max: value,
+                count: 1,
+            },
+        ),
+    }
+}
+
+// Keeps every sensor reading as per-minute buckets for a day and per-hour buckets for a month
+// (by default), so trends are available without Datadog
+pub struct History {
+    config: HistoryConfig,
+    series: BTreeMap<String, Series>,
+    dirty: bool,
+}
+
+impl Actor for History {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        HistoryConfig,
+    );
+    type Error = state_file::StateFileError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
+
+        let series = state_file::load(&config.path).await?;
+
+        tracing::info!("history loaded from {}", config.path);
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "sensors".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        let save_interval = std::time::Duration::from_secs(config.save_interval_secs.max(1));
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(save_interval);
+
+            loop {
+                interval.tick().await;
+
+                if actor_ref.tell(Tick).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            series,
+            dirty: false,
+        })
+    }
+}
+
+impl Message<crate::BrokerMessage> for History {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let crate::BrokerMessage::SensorReadings(sensor_readings) = message else {
+            return;
+        };
+
+        for (key, value) in sensor_readings.readings {
+            self.series
+                .entry(format!("{}.{}", sensor_readings.source, key))
+                .or_default()
+                .record(sensor_readings.at, value);
+        }
+
+        self.dirty = true;
+    }
+}
+
+struct Tick;
+
+impl Message<Tick> for History {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Tick,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let now = chrono::Utc::now();
+        let minutes_since = now - chrono::TimeDelta::hours(self.config.minute_retention_hours);
+        let hours_since = now - chrono::TimeDelta::days(self.config.hour_retention_days);
+
+        for series in self.series.values_mut() {
+            while series
+                .minutes
+                .front()
+                .is_some_and(|bucket| bucket.start < minutes_since)
+            {
+                series.minutes.pop_front();
+                self.dirty = true;
+            }
+
+            while series
+                .hours
+                .front()
+                .is_some_and(|bucket| bucket.start < hours_since)
+            {
+                series.hours.pop_front();
+                self.dirty = true;
+            }
+        }
+
+        // Sensors that stopped reporting a month ago
+        self.series.retain(|_, series| !series.hours.is_empty());
+
+        if !self.dirty {
+            return;
+        }
+
+        match self.save().await {
+            Ok(()) => self.dirty = false,
+            Err(e) => tracing::error!("! could not save history to {}: {}", self.config.path, e),
+        }
+    }
+}
+
+// `None` for a series that was never recorded, e.g. `urban.temperature` or `soil.a1`
+pub struct GetHistory {
+    pub series: String,
+    pub since: chrono::DateTime<chrono::Utc>,
+    pub resolution: Resolution,
+}
+
+impl Message<GetHistory> for History {
+    type Reply = Option<Vec<Bucket>>;
+
+    async fn handle(
+        &mut self,
+        message: GetHistory,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let series = self.series.get(&message.series)?;
+
+        Some(
+            series
+                .buckets(message.resolution)
+                .iter()
+                .filter(|bucket| bucket.start >= message.since)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+// The latest minute of every series
+pub struct GetLatest;
+
+impl Message<GetLatest> for History {
+    type Reply = BTreeMap<String, Bucket>;
+
+    async fn handle(
+        &mut self,
+        _message: GetLatest,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.series
+            .iter()
+            .filter_map(|(name, series)| Some((name.clone(), series.minutes.back()?.clone())))
+            .collect()
+    }
+}
+
+impl History {
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save(&self.config.path, &self.series).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minute(minutes: i64) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(1_699_999_980 + minutes * 60, 0).unwrap()
+    }
+
+    #[test]
+    fn late_readings_land_in_their_own_minute() {
+        let mut series = Series::default();
+        let half = chrono::TimeDelta::seconds(30);
+
+        series.record(minute(0) + half, 10.0);
+        series.record(minute(2) + half, 30.0);
+        series.record(minute(1) + half, 20.0);
+        series.record(minute(0), 20.0);
+
+        let minutes = series
+            .buckets(Resolution::Minute)
+            .iter()
+            .map(|bucket| (bucket.start, bucket.mean, bucket.count))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            minutes,
+            vec![
+                (minute(0), 15.0, 2),
+                (minute(1), 20.0, 1),
+                (minute(2), 30.0, 1),
+            ]
+        );
+    }
+}
@@ -1,7 +1,30 @@
-use axum::{Json, Router, http::StatusCode, routing::post};
-use kameo::error::Infallible;
+use axum::{
+    Json, Router,
+    extract::{State, rejection::JsonRejection},
+    http::StatusCode,
+    routing::post,
+};
 use kameo::prelude::*;
 use kameo_actors::broker;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HomeAssistantConfig {
+    #[serde(
+        default = "default_listen_address",
+        deserialize_with = "crate::config::deserialize_socket_addr"
+    )]
+    pub listen_address: std::net::SocketAddr,
+    #[serde(default)]
+    pub auth: crate::webhook::AuthConfig,
+}
+
+// The keys `[home_assistant.auth.<endpoint>]` accepts
+pub const ENDPOINTS: &[&str] = &["calendar", "message"];
+
+fn default_listen_address() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([0, 0, 0, 0], 9001))
+}
 
 pub struct HomeAssistant {
     broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
@@ -9,55 +32,42 @@ pub struct HomeAssistant {
 }
 
 impl Actor for HomeAssistant {
-    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>,);
-    type Error = Infallible;
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        HomeAssistantConfig,
+    );
+    type Error = crate::webhook::BindError;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let broker_ref = state.0;
+        let (broker_ref, config) = state;
 
         let (shutdown, shutdown_receive) = tokio::sync::oneshot::channel();
 
-        let calendar_actor_ref = actor_ref.clone();
-        let message_actor_ref = actor_ref.clone();
+        let app = Router::new()
+            .route(
+                "/calendar",
+                crate::webhook::guard(post(calendar), "calendar", &config.auth),
+            )
+            .route(
+                "/message",
+                crate::webhook::guard(post(message), "message", &config.auth),
+            )
+            .with_state(actor_ref);
+
+        let listener = crate::webhook::bind(config.listen_address).await?;
 
         tokio::spawn(async move {
-            let app = Router::new()
-                .route(
-                    "/calendar",
-                    post(
-                        |axum::extract::Json(payload): Json<serde_json::Value>| async move {
-                            calendar_actor_ref
-                                .tell(HomeAssistantUpdate {
-                                    payload: payload.clone(),
-                                })
-                                .await
-                                .unwrap();
-                            StatusCode::OK
-                        },
-                    ),
-                )
-                .route(
-                    "/message",
-                    post(
-                        |axum::extract::Json(payload): Json<serde_json::Value>| async move {
-                            message_actor_ref
-                                .tell(HomeAssistantMessage {
-                                    payload: payload.clone(),
-                                })
-                                .await
-                                .unwrap();
-                            StatusCode::OK
-                        },
-                    ),
-                );
-
-            let listener = tokio::net::TcpListener::bind("0.0.0.0:9001").await.unwrap();
-            axum::serve(listener, app)
-                .with_graceful_shutdown(async move {
-                    shutdown_receive.await.unwrap();
-                })
-                .await
-                .unwrap();
+            if let Err(e) = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(async move {
+                let _ = shutdown_receive.await;
+            })
+            .await
+            {
+                tracing::error!("! home assistant webhooks stopped: {}", e);
+            }
         });
 
         Ok(Self {
@@ -72,14 +82,133 @@ impl Actor for HomeAssistant {
         _reason: ActorStopReason,
     ) -> Result<(), Self::Error> {
         if let Some(shutdown) = self.shutdown.take() {
-            shutdown.send(()).unwrap();
+            let _ = shutdown.send(());
         }
         Ok(())
     }
 }
 
+// {
+//   "uid": "abc@example.com",
+//   "summary": "",
+//   "all_day": false,
+//   "start": "2025-09-02T23:30:00-05:00",
+//   "end": "2025-09-02T23:55:00-05:00",
+//   "location": "",
+//   "description": "",
+//   "reminders": [10, 1],
+//   "deleted": false
+// }
+//
+// All-day events have plain dates for "start" and "end"; "uid", "all_day", "reminders" and
+// "deleted" are optional and anything else is ignored
+#[derive(Debug, Deserialize)]
+pub struct CalendarPayload {
+    #[serde(default)]
+    uid: Option<String>,
+    summary: String,
+    #[serde(default)]
+    all_day: bool,
+    start: EventTime,
+    end: EventTime,
+    #[serde(default)]
+    reminders: Option<Vec<i64>>,
+    #[serde(default)]
+    deleted: bool,
+}
+
+#[derive(Debug)]
+struct EventTime {
+    value: String,
+    time: chrono::DateTime<chrono::Local>,
+}
+
+impl<'de> Deserialize<'de> for EventTime {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+
+        let time = match chrono::NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+            Ok(date) => date
+                .and_time(chrono::NaiveTime::MIN)
+                .and_local_timezone(chrono::Local)
+                .earliest(),
+            Err(_) => chrono::DateTime::parse_from_rfc3339(&value)
+                .ok()
+                .map(|time| time.with_timezone(&chrono::Local)),
+        };
+
+        let time = time.ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "expected a date or an RFC 3339 time, got {:?}",
+                value
+            ))
+        })?;
+
+        Ok(Self { value, time })
+    }
+}
+
+// {
+//   "text": "Hello World!",
+//   "effects": ["rainbow"],
+//   "read": false
+// }
+#[derive(Debug, Deserialize)]
+pub struct MessagePayload {
+    text: String,
+    #[serde(default)]
+    effects: Vec<String>,
+    #[serde(default)]
+    read: bool,
+}
+
+async fn calendar(
+    State(actor_ref): State<ActorRef<HomeAssistant>>,
+    payload: Result<Json<CalendarPayload>, JsonRejection>,
+) -> (StatusCode, String) {
+    match payload {
+        Ok(Json(payload)) => forward(&actor_ref, HomeAssistantUpdate { payload }).await,
+        Err(rejection) => bad_request("calendar", rejection),
+    }
+}
+
+async fn message(
+    State(actor_ref): State<ActorRef<HomeAssistant>>,
+    payload: Result<Json<MessagePayload>, JsonRejection>,
+) -> (StatusCode, String) {
+    match payload {
+        Ok(Json(payload)) => forward(&actor_ref, HomeAssistantMessage { payload }).await,
+        Err(rejection) => bad_request("message", rejection),
+    }
+}
+
+async fn forward<M>(actor_ref: &ActorRef<HomeAssistant>, message: M) -> (StatusCode, String)
+where
+    HomeAssistant: Message<M, Reply = ()>,
+    M: Send + 'static,
+{
+    match actor_ref.tell(message).await {
+        Ok(()) => (StatusCode::OK, String::new()),
+        Err(e) => {
+            tracing::warn!("! home assistant unavailable: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+        }
+    }
+}
+
+// Malformed JSON and missing or mistyped fields are all the automation's fault, so they're all
+// 400s with serde's description of what's wrong
+fn bad_request(route: &str, rejection: JsonRejection) -> (StatusCode, String) {
+    tracing::warn!("! rejected {} payload: {}", route, rejection.body_text());
+
+    (StatusCode::BAD_REQUEST, rejection.body_text())
+}
+
 pub struct HomeAssistantUpdate {
-    payload: serde_json::Value,
+    payload: CalendarPayload,
 }
 
 impl Message<HomeAssistantUpdate> for HomeAssistant {
@@ -90,43 +219,32 @@ impl Message<HomeAssistantUpdate> for HomeAssistant {
         message: HomeAssistantUpdate,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        // {
-        //   "uid": "abc@example.com"
-        //   "summary": "",
-        //   "all_day": false,
-        //   "start": "2025-09-02T23:30:00-05:00",
-        //   "end": "2025-09-02T23:55:00-05:00",
-        //   "location": "",
-        //   "description": "",
-        // }
-        //
-
-        tracing::info!("update {:?}", message.payload);
-
-        if message.payload["all_day"].as_bool().unwrap() {
-            return;
-        }
+        let payload = message.payload;
+
+        tracing::info!("update {:?}", payload);
+
+        // Events without a uid can only be matched on their summary and start
+        let uid = payload
+            .uid
+            .unwrap_or_else(|| format!("{}@{}", payload.summary, payload.start.value));
+
+        let broker_message = if payload.deleted {
+            crate::BrokerMessage::CalendarEventCancelled(uid)
+        } else {
+            crate::BrokerMessage::CalendarEventUpcoming(crate::CalendarEventUpcoming {
+                uid,
+                description: payload.summary,
+                start_at: payload.start.time,
+                end_at: payload.end.time,
+                all_day: payload.all_day,
+                reminder_minutes: payload.reminders,
+            })
+        };
 
         self.broker_ref
             .tell(broker::Publish {
                 topic: "calendar".parse().unwrap(),
-                message: crate::BrokerMessage::CalendarEventUpcoming(
-                    crate::CalendarEventUpcoming {
-                        description: message.payload["summary"].as_str().unwrap().to_string(),
-
-                        start_at: chrono::DateTime::<chrono::FixedOffset>::parse_from_rfc3339(
-                            message.payload["start"].as_str().unwrap(),
-                        )
-                        .unwrap()
-                        .with_timezone(&chrono::Local),
-
-                        end_at: chrono::DateTime::<chrono::FixedOffset>::parse_from_rfc3339(
-                            message.payload["end"].as_str().unwrap(),
-                        )
-                        .unwrap()
-                        .with_timezone(&chrono::Local),
-                    },
-                ),
+                message: broker_message,
             })
             .await
             .unwrap();
@@ -134,7 +252,7 @@ impl Message<HomeAssistantUpdate> for HomeAssistant {
 }
 
 pub struct HomeAssistantMessage {
-    payload: serde_json::Value,
+    payload: MessagePayload,
 }
 
 impl Message<HomeAssistantMessage> for HomeAssistant {
@@ -145,25 +263,16 @@ impl Message<HomeAssistantMessage> for HomeAssistant {
         message: HomeAssistantMessage,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        // {
-        //   "text": "Hello World!",
-        //   "effects": ["rainbow"]
-        //   "read": false
-        // }
         tracing::info!("message {:?}", message.payload);
 
         self.broker_ref
             .tell(broker::Publish {
                 topic: "message".parse().unwrap(),
                 message: crate::BrokerMessage::Message(crate::Message {
-                    text: message.payload["text"].as_str().unwrap().to_string(),
-                    effects: message.payload["effects"]
-                        .as_array()
-                        .unwrap()
-                        .iter()
-                        .map(|e| e.as_str().unwrap().to_string())
-                        .collect(),
-                    read: message.payload["read"].as_bool().unwrap_or(false),
+                    text: message.payload.text,
+                    effects: message.payload.effects,
+                    read: message.payload.read,
+                    source: "home_assistant".to_string(),
                 }),
             })
             .await
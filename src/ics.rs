@@ -0,0 +1,633 @@
+use chrono::TimeZone;
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::Deserialize;
+
+use crate::state_file;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IcsConfig {
+    // Paths or http(s)/webcal URLs
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default = "default_refresh_minutes")]
+    pub refresh_minutes: u64,
+    #[serde(default = "default_days_ahead")]
+    pub days_ahead: i64,
+    // What was published, so events deleted while the hub was down are still cancelled
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_refresh_minutes() -> u64 {
+    15
+}
+
+fn default_days_ahead() -> i64 {
+    7
+}
+
+fn default_path() -> String {
+    "ics.json".to_string()
+}
+
+// Keeps a runaway rule like FREQ=MINUTELY from flooding the schedule
+const MAXIMUM_OCCURRENCES: u16 = 500;
+
+#[derive(Debug)]
+pub enum IcsError {
+    Load(state_file::StateFileError),
+    Read(std::io::Error),
+    Fetch(reqwest::Error),
+    Parse(String),
+}
+
+impl std::fmt::Display for IcsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IcsError::Load(e) => write!(f, "{}", e),
+            IcsError::Read(e) => write!(f, "could not read: {}", e),
+            IcsError::Fetch(e) => write!(f, "could not fetch: {}", e),
+            IcsError::Parse(e) => write!(f, "could not parse: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IcsError {}
+
+// Reads the sources every `refresh_minutes` and publishes the events in the next `days_ahead`
+// days on "calendar" like Home Assistant does, with recurring events expanded into one event per
+// occurrence
+pub struct Ics {
+    config: IcsConfig,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    client: reqwest::Client,
+    // Published uids and when they end, so events that disappear can be cancelled
+    published: Vec<(String, chrono::DateTime<chrono::Local>)>,
+}
+
+impl Actor for Ics {
+    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>, IcsConfig);
+    type Error = IcsError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
+
+        let published = state_file::load(&config.path)
+            .await
+            .map_err(IcsError::Load)?;
+
+        let refresh_interval = std::time::Duration::from_secs(config.refresh_minutes.max(1) * 60);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(refresh_interval);
+
+            loop {
+                interval.tick().await;
+
+                if actor_ref.tell(Refresh).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            broker_ref,
+            client: reqwest::Client::new(),
+            published,
+        })
+    }
+}
+
+struct Refresh;
+
+impl Message<Refresh> for Ics {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Refresh,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let now = chrono::Local::now();
+        let until = now + chrono::Duration::days(self.config.days_ahead);
+
+        let mut events = vec![];
+
+        // One unreadable source skips the whole refresh, otherwise its events would be cancelled
+        for source in &self.config.sources {
+            let result = match self.read(source).await {
+                Ok(data) => parse_events(&data, now, until),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(source_events) => events.extend(source_events),
+                Err(e) => {
+                    tracing::warn!("! ics {}: {}", source, e);
+                    return;
+                }
+            }
+        }
+
+        tracing::info!("ics: {} upcoming events", events.len());
+
+        for (uid, end_at) in std::mem::take(&mut self.published) {
+            if end_at > now && !events.iter().any(|event| event.uid == uid) {
+                self.publish(crate::BrokerMessage::CalendarEventCancelled(uid))
+                    .await;
+            }
+        }
+
+        for event in events {
+            self.published.push((event.uid.clone(), event.end_at));
+            self.publish(crate::BrokerMessage::CalendarEventUpcoming(event))
+                .await;
+        }
+
+        if let Err(e) = self.save().await {
+            tracing::error!("! could not save ics to {}: {}", self.config.path, e);
+        }
+    }
+}
+
+impl Ics {
+    async fn read(&self, source: &str) -> Result<String, IcsError> {
+        if !source.starts_with("http://")
+            && !source.starts_with("https://")
+            && !source.starts_with("webcal://")
+        {
+            return tokio::fs::read_to_string(source)
+                .await
+                .map_err(IcsError::Read);
+        }
+
+        self.client
+            .get(source.replacen("webcal://", "https://", 1))
+            .timeout(std::time::Duration::from_secs(30))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(IcsError::Fetch)?
+            .text()
+            .await
+            .map_err(IcsError::Fetch)
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save(&self.config.path, &self.published).await
+    }
+
+    async fn publish(&self, message: crate::BrokerMessage) {
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "calendar".parse().unwrap(),
+                message,
+            })
+            .await
+            .unwrap();
+    }
+}
+
+type Properties = [ical::property::Property];
+
+fn parse_events(
+    data: &str,
+    now: chrono::DateTime<chrono::Local>,
+    until: chrono::DateTime<chrono::Local>,
+) -> Result<Vec<crate::CalendarEventUpcoming>, IcsError> {
+    let mut events = vec![];
+
+    for calendar in ical::IcalParser::new(data.as_bytes()) {
+        let calendar = calendar.map_err(|e| IcsError::Parse(e.to_string()))?;
+
+        // Occurrences moved or cancelled on their own replace the ones the rule generates
+        let overridden: Vec<(String, chrono::DateTime<chrono::Local>)> = calendar
+            .events
+            .iter()
+            .filter_map(|event| {
+                let recurrence_id = parse_time(property(&event.properties, "RECURRENCE-ID")?)?;
+
+                Some((
+                    value(&event.properties, "UID")?.to_string(),
+                    recurrence_id.0.with_timezone(&chrono::Local),
+                ))
+            })
+            .collect();
+
+        for event in &calendar.events {
+            events.extend(expand(event, &overridden, now, until)?);
+        }
+    }
+
+    events.sort_by_key(|event| event.start_at);
+
+    Ok(events)
+}
+
+fn expand(
+    event: &ical::parser::ical::component::IcalEvent,
+    overridden: &[(String, chrono::DateTime<chrono::Local>)],
+    now: chrono::DateTime<chrono::Local>,
+    until: chrono::DateTime<chrono::Local>,
+) -> Result<Vec<crate::CalendarEventUpcoming>, IcsError> {
+    let properties = &event.properties;
+
+    let Some((start, all_day)) = property(properties, "DTSTART").and_then(parse_time) else {
+        return Ok(vec![]);
+    };
+
+    if value(properties, "STATUS") == Some("CANCELLED") {
+        return Ok(vec![]);
+    }
+
+    let duration = match property(properties, "DTEND").and_then(parse_time) {
+        Some((end, _)) => end - start,
+        None => match value(properties, "DURATION").and_then(parse_duration) {
+            Some(duration) => duration,
+            None if all_day => chrono::Duration::days(1),
+            None => chrono::Duration::zero(),
+        },
+    };
+
+    let description = value(properties, "SUMMARY")
+        .unwrap_or("(no title)")
+        .to_string();
+
+    let uid = match value(properties, "UID") {
+        Some(uid) => uid.to_string(),
+        None => format!("{}@{}", description, start.to_rfc3339()),
+    };
+
+    // VALARM triggers before the start become the event's reminders
+    let reminder_minutes: Vec<i64> = event
+        .alarms
+        .iter()
+        .filter_map(|alarm| value(&alarm.properties, "TRIGGER").and_then(parse_duration))
+        .filter(|trigger| *trigger <= chrono::Duration::zero())
+        .map(|trigger| -trigger.num_minutes())
+        .collect();
+
+    let occurrence =
+        |start_at: chrono::DateTime<chrono::Local>, uid: String| crate::CalendarEventUpcoming {
+            uid,
+            description: description.clone(),
+            start_at,
+            end_at: start_at + duration,
+            all_day,
+            reminder_minutes: (!reminder_minutes.is_empty()).then(|| reminder_minutes.clone()),
+        };
+
+    let in_window =
+        |start_at: chrono::DateTime<chrono::Local>| start_at + duration > now && start_at < until;
+
+    // A moved occurrence keeps the uid of the one it replaces
+    if let Some((recurrence_id, _)) = property(properties, "RECURRENCE-ID").and_then(parse_time) {
+        let start_at = start.with_timezone(&chrono::Local);
+
+        if !in_window(start_at) {
+            return Ok(vec![]);
+        }
+
+        let uid = occurrence_uid(&uid, recurrence_id.with_timezone(&chrono::Local));
+
+        return Ok(vec![occurrence(start_at, uid)]);
+    }
+
+    if property(properties, "RRULE").is_none() {
+        let start_at = start.with_timezone(&chrono::Local);
+
+        if !in_window(start_at) {
+            return Ok(vec![]);
+        }
+
+        return Ok(vec![occurrence(start_at, uid)]);
+    }
+
+    let mut rrule_set = rrule::RRuleSet::new(start);
+
+    for rrule_value in values(properties, "RRULE") {
+        let rrule = rrule_value
+            .parse::<rrule::RRule<rrule::Unvalidated>>()
+            .map_err(|e| IcsError::Parse(e.to_string()))?;
+
+        rrule_set = rrule_set.rrule(
+            rrule
+                .validate(start)
+                .map_err(|e| IcsError::Parse(e.to_string()))?,
+        );
+    }
+
+    for exdate in times(properties, "EXDATE") {
+        rrule_set = rrule_set.exdate(exdate);
+    }
+
+    for rdate in times(properties, "RDATE") {
+        rrule_set = rrule_set.rdate(rdate);
+    }
+
+    let occurrences = rrule_set
+        .after((now - duration).with_timezone(&start.timezone()))
+        .before(until.with_timezone(&start.timezone()))
+        .all(MAXIMUM_OCCURRENCES)
+        .dates;
+
+    Ok(occurrences
+        .into_iter()
+        .map(|start_at| start_at.with_timezone(&chrono::Local))
+        .filter(|start_at| {
+            !overridden
+                .iter()
+                .any(|(overridden_uid, at)| *overridden_uid == uid && at == start_at)
+        })
+        .filter(|start_at| in_window(*start_at))
+        .map(|start_at| occurrence(start_at, occurrence_uid(&uid, start_at)))
+        .collect())
+}
+
+// Every occurrence is its own event for the calendar actor
+fn occurrence_uid(uid: &str, start_at: chrono::DateTime<chrono::Local>) -> String {
+    format!("{}@{}", uid, start_at.to_rfc3339())
+}
+
+fn property<'a>(properties: &'a Properties, name: &str) -> Option<&'a ical::property::Property> {
+    properties.iter().find(|property| property.name == name)
+}
+
+fn value<'a>(properties: &'a Properties, name: &str) -> Option<&'a str> {
+    property(properties, name)?.value.as_deref()
+}
+
+fn values<'a>(properties: &'a Properties, name: &'a str) -> impl Iterator<Item = &'a str> {
+    properties
+        .iter()
+        .filter(move |property| property.name == name)
+        .filter_map(|property| property.value.as_deref())
+}
+
+// EXDATE and RDATE may list several comma separated times sharing the same parameters
+fn times<'a>(
+    properties: &'a Properties,
+    name: &'a str,
+) -> impl Iterator<Item = chrono::DateTime<rrule::Tz>> + 'a {
+    properties
+        .iter()
+        .filter(move |property| property.name == name)
+        .flat_map(|property| {
+            property
+                .value
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(move |value| parse_time_value(value, &property.params))
+                .map(|(time, _)| time)
+        })
+}
+
+fn parse_time(property: &ical::property::Property) -> Option<(chrono::DateTime<rrule::Tz>, bool)> {
+    parse_time_value(property.value.as_deref()?, &property.params)
+}
+
+// Dates are all-day and local, times are UTC with a trailing Z, in their TZID, or floating and
+// treated as local. Returns the time and whether it was a date.
+fn parse_time_value(
+    value: &str,
+    params: &Option<Vec<(String, Vec<String>)>>,
+) -> Option<(chrono::DateTime<rrule::Tz>, bool)> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y%m%d") {
+        let time = rrule::Tz::LOCAL
+            .from_local_datetime(&date.and_time(chrono::NaiveTime::MIN))
+            .earliest()?;
+
+        return Some((time, true));
+    }
+
+    if let Some(value) = value.strip_suffix('Z') {
+        let time = chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+
+        return Some((rrule::Tz::UTC.from_utc_datetime(&time), false));
+    }
+
+    let time = chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+
+    // Unknown zones, e.g. Windows names from Outlook, fall back to local time
+    let timezone = params
+        .iter()
+        .flatten()
+        .find(|(name, _)| name == "TZID")
+        .and_then(|(_, values)| values.first())
+        .and_then(|tzid| tzid.parse::<chrono_tz::Tz>().ok())
+        .map(rrule::Tz::Tz)
+        .unwrap_or(rrule::Tz::LOCAL);
+
+    Some((timezone.from_local_datetime(&time).earliest()?, false))
+}
+
+// ISO 8601 durations as used by DURATION and TRIGGER, e.g. "PT1H30M" or "-P1D"
+fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let mut duration = chrono::Duration::zero();
+    let mut number = String::new();
+
+    for character in value.strip_prefix('P')?.chars() {
+        match character {
+            '0'..='9' => number.push(character),
+            'T' => {}
+            unit => {
+                let amount = number.parse::<i64>().ok()?;
+
+                duration += match unit {
+                    'W' => chrono::Duration::weeks(amount),
+                    'D' => chrono::Duration::days(amount),
+                    'H' => chrono::Duration::hours(amount),
+                    'M' => chrono::Duration::minutes(amount),
+                    'S' => chrono::Duration::seconds(amount),
+                    _ => return None,
+                };
+
+                number.clear();
+            }
+        }
+    }
+
+    Some(if negative { -duration } else { duration })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> chrono::DateTime<chrono::Local> {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&chrono::Local)
+    }
+
+    fn parse(events: &str) -> Vec<crate::CalendarEventUpcoming> {
+        let data = format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{}END:VCALENDAR\r\n",
+            events.replace('\n', "\r\n")
+        );
+
+        parse_events(
+            &data,
+            at("2030-01-06T00:00:00Z"),
+            at("2030-02-06T00:00:00Z"),
+        )
+        .unwrap()
+    }
+
+    fn starts(events: &[crate::CalendarEventUpcoming]) -> Vec<chrono::DateTime<chrono::Local>> {
+        events.iter().map(|event| event.start_at).collect()
+    }
+
+    #[test]
+    fn rrule_skips_exdates() {
+        let events = parse(
+            "BEGIN:VEVENT
+UID:standup
+SUMMARY:Standup
+DTSTART:20300107T090000Z
+DTEND:20300107T091500Z
+RRULE:FREQ=DAILY;COUNT=5
+EXDATE:20300109T090000Z,20300110T090000Z
+END:VEVENT
+",
+        );
+
+        assert_eq!(
+            starts(&events),
+            vec![
+                at("2030-01-07T09:00:00Z"),
+                at("2030-01-08T09:00:00Z"),
+                at("2030-01-11T09:00:00Z"),
+            ]
+        );
+        assert_eq!(events[0].uid, occurrence_uid("standup", events[0].start_at));
+        assert_eq!(events[0].end_at, at("2030-01-07T09:15:00Z"));
+        assert_eq!(events[0].description, "Standup");
+        assert!(!events[0].all_day);
+    }
+
+    #[test]
+    fn recurrence_ids_replace_occurrences() {
+        let events = parse(
+            "BEGIN:VEVENT
+UID:sync
+SUMMARY:Sync
+DTSTART:20300107T090000Z
+DURATION:PT30M
+RRULE:FREQ=DAILY;COUNT=3
+END:VEVENT
+BEGIN:VEVENT
+UID:sync
+SUMMARY:Sync (moved)
+RECURRENCE-ID:20300108T090000Z
+DTSTART:20300108T140000Z
+DURATION:PT30M
+END:VEVENT
+BEGIN:VEVENT
+UID:sync
+SUMMARY:Sync
+RECURRENCE-ID:20300109T090000Z
+DTSTART:20300109T090000Z
+STATUS:CANCELLED
+END:VEVENT
+",
+        );
+
+        assert_eq!(
+            starts(&events),
+            vec![at("2030-01-07T09:00:00Z"), at("2030-01-08T14:00:00Z")]
+        );
+        // Keeps the uid of the occurrence it moved, so the calendar updates rather than adds it
+        assert_eq!(
+            events[1].uid,
+            occurrence_uid("sync", at("2030-01-08T09:00:00Z"))
+        );
+        assert_eq!(events[1].description, "Sync (moved)");
+    }
+
+    #[test]
+    fn all_day_events_last_a_day() {
+        let events = parse(
+            "BEGIN:VEVENT
+UID:holiday
+SUMMARY:Holiday
+DTSTART;VALUE=DATE:20300107
+END:VEVENT
+BEGIN:VEVENT
+UID:past
+SUMMARY:Over
+DTSTART;VALUE=DATE:20300101
+END:VEVENT
+",
+        );
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].all_day);
+        assert_eq!(
+            events[0].start_at.date_naive(),
+            chrono::NaiveDate::from_ymd_opt(2030, 1, 7).unwrap()
+        );
+        assert_eq!(
+            events[0].end_at - events[0].start_at,
+            chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn alarms_before_the_start_become_reminders() {
+        let events = parse(
+            "BEGIN:VEVENT
+UID:review
+SUMMARY:Review
+DTSTART:20300107T090000Z
+BEGIN:VALARM
+ACTION:DISPLAY
+TRIGGER:-PT15M
+END:VALARM
+BEGIN:VALARM
+ACTION:DISPLAY
+TRIGGER:-P1D
+END:VALARM
+BEGIN:VALARM
+ACTION:DISPLAY
+TRIGGER:PT5M
+END:VALARM
+END:VEVENT
+BEGIN:VEVENT
+UID:lunch
+SUMMARY:Lunch
+DTSTART:20300107T120000Z
+END:VEVENT
+",
+        );
+
+        assert_eq!(events[0].reminder_minutes, Some(vec![15, 1440]));
+        // Without alarms the calendar's default reminders apply
+        assert_eq!(events[1].reminder_minutes, None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(
+            parse_duration("PT1H30M"),
+            Some(chrono::Duration::minutes(90))
+        );
+        assert_eq!(parse_duration("-P1D"), Some(-chrono::Duration::days(1)));
+        assert_eq!(
+            parse_duration("+P1WT5S"),
+            Some(chrono::Duration::weeks(1) + chrono::Duration::seconds(5))
+        );
+        assert_eq!(parse_duration("PT0S"), Some(chrono::Duration::zero()));
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("PT1X"), None);
+        assert_eq!(parse_duration("PTH"), None);
+    }
+}
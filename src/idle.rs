@@ -0,0 +1,308 @@
+use kameo::error::{Infallible, SendError};
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::IdleResolution;
+use crate::restarting_manager::{self, RestartingManager};
+use crate::toggl;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdleConfig {
+    #[serde(default = "default_max_entry_minutes")]
+    pub max_entry_minutes: i64,
+    #[serde(default = "default_idle_minutes")]
+    pub idle_minutes: i64,
+    // Defaults to when the ThinkInk light switches off
+    #[serde(default = "default_night_start")]
+    pub night_start: chrono::NaiveTime,
+    #[serde(default = "default_night_end")]
+    pub night_end: chrono::NaiveTime,
+}
+
+impl IdleConfig {
+    // The range wraps past midnight when it starts later in the day than it ends
+    fn is_night(&self, time: chrono::NaiveTime) -> bool {
+        if self.night_start <= self.night_end {
+            time >= self.night_start && time < self.night_end
+        } else {
+            time >= self.night_start || time < self.night_end
+        }
+    }
+}
+
+fn default_max_entry_minutes() -> i64 {
+    4 * 60
+}
+
+fn default_idle_minutes() -> i64 {
+    30
+}
+
+fn default_night_start() -> chrono::NaiveTime {
+    chrono::NaiveTime::from_hms_opt(23, 20, 0).unwrap()
+}
+
+fn default_night_end() -> chrono::NaiveTime {
+    chrono::NaiveTime::from_hms_opt(6, 0, 0).unwrap()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IdleReason {
+    TooLong,
+    Overnight,
+}
+
+struct Prompt {
+    time_entry: toggl::TimeEntry,
+    last_activity_at: chrono::DateTime<chrono::Utc>,
+}
+
+// Entries left running past `max_entry_minutes` or into the night, with nobody touching the
+// desk for `idle_minutes`, are flagged once until they're resolved or stopped
+pub struct Idle {
+    config: IdleConfig,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    toggl_manager_ref: ActorRef<RestartingManager<toggl::Toggl>>,
+    last_activity_at: chrono::DateTime<chrono::Utc>,
+    prompt: Option<Prompt>,
+}
+
+impl Actor for Idle {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        ActorRef<RestartingManager<toggl::Toggl>>,
+        IdleConfig,
+    );
+    type Error = Infallible;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, toggl_manager_ref, config) = state;
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "activity".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "toggl".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+
+                if actor_ref.tell(Tick).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            broker_ref,
+            toggl_manager_ref,
+            // Nothing is known from before a restart, so count from now
+            last_activity_at: chrono::Utc::now(),
+            prompt: None,
+        })
+    }
+}
+
+impl Message<crate::BrokerMessage> for Idle {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match message {
+            crate::BrokerMessage::Activity | crate::BrokerMessage::TimeEntryStarted(_) => {
+                self.last_activity_at = chrono::Utc::now();
+            }
+            crate::BrokerMessage::TimeEntryStopped => {
+                self.prompt = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Tick;
+
+impl Message<Tick> for Idle {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Tick,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Ok(toggl_ref) = self
+            .toggl_manager_ref
+            .ask(restarting_manager::GetChild)
+            .await
+        else {
+            return;
+        };
+
+        let Ok(Some(time_entry)) = toggl_ref.ask(toggl::GetRunningTimeEntry).await else {
+            self.prompt = None;
+            return;
+        };
+
+        if let Some(prompt) = &self.prompt {
+            if prompt.time_entry.id == time_entry.id {
+                return;
+            }
+
+            self.prompt = None;
+        }
+
+        let now = chrono::Utc::now();
+
+        if now - self.last_activity_at < chrono::Duration::minutes(self.config.idle_minutes) {
+            return;
+        }
+
+        let reason =
+            if now - time_entry.start >= chrono::Duration::minutes(self.config.max_entry_minutes) {
+                IdleReason::TooLong
+            } else if self
+                .config
+                .is_night(now.with_timezone(&chrono::Local).time())
+            {
+                IdleReason::Overnight
+            } else {
+                return;
+            };
+
+        tracing::info!(
+            "idle: {:?} running {} minutes ({:?})",
+            time_entry.description(),
+            (now - time_entry.start).num_minutes(),
+            reason
+        );
+
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "idle".parse().unwrap(),
+                message: crate::BrokerMessage::IdleTimeEntryDetected(
+                    crate::IdleTimeEntryDetected {
+                        description: time_entry.description().to_string(),
+                        started_at: time_entry.start,
+                        last_activity_at: self.last_activity_at,
+                        reason,
+                    },
+                ),
+            })
+            .await
+            .unwrap();
+
+        self.prompt = Some(Prompt {
+            time_entry,
+            last_activity_at: self.last_activity_at,
+        });
+    }
+}
+
+pub struct Resolve {
+    pub resolution: IdleResolution,
+}
+
+impl Message<Resolve> for Idle {
+    type Reply = Result<(), toggl::TogglError>;
+
+    async fn handle(
+        &mut self,
+        message: Resolve,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let prompt = self.prompt.take().ok_or(toggl::TogglError::NotFound)?;
+
+        tracing::info!("idle: {:?}", message.resolution);
+
+        // Answering counts as activity, so a kept entry waits for another idle stretch
+        self.last_activity_at = chrono::Utc::now();
+
+        let toggl_ref = self
+            .toggl_manager_ref
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|e| toggl::TogglError::Network(e.to_string()))?;
+
+        match message.resolution {
+            IdleResolution::Keep => Ok(()),
+            IdleResolution::Stop => toggl_ref
+                .ask(toggl::StopTimeEntry)
+                .await
+                .map_err(toggl_error),
+            IdleResolution::Trim => toggl_ref
+                .ask(toggl::StopTimeEntryAt {
+                    stop: prompt.last_activity_at.max(prompt.time_entry.start),
+                })
+                .await
+                .map_err(toggl_error),
+        }
+    }
+}
+
+fn toggl_error<M>(e: SendError<M, toggl::TogglError>) -> toggl::TogglError {
+    match e {
+        SendError::HandlerError(e) => e,
+        e => toggl::TogglError::Network(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> chrono::NaiveTime {
+        chrono::NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn config(night_start: chrono::NaiveTime, night_end: chrono::NaiveTime) -> IdleConfig {
+        IdleConfig {
+            max_entry_minutes: default_max_entry_minutes(),
+            idle_minutes: default_idle_minutes(),
+            night_start,
+            night_end,
+        }
+    }
+
+    #[test]
+    fn night_wrapping_past_midnight() {
+        let config = config(time(23, 20), time(6, 0));
+
+        assert!(config.is_night(time(23, 20)));
+        assert!(config.is_night(time(23, 59)));
+        assert!(config.is_night(time(0, 0)));
+        assert!(config.is_night(time(5, 59)));
+        assert!(!config.is_night(time(6, 0)));
+        assert!(!config.is_night(time(12, 0)));
+        assert!(!config.is_night(time(23, 19)));
+    }
+
+    #[test]
+    fn night_within_one_day() {
+        let config = config(time(1, 0), time(5, 0));
+
+        assert!(config.is_night(time(1, 0)));
+        assert!(config.is_night(time(4, 59)));
+        assert!(!config.is_night(time(5, 0)));
+        assert!(!config.is_night(time(0, 59)));
+        assert!(!config.is_night(time(23, 30)));
+    }
+}
@@ -0,0 +1,277 @@
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::state_file;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InboxConfig {
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+}
+
+fn default_path() -> String {
+    "inbox.json".to_string()
+}
+
+fn default_history_limit() -> usize {
+    500
+}
+
+impl InboxConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.history_limit == 0 {
+            return Err(ConfigError::Invalid(
+                "inbox".to_string(),
+                "history_limit must be at least 1".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InboxEntry {
+    pub id: u64,
+    pub text: String,
+    pub effects: Vec<String>,
+    pub source: String,
+    pub read: bool,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+    pub read_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub cleared_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl InboxEntry {
+    // What the Unicorn keeps in its own inbox
+    pub fn is_pending(&self) -> bool {
+        !self.read && self.cleared_at.is_none()
+    }
+}
+
+// Ids keep counting up after old entries are trimmed, so one is never handed out twice
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedInbox {
+    next_id: u64,
+    entries: Vec<InboxEntry>,
+}
+
+pub struct Inbox {
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    config: InboxConfig,
+    next_id: u64,
+    entries: Vec<InboxEntry>,
+}
+
+impl Actor for Inbox {
+    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>, InboxConfig);
+    type Error = state_file::StateFileError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
+
+        let SavedInbox { next_id, entries } = state_file::load(&config.path).await?;
+
+        tracing::info!("inbox loaded from {}", config.path);
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "message".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        Ok(Self {
+            broker_ref,
+            config,
+            next_id: next_id.max(1),
+            entries,
+        })
+    }
+}
+
+impl Message<crate::BrokerMessage> for Inbox {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let now = chrono::Utc::now();
+
+        match message {
+            crate::BrokerMessage::Message(message) => {
+                let id = self.next_id;
+                self.next_id += 1;
+
+                self.entries.push(InboxEntry {
+                    id,
+                    text: message.text,
+                    effects: message.effects,
+                    source: message.source,
+                    read: message.read,
+                    received_at: now,
+                    read_at: message.read.then_some(now),
+                    cleared_at: None,
+                });
+
+                trim(&mut self.entries, self.config.history_limit);
+            }
+            crate::BrokerMessage::ReadInbox => {
+                for entry in self.entries.iter_mut().filter(|entry| entry.is_pending()) {
+                    entry.read = true;
+                    entry.read_at = Some(now);
+                }
+            }
+            crate::BrokerMessage::ClearInbox => {
+                for entry in self
+                    .entries
+                    .iter_mut()
+                    .filter(|entry| entry.cleared_at.is_none())
+                {
+                    entry.cleared_at = Some(now);
+                }
+            }
+            _ => return,
+        }
+
+        if let Err(e) = self.save().await {
+            tracing::error!("! could not save inbox to {}: {}", self.config.path, e);
+        }
+
+        let pending = self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_pending())
+            .count();
+
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "inbox".parse().unwrap(),
+                message: crate::BrokerMessage::InboxUpdated(pending),
+            })
+            .await
+            .unwrap();
+    }
+}
+
+pub struct GetEntries {
+    pub pending_only: bool,
+}
+
+impl Message<GetEntries> for Inbox {
+    type Reply = Vec<InboxEntry>;
+
+    async fn handle(
+        &mut self,
+        message: GetEntries,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.entries
+            .iter()
+            .filter(|entry| !message.pending_only || entry.is_pending())
+            .cloned()
+            .collect()
+    }
+}
+
+impl Inbox {
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save_pretty(
+            &self.config.path,
+            &SavedInbox {
+                next_id: self.next_id,
+                entries: self.entries.clone(),
+            },
+        )
+        .await
+    }
+}
+
+// Read and cleared entries go first, oldest first; pending ones only once nothing else is left
+fn trim(entries: &mut Vec<InboxEntry>, limit: usize) {
+    let mut overflow = entries.len().saturating_sub(limit);
+
+    entries.retain(|entry| {
+        if overflow > 0 && !entry.is_pending() {
+            overflow -= 1;
+            false
+        } else {
+            true
+        }
+    });
+
+    entries.drain(..overflow);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u64, pending: bool) -> InboxEntry {
+        InboxEntry {
+            id,
+            text: format!("message {}", id),
+            effects: vec![],
+            source: "test".to_string(),
+            read: !pending,
+            received_at: chrono::Utc::now(),
+            read_at: None,
+            cleared_at: None,
+        }
+    }
+
+    fn ids(entries: &[InboxEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn trim_drops_read_entries_before_pending_ones() {
+        let mut entries = vec![
+            entry(1, true),
+            entry(2, false),
+            entry(3, true),
+            entry(4, false),
+        ];
+
+        trim(&mut entries, 3);
+        assert_eq!(ids(&entries), vec![1, 3, 4]);
+
+        trim(&mut entries, 2);
+        assert_eq!(ids(&entries), vec![1, 3]);
+    }
+
+    #[test]
+    fn trim_drops_the_oldest_pending_entries_last() {
+        let mut entries = vec![entry(1, true), entry(2, false), entry(3, true)];
+
+        trim(&mut entries, 1);
+        assert_eq!(ids(&entries), vec![3]);
+    }
+
+    #[test]
+    fn validate_rejects_a_zero_history_limit() {
+        let config = InboxConfig {
+            path: default_path(),
+            history_limit: 0,
+        };
+
+        assert!(config.validate().is_err());
+        assert!(
+            InboxConfig {
+                history_limit: 1,
+                ..config
+            }
+            .validate()
+            .is_ok()
+        );
+    }
+}
@@ -1,20 +1,26 @@
 use chrono::Timelike;
 use kameo::error::Infallible;
 use kameo::prelude::*;
+use kameo_actors::broker;
 
 pub struct Light {
     thinkink_ref: ActorRef<crate::thinkink::ThinkInk>,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
     light_spline: splines::Spline<f32, f32>,
+    last_value: Option<u32>,
 }
 
 pub struct Tick;
 
 impl Actor for Light {
-    type Args = (ActorRef<crate::thinkink::ThinkInk>,);
+    type Args = (
+        ActorRef<crate::thinkink::ThinkInk>,
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+    );
     type Error = Infallible;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let thinkink_ref = state.0;
+        let (thinkink_ref, broker_ref) = state;
 
         let light_spline = splines::Spline::from_vec(vec![
             splines::Key::new(0.0, 0.0, splines::Interpolation::Step(1.0)),
@@ -58,6 +64,8 @@ impl Actor for Light {
         Ok(Self {
             light_spline,
             thinkink_ref,
+            broker_ref,
+            last_value: None,
         })
     }
 }
@@ -84,6 +92,18 @@ impl Message<Tick> for Light {
             })
             .await
             .unwrap();
+
+        if self.last_value != Some(target_value) {
+            self.last_value = Some(target_value);
+
+            self.broker_ref
+                .tell(broker::Publish {
+                    topic: "light".parse().unwrap(),
+                    message: crate::BrokerMessage::LightLevelChanged(target_value),
+                })
+                .await
+                .unwrap();
+        }
     }
 }
 
@@ -1,33 +1,74 @@
+use kameo::error::SendError;
 use kameo::message::StreamMessage;
 use kameo::prelude::*;
 use kameo_actors::broker;
-
-#[cfg(feature = "pi")]
-use futures::stream::StreamExt;
-#[cfg(feature = "pi")]
-use tokio_serial::SerialPortBuilderExt;
-#[cfg(feature = "pi")]
-use tokio_util::codec::{Framed, LinesCodec};
-
+use serde::Deserialize;
+
+use crate::focus;
+use crate::idle;
+use crate::protocol::{
+    self, ErrorReason, FocusSession, IdleResolution, IdleTimeEntry, MacropadFrame, MacropadMessage,
+    MacropadRequest, ProjectOption, TagOption, TimeEntry,
+};
+use crate::restarting_manager::{self, RestartingManager};
 use crate::toggl;
+use crate::transport;
+
+pub const DEVICE: transport::Device = transport::Device {
+    name: "macropad",
+    baud_rate: 19200,
+    usb_vid: 0x239A,
+    usb_pid: 0x8108,
+    heartbeat: "h",
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Handshake {
+    Pending,
+    Complete,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MacropadConfig {
+    #[serde(flatten)]
+    pub transport: transport::TransportSettings,
+}
 
 pub struct Macropad {
-    transmit: Box<dyn crate::serial_sink::Sink>,
+    transmit: Box<dyn transport::Sink>,
     broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
-    toggl_ref: ActorRef<toggl::Toggl>,
+    toggl_manager_ref: Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+    focus_manager_ref: Option<ActorRef<RestartingManager<focus::Focus>>>,
+    idle_manager_ref: Option<ActorRef<RestartingManager<idle::Idle>>>,
+    last_activity_published_at: Option<std::time::Instant>,
+    handshake: Handshake,
 }
 
 #[derive(Debug)]
 pub struct MacropadError;
 
 impl Actor for Macropad {
-    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>,);
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        transport::TransportConfig,
+        Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+        Option<ActorRef<RestartingManager<focus::Focus>>>,
+        Option<ActorRef<RestartingManager<idle::Idle>>>,
+    );
     type Error = MacropadError;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let (broker_ref,) = state;
+        let (broker_ref, transport_config, toggl_manager_ref, focus_manager_ref, idle_manager_ref) =
+            state;
 
-        let toggl_ref = crate::toggl::Toggl::spawn_link(&actor_ref, (broker_ref.clone(),)).await;
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "idle".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
 
         let tick_actor_ref = actor_ref.clone();
 
@@ -45,36 +86,25 @@ impl Actor for Macropad {
             }
         });
 
-        #[cfg(feature = "pi")]
-        {
-            let serial_port =
-                tokio_serial::new(std::env::var("MACROPAD_SERIAL_PATH").unwrap(), 19200)
-                    .open_native_async()
-                    .unwrap();
-            tracing::info!("serial opened");
-
-            let device = Framed::new(serial_port, LinesCodec::new());
-            let (transmit, receive) = device.split::<String>();
-
-            actor_ref.attach_stream(receive, (), ());
-
-            let serial_sink = crate::serial_sink::SerialSink::new(transmit);
-
-            Ok(Self {
-                transmit: Box::new(serial_sink),
-                broker_ref,
-                toggl_ref,
-            })
-        }
-
-        #[cfg(not(feature = "pi"))]
-        {
-            Ok(Self {
-                transmit: Box::new(crate::serial_sink::DummySink),
-                broker_ref,
-                toggl_ref,
-            })
-        }
+        // The Macropad answers with its own hello, nothing else is accepted until it does
+        let greeting = serde_json::to_string(&MacropadMessage::Hello {
+            version: protocol::PROTOCOL_VERSION,
+        })
+        .unwrap();
+
+        let link = transport_config.connect(Some(greeting));
+
+        actor_ref.attach_stream(link.receive, (), ());
+
+        Ok(Self {
+            transmit: link.transmit,
+            broker_ref,
+            toggl_manager_ref,
+            focus_manager_ref,
+            idle_manager_ref,
+            last_activity_published_at: None,
+            handshake: Handshake::Pending,
+        })
     }
 }
 
@@ -88,66 +118,88 @@ impl Message<Tick> for Macropad {
         _message: Tick,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        self.send_message(serde_json::json!({ "kind": "heartbeat" }))
-            .await;
+        self.send_message(MacropadMessage::Heartbeat).await;
     }
 }
 
-impl Message<StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (), ()>>
-    for Macropad
-{
+impl Message<StreamMessage<transport::Received, (), ()>> for Macropad {
     type Reply = ();
 
     async fn handle(
         &mut self,
-        message: StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (), ()>,
+        message: StreamMessage<transport::Received, (), ()>,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         match message {
-            StreamMessage::Next(Ok(line)) => {
-                if line == "h" {
+            // Possibly a different Macropad, or the same one with new code
+            StreamMessage::Next(transport::Received::Connected) => {
+                self.handshake = Handshake::Pending;
+            }
+            StreamMessage::Next(transport::Received::Line(line)) => {
+                let frame = match line.parse::<MacropadFrame>() {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        tracing::warn!("! rejected frame {:?}: {}", line, e);
+                        self.send_error_message(e.reason()).await;
+                        return;
+                    }
+                };
+
+                if let MacropadFrame::Request(MacropadRequest::Hello { version }) = frame {
+                    self.complete_handshake(version).await;
                     return;
                 }
 
-                tracing::info!("<- {}", line);
-
-                if line.starts_with("p") {
-                    return;
+                match self.handshake {
+                    Handshake::Complete => {}
+                    _ if frame == MacropadFrame::Heartbeat => return,
+                    Handshake::Pending => {
+                        tracing::warn!("! ignored frame before handshake: {:?}", line);
+                        return;
+                    }
+                    Handshake::Rejected => {
+                        tracing::warn!("! ignored frame from incompatible macropad: {:?}", line);
+                        self.send_error_message(ErrorReason::UnsupportedVersion)
+                            .await;
+                        return;
+                    }
                 }
 
-                if line.starts_with("x") {
-                    let value = line["x".len()..].parse::<f32>().unwrap() / 32768.0;
-
-                    self.broker_ref
-                        .tell(broker::Publish {
-                            topic: "servo".parse().unwrap(),
-                            message: crate::BrokerMessage::ServoX((300.0 + value * 100.0) as u32),
-                        })
-                        .await
-                        .unwrap();
-
-                    return;
+                if frame != MacropadFrame::Heartbeat {
+                    tracing::info!("<- {}", line);
+                    self.publish_activity().await;
                 }
 
-                if line.starts_with("y") {
-                    let value = line["y".len()..].parse::<f32>().unwrap() / 32768.0;
-
-                    self.broker_ref
-                        .tell(broker::Publish {
-                            topic: "servo".parse().unwrap(),
-                            message: crate::BrokerMessage::ServoY((300.0 + value * 100.0) as u32),
-                        })
-                        .await
-                        .unwrap();
-
-                    return;
+                match frame {
+                    MacropadFrame::Heartbeat | MacropadFrame::Potentiometer(_) => {}
+                    MacropadFrame::AxisX(value) => {
+                        let value = value / 32768.0;
+
+                        self.broker_ref
+                            .tell(broker::Publish {
+                                topic: "servo".parse().unwrap(),
+                                message: crate::BrokerMessage::ServoX(
+                                    (300.0 + value * 100.0) as u32,
+                                ),
+                            })
+                            .await
+                            .unwrap();
+                    }
+                    MacropadFrame::AxisY(value) => {
+                        let value = value / 32768.0;
+
+                        self.broker_ref
+                            .tell(broker::Publish {
+                                topic: "servo".parse().unwrap(),
+                                message: crate::BrokerMessage::ServoY(
+                                    (300.0 + value * 100.0) as u32,
+                                ),
+                            })
+                            .await
+                            .unwrap();
+                    }
+                    MacropadFrame::Request(request) => self.process_request(request).await,
                 }
-
-                let message: serde_json::Value = serde_json::from_str(&line).unwrap();
-                self.process_command(message).await;
-            }
-            StreamMessage::Next(Err(e)) => {
-                tracing::error!("! serial error: {}", e);
             }
             StreamMessage::Started(_) => {
                 tracing::info!("= serial started");
@@ -159,172 +211,469 @@ impl Message<StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (
     }
 }
 
+impl Message<crate::BrokerMessage> for Macropad {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let crate::BrokerMessage::IdleTimeEntryDetected(idle) = message {
+            let now = chrono::Utc::now();
+
+            self.send_message(MacropadMessage::IdleTimeEntry {
+                idle: IdleTimeEntry {
+                    description: idle.description,
+                    running_minutes: (now - idle.started_at).num_minutes(),
+                    idle_minutes: (now - idle.last_activity_at).num_minutes(),
+                },
+            })
+            .await;
+        }
+    }
+}
+
 impl Macropad {
-    async fn process_command(&mut self, message: serde_json::Value) {
-        let kind = message["kind"].as_str().unwrap();
-
-        let result = match kind {
-            "getTimeEntries" => self.send_time_entries().await,
-            "startTimeEntry" => self.start_time_entry(message).await,
-            "stopTimeEntry" => self.stop_time_entry().await,
-            "continueTimeEntry" => self.continue_time_entry().await,
-            "adjustTime" => self.adjust_time(message).await,
-            "readInbox" => self.read_inbox().await,
-            "clearInbox" => self.clear_inbox().await,
-            "startClock" => self.start_clock().await,
-            "startCountdown" => self.start_countdown(message).await,
-            "cancelAnimation" => self.cancel_animation().await,
-            _ => panic!("Unknown message kind: {}", kind),
+    async fn process_request(&mut self, request: MacropadRequest) {
+        let result = match request {
+            // Handled before any other frame
+            MacropadRequest::Hello { .. } => return,
+            MacropadRequest::GetTimeEntries => self.send_time_entries().await,
+            MacropadRequest::GetProjects => self.send_projects().await,
+            MacropadRequest::GetTags => self.send_tags().await,
+            MacropadRequest::StartTimeEntry { time_entry } => {
+                self.start_time_entry(time_entry).await
+            }
+            MacropadRequest::StopTimeEntry => self.stop_time_entry().await,
+            MacropadRequest::ContinueTimeEntry => self.continue_time_entry().await,
+            MacropadRequest::AdjustTime { minutes } => self.adjust_time(minutes).await,
+            MacropadRequest::ReadInbox => self.read_inbox().await,
+            MacropadRequest::ClearInbox => self.clear_inbox().await,
+            MacropadRequest::StartClock => self.start_clock().await,
+            MacropadRequest::StartCountdown { minutes } => self.start_countdown(minutes).await,
+            MacropadRequest::CancelAnimation => self.cancel_animation().await,
+            MacropadRequest::StartFocusSession { session } => {
+                self.start_focus_session(session).await
+            }
+            MacropadRequest::StopFocusSession => self.stop_focus_session().await,
+            MacropadRequest::ResolveIdleTimeEntry { resolution } => {
+                self.resolve_idle_time_entry(resolution).await
+            }
         };
 
         match result {
             Ok(_) => self.send_success_message().await,
-            Err(_) => self.send_error_message().await,
+            Err(reason) => self.send_error_message(reason).await,
         }
     }
 
-    async fn read_inbox(&mut self) -> Result<(), ()> {
+    // Answers the hub's greeting, or is sent on its own when the Macropad boots. Not answered, so
+    // the two sides don't keep greeting each other.
+    async fn complete_handshake(&mut self, version: u32) {
+        match protocol::check_version(version) {
+            Ok(()) => {
+                tracing::info!("= handshake complete, version {}", version);
+                self.handshake = Handshake::Complete;
+            }
+            Err(e) => {
+                tracing::warn!("! handshake failed: {}", e);
+                self.handshake = Handshake::Rejected;
+                self.send_error_message(e.reason()).await;
+            }
+        }
+    }
+
+    async fn read_inbox(&mut self) -> Result<(), ErrorReason> {
         self.broker_ref
             .tell(broker::Publish {
                 topic: "message".parse().unwrap(),
                 message: crate::BrokerMessage::ReadInbox,
             })
             .await
-            .map_err(|_| ())?;
+            .map_err(|_| ErrorReason::RequestFailed)?;
 
         Ok(())
     }
 
-    async fn clear_inbox(&mut self) -> Result<(), ()> {
+    async fn clear_inbox(&mut self) -> Result<(), ErrorReason> {
         self.broker_ref
             .tell(broker::Publish {
                 topic: "message".parse().unwrap(),
                 message: crate::BrokerMessage::ClearInbox,
             })
             .await
-            .map_err(|_| ())?;
+            .map_err(|_| ErrorReason::RequestFailed)?;
 
         Ok(())
     }
 
-    async fn start_clock(&mut self) -> Result<(), ()> {
+    async fn start_clock(&mut self) -> Result<(), ErrorReason> {
         self.broker_ref
             .tell(broker::Publish {
                 topic: "clock".parse().unwrap(),
                 message: crate::BrokerMessage::StartClock,
             })
             .await
-            .map_err(|_| ())?;
+            .map_err(|_| ErrorReason::RequestFailed)?;
 
         Ok(())
     }
 
-    async fn send_time_entries(&mut self) -> Result<(), ()> {
-        let result = self
-            .toggl_ref
+    async fn send_time_entries(&mut self) -> Result<(), ErrorReason> {
+        let time_entries = self
+            .toggl()
+            .await?
             .ask(toggl::GetTimeEntries)
             .await
-            .map_err(|_| ())?;
+            .map_err(toggl_error)?;
 
-        let descriptions: std::collections::HashSet<&str> = result
-            .as_array()
-            .unwrap()
+        let options: std::collections::HashSet<TimeEntry> = time_entries
             .iter()
-            .map(|entry| entry["description"].as_str().unwrap())
+            .filter(|time_entry| !time_entry.description().is_empty())
+            .map(|time_entry| TimeEntry {
+                description: time_entry.description().to_string(),
+                project_id: time_entry.project_id,
+                tag_ids: time_entry.tag_ids.clone().unwrap_or_default(),
+            })
             .collect();
 
-        for description in descriptions {
-            self.send_message(serde_json::json!({
-                "kind": "timeEntry",
-                "timeEntry": {
-                    "description": description,
-                }
-            }))
+        for time_entry in options {
+            self.send_message(MacropadMessage::TimeEntry { time_entry })
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn send_projects(&mut self) -> Result<(), ErrorReason> {
+        let projects = self
+            .toggl()
+            .await?
+            .ask(toggl::GetProjects)
+            .await
+            .map_err(toggl_error)?;
+
+        for project in projects {
+            self.send_message(MacropadMessage::Project {
+                project: ProjectOption {
+                    id: project.id,
+                    name: project.name,
+                },
+            })
+            .await;
+        }
+
+        Ok(())
+    }
+
+    async fn send_tags(&mut self) -> Result<(), ErrorReason> {
+        let tags = self
+            .toggl()
+            .await?
+            .ask(toggl::GetTags)
+            .await
+            .map_err(toggl_error)?;
+
+        for tag in tags {
+            self.send_message(MacropadMessage::Tag {
+                tag: TagOption {
+                    id: tag.id,
+                    name: tag.name,
+                },
+            })
             .await;
         }
 
         Ok(())
     }
 
-    async fn start_time_entry(&mut self, message: serde_json::Value) -> Result<(), ()> {
-        self.toggl_ref
+    async fn start_time_entry(&mut self, time_entry: TimeEntry) -> Result<(), ErrorReason> {
+        self.toggl()
+            .await?
             .ask(toggl::StartTimeEntry {
-                description: message["timeEntry"]["description"]
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
+                details: toggl::TimeEntryDetails {
+                    description: time_entry.description,
+                    project_id: time_entry.project_id,
+                    tag_ids: time_entry.tag_ids,
+                },
             })
             .await
-            .map_err(|_| ())?;
+            .map_err(toggl_error)?;
 
         Ok(())
     }
 
-    async fn stop_time_entry(&mut self) -> Result<(), ()> {
-        self.toggl_ref
+    async fn stop_time_entry(&mut self) -> Result<(), ErrorReason> {
+        self.toggl()
+            .await?
             .ask(toggl::StopTimeEntry)
             .await
-            .map_err(|_| ())?;
+            .map_err(toggl_error)?;
 
         Ok(())
     }
 
-    async fn continue_time_entry(&mut self) -> Result<(), ()> {
-        self.toggl_ref
+    async fn continue_time_entry(&mut self) -> Result<(), ErrorReason> {
+        self.toggl()
+            .await?
             .ask(toggl::ContinueTimeEntry)
             .await
-            .map_err(|_| ())?;
+            .map_err(toggl_error)?;
 
         Ok(())
     }
 
-    async fn adjust_time(&mut self, message: serde_json::Value) -> Result<(), ()> {
-        self.toggl_ref
-            .ask(toggl::AdjustTime {
-                minutes: message["minutes"].as_i64().unwrap(),
-            })
+    async fn adjust_time(&mut self, minutes: i64) -> Result<(), ErrorReason> {
+        self.toggl()
+            .await?
+            .ask(toggl::AdjustTime { minutes })
             .await
-            .map_err(|_| ())?;
+            .map_err(toggl_error)?;
 
         Ok(())
     }
 
-    async fn start_countdown(&mut self, message: serde_json::Value) -> Result<(), ()> {
+    async fn start_countdown(&mut self, minutes: i64) -> Result<(), ErrorReason> {
         self.broker_ref
             .tell(broker::Publish {
                 topic: "countdown".parse().unwrap(),
-                message: crate::BrokerMessage::StartCountdown(message["minutes"].as_i64().unwrap()),
+                message: crate::BrokerMessage::StartCountdown(minutes),
             })
             .await
-            .map_err(|_| ())?;
+            .map_err(|_| ErrorReason::RequestFailed)?;
 
         Ok(())
     }
 
-    async fn cancel_animation(&mut self) -> Result<(), ()> {
+    async fn cancel_animation(&mut self) -> Result<(), ErrorReason> {
         self.broker_ref
             .tell(broker::Publish {
                 topic: "countdown".parse().unwrap(),
                 message: crate::BrokerMessage::CancelAnimation,
             })
             .await
-            .map_err(|_| ())?;
+            .map_err(|_| ErrorReason::RequestFailed)?;
 
         Ok(())
     }
 
+    async fn start_focus_session(&mut self, session: FocusSession) -> Result<(), ErrorReason> {
+        self.focus()
+            .await?
+            .ask(focus::StartSession {
+                details: session
+                    .time_entry
+                    .map(|time_entry| toggl::TimeEntryDetails {
+                        description: time_entry.description,
+                        project_id: time_entry.project_id,
+                        tag_ids: time_entry.tag_ids,
+                    }),
+                work_minutes: session.work_minutes,
+                break_minutes: session.break_minutes,
+                rounds: session.rounds,
+            })
+            .await
+            .map_err(focus_error)?;
+
+        Ok(())
+    }
+
+    async fn stop_focus_session(&mut self) -> Result<(), ErrorReason> {
+        self.focus()
+            .await?
+            .ask(focus::StopSession)
+            .await
+            .map_err(focus_error)?;
+
+        Ok(())
+    }
+
+    async fn resolve_idle_time_entry(
+        &mut self,
+        resolution: IdleResolution,
+    ) -> Result<(), ErrorReason> {
+        self.idle_manager_ref
+            .as_ref()
+            .ok_or(ErrorReason::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ErrorReason::Unavailable)?
+            .ask(idle::Resolve { resolution })
+            .await
+            .map_err(toggl_error)?;
+
+        Ok(())
+    }
+
+    // Key presses and knob turns mean someone is at the desk; published at most once a minute
+    async fn publish_activity(&mut self) {
+        if let Some(at) = self.last_activity_published_at
+            && at.elapsed() < std::time::Duration::from_secs(60)
+        {
+            return;
+        }
+
+        self.last_activity_published_at = Some(std::time::Instant::now());
+
+        if let Err(e) = self
+            .broker_ref
+            .tell(broker::Publish {
+                topic: "activity".parse().unwrap(),
+                message: crate::BrokerMessage::Activity,
+            })
+            .await
+        {
+            tracing::warn!("! could not publish activity: {}", e);
+        }
+    }
+
+    async fn focus(&mut self) -> Result<ActorRef<focus::Focus>, ErrorReason> {
+        self.focus_manager_ref
+            .as_ref()
+            .ok_or(ErrorReason::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ErrorReason::Unavailable)
+    }
+
+    async fn toggl(&mut self) -> Result<ActorRef<toggl::Toggl>, ErrorReason> {
+        self.toggl_manager_ref
+            .as_ref()
+            .ok_or(ErrorReason::Unavailable)?
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|_| ErrorReason::Unavailable)
+    }
+
     async fn send_success_message(&mut self) {
-        let reply = serde_json::json!({ "kind": "success" });
-        self.send_message(reply).await;
+        self.send_message(MacropadMessage::Success).await;
     }
 
-    async fn send_error_message(&mut self) {
-        let reply = serde_json::json!({ "kind": "error" });
-        self.send_message(reply).await;
+    async fn send_error_message(&mut self, reason: ErrorReason) {
+        self.send_message(MacropadMessage::Error { reason }).await;
     }
 
-    async fn send_message(&mut self, message: serde_json::Value) {
+    async fn send_message(&mut self, message: MacropadMessage) {
         tracing::info!("-> {:?}", message);
-        self.transmit.send(message.to_string()).await.unwrap();
+        self.transmit
+            .send(serde_json::to_string(&message).unwrap())
+            .await
+            .unwrap();
+    }
+}
+
+fn toggl_error<M>(e: SendError<M, toggl::TogglError>) -> ErrorReason {
+    match e {
+        SendError::HandlerError(e) => {
+            tracing::warn!("! toggl failed: {}", e);
+            e.reason()
+        }
+        _ => ErrorReason::Unavailable,
+    }
+}
+
+fn focus_error<M>(e: SendError<M, focus::SessionError>) -> ErrorReason {
+    match e {
+        SendError::HandlerError(e) => {
+            tracing::warn!("! focus failed: {}", e);
+            e.reason()
+        }
+        _ => ErrorReason::Unavailable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    use super::*;
+
+    // Reads what the hub sent, skipping heartbeats
+    async fn receive(device: &mut Framed<tokio::io::DuplexStream, LinesCodec>) -> MacropadMessage {
+        loop {
+            let line = tokio::time::timeout(std::time::Duration::from_secs(5), device.next())
+                .await
+                .expect("no message from the hub")
+                .unwrap()
+                .unwrap();
+
+            let message = serde_json::from_str(&line).unwrap();
+
+            if message != MacropadMessage::Heartbeat {
+                return message;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_wait_for_the_handshake() {
+        let broker_ref = broker::Broker::spawn(broker::Broker::new(
+            kameo_actors::DeliveryStrategy::Guaranteed,
+        ));
+
+        let (transport_config, device) = transport::TransportConfig::channel();
+        let mut device = Framed::new(device, LinesCodec::new());
+
+        let _macropad_ref = Macropad::spawn((broker_ref, transport_config, None, None, None));
+
+        assert_eq!(
+            receive(&mut device).await,
+            MacropadMessage::Hello {
+                version: protocol::PROTOCOL_VERSION
+            }
+        );
+
+        // Ignored until the Macropad has answered
+        device
+            .send(r#"{"kind":"getProjects"}"#.to_string())
+            .await
+            .unwrap();
+
+        device
+            .send(r#"{"kind":"hello","version":999}"#.to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            receive(&mut device).await,
+            MacropadMessage::Error {
+                reason: ErrorReason::UnsupportedVersion
+            }
+        );
+
+        device
+            .send(r#"{"kind":"getProjects"}"#.to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            receive(&mut device).await,
+            MacropadMessage::Error {
+                reason: ErrorReason::UnsupportedVersion
+            }
+        );
+
+        device
+            .send(format!(
+                r#"{{"kind":"hello","version":{}}}"#,
+                protocol::PROTOCOL_VERSION
+            ))
+            .await
+            .unwrap();
+
+        device
+            .send(r#"{"kind":"getProjects"}"#.to_string())
+            .await
+            .unwrap();
+
+        // Toggl isn't configured
+        assert_eq!(
+            receive(&mut device).await,
+            MacropadMessage::Error {
+                reason: ErrorReason::Unavailable
+            }
+        );
     }
 }
@@ -1,29 +1,55 @@
 use kameo::actor::Actor;
 use kameo::prelude::*;
 use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
 use tracing_subscriber::EnvFilter;
 
+mod alerts;
+mod api;
 mod apps;
 mod backlight;
+mod calendar;
 mod circuit_playground;
+mod config;
+mod desk_state;
+mod fireworks;
+mod focus;
+mod history;
 mod home_assistant;
+mod ics;
+mod idle;
+mod inbox;
 mod light;
 mod macropad;
+mod meetings;
+mod metrics;
+mod mqtt;
+mod mqtt_bridge;
+mod protocol;
 mod raylib_manager;
 mod restarting_manager;
-mod serial_sink;
+mod state_file;
 mod thinkink;
 mod toggl;
+mod transport;
 mod unicorn;
 mod urban;
-mod fireworks;
+mod watering;
+mod webhook;
 
-#[derive(Debug, Clone)]
+// Mirrored over MQTT as e.g. `{"type": "servoX", "data": 90}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
 pub enum BrokerMessage {
     TimeEntryStarted(TimeEntryStarted),
     TimeEntryStopped,
     TimeEntryTimeUpdated(TimeEntryTimeUpdated),
     CalendarEventUpcoming(CalendarEventUpcoming),
+    CalendarEventCancelled(String),
+    CalendarReminder(CalendarReminder),
+    NextCalendarEvent(Option<CalendarEventUpcoming>),
+    CalendarEventStarted(CalendarEventUpcoming),
+    CalendarEventEnded(CalendarEventUpcoming),
     StartCountdown(i64),
     CancelAnimation,
     StartTimestampCountdown(i64),
@@ -35,34 +61,111 @@ pub enum BrokerMessage {
     ServoY(u32),
     StartFireworks,
     StopFireworks,
+    FocusPhaseStarted(FocusPhaseStarted),
+    FocusSessionEnded,
+    Activity,
+    IdleTimeEntryDetected(IdleTimeEntryDetected),
+    InboxUpdated(usize),
+    SensorReadings(SensorReadings),
+    BacklightChanged(bool),
+    LightLevelChanged(u32),
+    Metrics(Vec<metrics::Point>),
+    AlertRaised(Alert),
+    AlertCleared(String),
+    // A display that just started asks for the alerts that are still raised
+    AlertsRequested,
+    PlantWatered(PlantWatered),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Message {
     pub text: String,
     pub effects: Vec<String>,
     pub read: bool,
+    pub source: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TimeEntryStarted {
     pub description: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusPhaseStarted {
+    pub phase: focus::Phase,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdleTimeEntryDetected {
+    pub description: String,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub last_activity_at: chrono::DateTime<chrono::Utc>,
+    pub reason: idle::IdleReason,
+}
+
+// Keyed by reading name, e.g. "temperature" from the "urban" source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SensorReadings {
+    pub source: String,
+    pub readings: std::collections::BTreeMap<String, f64>,
+    // When the sensor took them, which can be well before they reach the hub
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+// Named after the rule that raised it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub name: String,
+    pub series: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlantWatered {
+    pub name: String,
+    pub moisture: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TimeEntryTimeUpdated {
     pub minutes: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CalendarEventUpcoming {
+    pub uid: String,
     pub description: String,
     pub start_at: chrono::DateTime<chrono::Local>,
     pub end_at: chrono::DateTime<chrono::Local>,
+    pub all_day: bool,
+    // Falls back to the [calendar] section
+    pub reminder_minutes: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarReminder {
+    pub event: CalendarEventUpcoming,
+    pub minutes_before: i64,
 }
 
 pub enum RaylibRequest {
-    RenderThinkInkImage,
+    RenderThinkInkImage(
+        apps::thinkink_image::WeatherConfig,
+        Option<CalendarEventUpcoming>,
+        Vec<Alert>,
+    ),
+    RenderTimeSummaryImage(apps::thinkink_image::TimeSummary),
 }
 
 pub enum RaylibResponse {
@@ -71,7 +174,11 @@ pub enum RaylibResponse {
 
 fn main() {
     tracing_subscriber::fmt()
-        .with_env_filter("kameo=trace,reqwest=trace,info".parse::<EnvFilter>().unwrap())
+        .with_env_filter(
+            "kameo=trace,reqwest=trace,info"
+                .parse::<EnvFilter>()
+                .unwrap(),
+        )
         .without_time()
         .with_target(true)
         .init();
@@ -98,8 +205,9 @@ fn main() {
 }
 
 macro_rules! restarting {
-    ($type:ty, ($($var:ident),* $(,)?)) => {
+    ($supervisor:expr, $name:expr, $type:ty, ($($var:ident),* $(,)?)) => {
         restarting_manager::RestartingManager::<$type>::spawn((
+            $supervisor.child($name),
             Box::new({
                 $( let $var = $var.clone(); )*
                 move || ( $( $var.clone(), )* )
@@ -124,8 +232,182 @@ async fn tokio_main(
     raylib_transmit: tokio::sync::mpsc::Sender<RaylibRequest>,
     raylib_manager_receive: tokio::sync::mpsc::Receiver<RaylibResponse>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::Config::load()?;
+
+    let swarm_config: config::SwarmConfig = config.required("swarm")?;
+    let restart_config: restarting_manager::RestartConfig = config.required("restart")?;
+
+    let backlight_enabled = config.enabled("backlight");
+    let fireworks_enabled = config.enabled("fireworks");
+
+    let macropad_transport = match config.section::<macropad::MacropadConfig>("macropad")? {
+        Some(macropad_config) => Some(transport::TransportConfig::resolve(
+            &macropad_config.transport,
+            &macropad::DEVICE,
+        )?),
+        None => None,
+    };
+
+    let api_config = config.section::<api::ApiConfig>("api")?;
+
+    if let Some(api_config) = &api_config {
+        webhook::validate_endpoint(
+            "api",
+            "auth",
+            api_config.listen_address,
+            Some(&api_config.auth),
+        )?;
+    }
+
+    let focus_config = config.section::<focus::FocusConfig>("focus")?;
+
+    if let Some(focus_config) = &focus_config {
+        focus_config.validate()?;
+    }
+
+    let idle_config = config.section::<idle::IdleConfig>("idle")?;
+
+    let meetings_args = match config.section::<meetings::MeetingsConfig>("meetings")? {
+        Some(meetings_config) if !meetings_config.rules.is_empty() => Some((
+            meetings::Rule::compile(&meetings_config.rules)?,
+            meetings_config.path,
+        )),
+        _ => None,
+    };
+
+    let alerts_args = match config.section::<alerts::AlertsConfig>("alerts")? {
+        Some(alerts_config) if !alerts_config.rules.is_empty() => Some((
+            alerts::Rule::compile(&alerts_config.rules)?,
+            alerts_config.path,
+        )),
+        _ => None,
+    };
+
+    let thinkink_args = match config.section::<thinkink::ThinkInkConfig>("thinkink")? {
+        Some(thinkink_config) => Some((
+            transport::TransportConfig::resolve(&thinkink_config.transport, &thinkink::DEVICE)?,
+            config.required::<apps::thinkink_image::WeatherConfig>("weather")?,
+            thinkink_config.panel,
+        )),
+        None => None,
+    };
+
+    let time_summary_enabled = matches!(
+        thinkink_args,
+        Some((_, _, thinkink::ThinkInkPanel::TimeSummary))
+    );
+
+    let toggl_config = if macropad_transport.is_some()
+        || api_config.is_some()
+        || focus_config.is_some()
+        || meetings_args.is_some()
+        || time_summary_enabled
+    {
+        config.section::<toggl::TogglConfig>("toggl")?
+    } else {
+        None
+    };
+
+    let circuit_playground_transport = match config
+        .section::<circuit_playground::CircuitPlaygroundConfig>("circuit_playground")?
+    {
+        Some(circuit_playground_config) => Some(transport::TransportConfig::resolve(
+            &circuit_playground_config.transport,
+            &circuit_playground::DEVICE,
+        )?),
+        None => None,
+    };
+
+    let unicorn_config = config.section::<unicorn::UnicornConfig>("unicorn")?;
+
+    let inbox_config = config.section::<inbox::InboxConfig>("inbox")?;
+
+    if let Some(inbox_config) = &inbox_config {
+        inbox_config.validate()?;
+    }
+
+    let calendar_config = config.section::<calendar::CalendarConfig>("calendar")?;
+
+    let ics_config = config.section::<ics::IcsConfig>("ics")?;
+
+    // Home Assistant discovery and the bridge need a broker to talk to
+    let mqtt_config = config
+        .section::<mqtt::MqttConfig>("mqtt")?
+        .filter(|mqtt_config| mqtt_config.host.is_some());
+
+    let desk_state_config = match &mqtt_config {
+        Some(_) => config.section::<desk_state::DeskStateConfig>("desk_state")?,
+        None => None,
+    };
+
+    let mqtt_bridge_config = match &mqtt_config {
+        Some(_) => config
+            .section::<mqtt_bridge::MqttBridgeConfig>("mqtt_bridge")?
+            .filter(|mqtt_bridge_config| {
+                !mqtt_bridge_config.publish.is_empty() || !mqtt_bridge_config.subscribe.is_empty()
+            }),
+        None => None,
+    };
+
+    if let Some(mqtt_bridge_config) = &mqtt_bridge_config {
+        mqtt_bridge_config.validate()?;
+    }
+
+    let home_assistant_config =
+        config.section::<home_assistant::HomeAssistantConfig>("home_assistant")?;
+
+    if let Some(home_assistant_config) = &home_assistant_config {
+        webhook::validate(
+            "home_assistant",
+            home_assistant_config.listen_address,
+            &home_assistant_config.auth,
+            home_assistant::ENDPOINTS,
+        )?;
+    }
+
+    let urban_config = config.section::<urban::UrbanConfig>("urban")?;
+
+    if let Some(urban_config) = &urban_config {
+        webhook::validate(
+            "urban",
+            urban_config.listen_address,
+            &urban_config.auth,
+            urban::ENDPOINTS,
+        )?;
+    }
+
+    // Every section has defaults, but Datadog needs an API key so metrics stay off until configured
+    let metrics_args = if config.present("metrics") {
+        match config.section::<metrics::MetricsConfig>("metrics")? {
+            Some(metrics_config) => {
+                metrics_config.validate()?;
+                let backend_config = metrics_config.backend(&config)?;
+                Some((metrics_config, backend_config))
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    let watering_config = match config.section::<watering::WateringConfig>("watering")? {
+        Some(watering_config)
+            if circuit_playground_transport.is_some() && !watering_config.plants.is_empty() =>
+        {
+            watering::PlantConfig::validate(&watering_config.plants)?;
+            Some(watering_config)
+        }
+        _ => None,
+    };
+
+    let history_config = if circuit_playground_transport.is_some() || urban_config.is_some() {
+        config.section::<history::HistoryConfig>("history")?
+    } else {
+        None
+    };
+
     ActorSwarm::bootstrap()?
-        .listen_on("/ip4/0.0.0.0/udp/8020/quic-v1".parse()?)
+        .listen_on(swarm_config.listen_address.parse()?)
         .await?;
 
     let broker_ref = broker::Broker::spawn(broker::Broker::new(
@@ -135,22 +417,279 @@ async fn tokio_main(
     let raylib_manager_ref =
         raylib_manager::RaylibManager::spawn((raylib_transmit.clone(), raylib_manager_receive));
 
-    let actor_refs: Vec<Box<dyn WaitableActorRef>> = vec![
+    let supervisor = restarting_manager::Supervisor::new(restart_config, broker_ref.clone());
+
+    let mut actor_refs: Vec<Box<dyn WaitableActorRef>> = vec![
         Box::new(broker_ref.clone()),
         Box::new(raylib_manager_ref.clone()),
-        Box::new(restarting!(backlight::Backlight, ())),
-        Box::new(restarting!(macropad::Macropad, (broker_ref,))),
-        Box::new(restarting!(circuit_playground::CircuitPlayground, ())),
-        Box::new(restarting!(
-            thinkink::ThinkInk,
-            (broker_ref, raylib_manager_ref)
-        )),
-        Box::new(restarting!(unicorn::Unicorn, (broker_ref,))),
-        Box::new(restarting!(home_assistant::HomeAssistant, (broker_ref,))),
-        Box::new(restarting!(urban::Urban, ())),
-        Box::new(restarting!(fireworks::Fireworks, (broker_ref,))),
     ];
 
+    // Spawned first so it hears from everything else
+    if let Some((metrics_config, backend_config)) = metrics_args {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "metrics",
+            metrics::Metrics,
+            (broker_ref, metrics_config, backend_config)
+        )));
+    }
+
+    // Also before the sensors, so their first readings are kept
+    let history_manager_ref = history_config.map(|history_config| {
+        restarting!(
+            supervisor,
+            "history",
+            history::History,
+            (broker_ref, history_config)
+        )
+    });
+
+    if let Some(history_manager_ref) = &history_manager_ref {
+        actor_refs.push(Box::new(history_manager_ref.clone()));
+    }
+
+    if let Some((alert_rules, alerts_path)) = alerts_args {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "alerts",
+            alerts::Alerts,
+            (broker_ref, alert_rules, alerts_path)
+        )));
+    }
+
+    if let Some(watering_config) = watering_config {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "watering",
+            watering::Watering,
+            (broker_ref, watering_config)
+        )));
+    }
+
+    let toggl_manager_ref = toggl_config.map(|toggl_config| {
+        restarting!(
+            supervisor,
+            "toggl",
+            toggl::Toggl,
+            (broker_ref, toggl_config)
+        )
+    });
+
+    if let Some(toggl_manager_ref) = &toggl_manager_ref {
+        actor_refs.push(Box::new(toggl_manager_ref.clone()));
+    }
+
+    let inbox_manager_ref = inbox_config.map(|inbox_config| {
+        restarting!(
+            supervisor,
+            "inbox",
+            inbox::Inbox,
+            (broker_ref, inbox_config)
+        )
+    });
+
+    if let Some(inbox_manager_ref) = &inbox_manager_ref {
+        actor_refs.push(Box::new(inbox_manager_ref.clone()));
+    }
+
+    // Spawned ahead of the devices so it's usually listening by their first readings
+    if let (Some(mqtt_config), Some(desk_state_config)) = (&mqtt_config, desk_state_config) {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "desk_state",
+            desk_state::DeskState,
+            (
+                broker_ref,
+                toggl_manager_ref,
+                inbox_manager_ref,
+                mqtt_config,
+                desk_state_config
+            )
+        )));
+    }
+
+    if backlight_enabled {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "backlight",
+            backlight::Backlight,
+            (broker_ref,)
+        )));
+    }
+
+    let calendar_manager_ref = calendar_config.map(|calendar_config| {
+        restarting!(
+            supervisor,
+            "calendar",
+            calendar::Calendar,
+            (broker_ref, calendar_config)
+        )
+    });
+
+    if let Some(calendar_manager_ref) = &calendar_manager_ref {
+        actor_refs.push(Box::new(calendar_manager_ref.clone()));
+    }
+
+    // ICS events only reach the displays through the calendar schedule
+    if let Some(ics_config) = ics_config
+        && !ics_config.sources.is_empty()
+        && calendar_manager_ref.is_some()
+    {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "ics",
+            ics::Ics,
+            (broker_ref, ics_config)
+        )));
+    }
+
+    // Meetings follow the calendar schedule and drive Toggl, so they need both
+    if let (Some((meeting_rules, meetings_path)), Some(_), Some(toggl_manager_ref)) =
+        (meetings_args, &calendar_manager_ref, &toggl_manager_ref)
+    {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "meetings",
+            meetings::Meetings,
+            (broker_ref, toggl_manager_ref, meeting_rules, meetings_path)
+        )));
+    }
+
+    let focus_manager_ref = focus_config.map(|focus_config| {
+        restarting!(
+            supervisor,
+            "focus",
+            focus::Focus,
+            (broker_ref, toggl_manager_ref, focus_config)
+        )
+    });
+
+    if let Some(focus_manager_ref) = &focus_manager_ref {
+        actor_refs.push(Box::new(focus_manager_ref.clone()));
+    }
+
+    // Idle detection watches Toggl's running entry, so it has nothing to do without it
+    let idle_manager_ref = match (idle_config, &toggl_manager_ref) {
+        (Some(idle_config), Some(toggl_manager_ref)) => Some(restarting!(
+            supervisor,
+            "idle",
+            idle::Idle,
+            (broker_ref, toggl_manager_ref, idle_config)
+        )),
+        _ => None,
+    };
+
+    if let Some(idle_manager_ref) = &idle_manager_ref {
+        actor_refs.push(Box::new(idle_manager_ref.clone()));
+    }
+
+    if let Some(macropad_transport) = macropad_transport {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "macropad",
+            macropad::Macropad,
+            (
+                broker_ref,
+                macropad_transport,
+                toggl_manager_ref,
+                focus_manager_ref,
+                idle_manager_ref
+            )
+        )));
+    }
+
+    if let Some(circuit_playground_transport) = circuit_playground_transport {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "circuit_playground",
+            circuit_playground::CircuitPlayground,
+            (broker_ref, circuit_playground_transport)
+        )));
+    }
+
+    if let Some((thinkink_transport, weather_config, thinkink_panel)) = thinkink_args {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "thinkink",
+            thinkink::ThinkInk,
+            (
+                broker_ref,
+                raylib_manager_ref,
+                thinkink_transport,
+                weather_config,
+                thinkink_panel,
+                toggl_manager_ref,
+                calendar_manager_ref
+            )
+        )));
+    }
+
+    if let Some(unicorn_config) = unicorn_config {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "unicorn",
+            unicorn::Unicorn,
+            (broker_ref, inbox_manager_ref, unicorn_config)
+        )));
+    }
+
+    if let Some(home_assistant_config) = home_assistant_config {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "home_assistant",
+            home_assistant::HomeAssistant,
+            (broker_ref, home_assistant_config)
+        )));
+    }
+
+    if let Some(urban_config) = urban_config {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "urban",
+            urban::Urban,
+            (broker_ref, urban_config)
+        )));
+    }
+
+    if fireworks_enabled {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "fireworks",
+            fireworks::Fireworks,
+            (broker_ref,)
+        )));
+    }
+
+    if let (Some(mqtt_config), Some(mqtt_bridge_config)) = (&mqtt_config, mqtt_bridge_config) {
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "mqtt_bridge",
+            mqtt_bridge::MqttBridge,
+            (broker_ref, mqtt_config, mqtt_bridge_config)
+        )));
+    }
+
+    if let Some(api_config) = api_config {
+        let registry = supervisor.registry();
+
+        actor_refs.push(Box::new(restarting!(
+            supervisor,
+            "api",
+            api::Api,
+            (
+                broker_ref,
+                toggl_manager_ref,
+                inbox_manager_ref,
+                focus_manager_ref,
+                idle_manager_ref,
+                calendar_manager_ref,
+                history_manager_ref,
+                registry,
+                api_config
+            )
+        )));
+    }
+
     for actor_ref in actor_refs {
         actor_ref.wait_for_shutdown().await;
     }
@@ -164,8 +703,16 @@ fn raylib_main(
 ) {
     while let Some(request) = raylib_receive.blocking_recv() {
         match request {
-            RaylibRequest::RenderThinkInkImage => {
-                apps::thinkink_image::thinkink_image(&raylib_manager_transmit);
+            RaylibRequest::RenderThinkInkImage(weather_config, next_event, alerts) => {
+                apps::thinkink_image::thinkink_image(
+                    &raylib_manager_transmit,
+                    &weather_config,
+                    next_event.as_ref(),
+                    &alerts,
+                );
+            }
+            RaylibRequest::RenderTimeSummaryImage(summary) => {
+                apps::thinkink_image::time_summary_image(&raylib_manager_transmit, &summary);
             }
         }
     }
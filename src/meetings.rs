@@ -0,0 +1,405 @@
+use kameo::error::SendError;
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::restarting_manager::{self, RestartingManager};
+use crate::state_file;
+use crate::toggl;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MeetingsConfig {
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    // Meetings in progress and what to go back to after them, kept across restarts
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    "meetings.json".to_string()
+}
+
+#[derive(Debug)]
+pub enum MeetingsError {
+    Load(state_file::StateFileError),
+    Toggl(toggl::TogglError),
+    // The Toggl actor is restarting or didn't take the message
+    Unavailable(String),
+}
+
+impl std::fmt::Display for MeetingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeetingsError::Load(e) => write!(f, "{}", e),
+            MeetingsError::Toggl(e) => write!(f, "toggl failed: {}", e),
+            MeetingsError::Unavailable(e) => write!(f, "toggl unavailable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MeetingsError {}
+
+// Exactly one of `regex` or `keyword` (case-insensitive substring); without a description the
+// event's summary is used
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    #[serde(default)]
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub keyword: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub project_id: Option<i64>,
+    #[serde(default)]
+    pub tag_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Regex(regex::Regex),
+    Keyword(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    matcher: Matcher,
+    description: Option<String>,
+    project_id: Option<i64>,
+    tag_ids: Vec<i64>,
+}
+
+impl Rule {
+    pub fn compile(rules: &[RuleConfig]) -> Result<Vec<Self>, ConfigError> {
+        rules
+            .iter()
+            .map(|rule| {
+                let matcher = match (&rule.regex, &rule.keyword) {
+                    (Some(regex), None) => {
+                        Matcher::Regex(regex::Regex::new(regex).map_err(|e| {
+                            ConfigError::Invalid("meetings".to_string(), e.to_string())
+                        })?)
+                    }
+                    (None, Some(keyword)) => Matcher::Keyword(keyword.to_lowercase()),
+                    _ => {
+                        return Err(ConfigError::Invalid(
+                            "meetings".to_string(),
+                            "each rule needs either regex or keyword".to_string(),
+                        ));
+                    }
+                };
+
+                Ok(Self {
+                    matcher,
+                    description: rule.description.clone(),
+                    project_id: rule.project_id,
+                    tag_ids: rule.tag_ids.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn details(&self, summary: &str) -> Option<toggl::TimeEntryDetails> {
+        let matched = match &self.matcher {
+            Matcher::Regex(regex) => regex.is_match(summary),
+            Matcher::Keyword(keyword) => summary.to_lowercase().contains(keyword),
+        };
+
+        matched.then(|| toggl::TimeEntryDetails {
+            description: self
+                .description
+                .clone()
+                .unwrap_or_else(|| summary.to_string()),
+            project_id: self.project_id,
+            tag_ids: self.tag_ids.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meeting {
+    uid: String,
+    description: String,
+    // What was running when the meeting started
+    previous: Option<toggl::TimeEntryDetails>,
+}
+
+// Starts the first matching rule's entry when a calendar event starts and switches back to the
+// entry it interrupted when the event ends, unless the entry was changed in the meantime
+pub struct Meetings {
+    rules: Vec<Rule>,
+    toggl_manager_ref: ActorRef<RestartingManager<toggl::Toggl>>,
+    path: String,
+    meetings: Vec<Meeting>,
+}
+
+impl Actor for Meetings {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        ActorRef<RestartingManager<toggl::Toggl>>,
+        Vec<Rule>,
+        String,
+    );
+    type Error = MeetingsError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, toggl_manager_ref, rules, path) = state;
+
+        // The calendar sends the end of a meeting that finished while the hub was down once it's
+        // back, so nothing here goes stale
+        let meetings = state_file::load(&path).await.map_err(MeetingsError::Load)?;
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "schedule".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        Ok(Self {
+            rules,
+            toggl_manager_ref,
+            path,
+            meetings,
+        })
+    }
+}
+
+impl Message<crate::BrokerMessage> for Meetings {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let result = match message {
+            crate::BrokerMessage::CalendarEventStarted(event) => self.started(event).await,
+            crate::BrokerMessage::CalendarEventEnded(event) => self.ended(event).await,
+            _ => return,
+        };
+
+        if let Err(e) = result {
+            tracing::warn!("! meetings could not update time entry: {}", e);
+        }
+
+        if let Err(e) = self.save().await {
+            tracing::error!("! could not save meetings to {}: {}", self.path, e);
+        }
+    }
+}
+
+impl Meetings {
+    async fn started(&mut self, event: crate::CalendarEventUpcoming) -> Result<(), MeetingsError> {
+        if event.all_day {
+            return Ok(());
+        }
+
+        let Some(details) = self
+            .rules
+            .iter()
+            .find_map(|rule| rule.details(&event.description))
+        else {
+            return Ok(());
+        };
+
+        let toggl_ref = self.toggl().await?;
+
+        let previous = running(&toggl_ref)
+            .await?
+            .map(|time_entry| toggl::TimeEntryDetails::from(&time_entry));
+
+        tracing::info!("meetings: {:?} started", details.description);
+
+        self.meetings.push(Meeting {
+            uid: event.uid,
+            description: details.description.clone(),
+            previous,
+        });
+
+        toggl_ref
+            .ask(toggl::StartTimeEntry { details })
+            .await
+            .map_err(toggl_error)
+    }
+
+    async fn ended(&mut self, event: crate::CalendarEventUpcoming) -> Result<(), MeetingsError> {
+        let Some(index) = self
+            .meetings
+            .iter()
+            .position(|meeting| meeting.uid == event.uid)
+        else {
+            return Ok(());
+        };
+
+        let meeting = self.meetings.remove(index);
+
+        let toggl_ref = self.toggl().await?;
+
+        let running = running(&toggl_ref).await?;
+
+        // Someone switched entries during the meeting
+        if running.as_ref().map(|time_entry| time_entry.description())
+            != Some(meeting.description.as_str())
+        {
+            hand_back(&mut self.meetings, &meeting);
+            return Ok(());
+        }
+
+        tracing::info!("meetings: {:?} ended", meeting.description);
+
+        // ContinueTimeEntry would pick up the meeting itself as the latest entry, so the
+        // interrupted entry is started again from its details
+        match meeting.previous {
+            Some(details) => toggl_ref
+                .ask(toggl::StartTimeEntry { details })
+                .await
+                .map_err(toggl_error),
+            None => toggl_ref
+                .ask(toggl::StopTimeEntry)
+                .await
+                .map_err(toggl_error),
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save(&self.path, &self.meetings).await
+    }
+
+    async fn toggl(&self) -> Result<ActorRef<toggl::Toggl>, MeetingsError> {
+        self.toggl_manager_ref
+            .ask(restarting_manager::GetChild)
+            .await
+            .map_err(|e| MeetingsError::Unavailable(e.to_string()))
+    }
+}
+
+// Toggl's own view, unless operations are still queued there; what the actor last saw already
+// includes them then
+async fn running(
+    toggl_ref: &ActorRef<toggl::Toggl>,
+) -> Result<Option<toggl::TimeEntry>, MeetingsError> {
+    match toggl_ref.ask(toggl::GetCurrentTimeEntry).await {
+        Err(SendError::HandlerError(toggl::TogglError::Conflict(_))) => toggl_ref
+            .ask(toggl::GetRunningTimeEntry)
+            .await
+            .map_err(|e| MeetingsError::Unavailable(e.to_string())),
+        result => result.map_err(toggl_error),
+    }
+}
+
+// A meeting that started on top of the one that ended goes back to what that one interrupted
+fn hand_back(meetings: &mut [Meeting], ended: &Meeting) {
+    for meeting in meetings.iter_mut() {
+        if meeting
+            .previous
+            .as_ref()
+            .map(|details| &details.description)
+            == Some(&ended.description)
+        {
+            meeting.previous = ended.previous.clone();
+        }
+    }
+}
+
+fn toggl_error<M>(e: SendError<M, toggl::TogglError>) -> MeetingsError {
+    match e {
+        SendError::HandlerError(e) => MeetingsError::Toggl(e),
+        e => MeetingsError::Unavailable(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(regex: Option<&str>, keyword: Option<&str>) -> RuleConfig {
+        RuleConfig {
+            regex: regex.map(str::to_string),
+            keyword: keyword.map(str::to_string),
+            description: None,
+            project_id: Some(7),
+            tag_ids: vec![1],
+        }
+    }
+
+    fn details(description: &str) -> toggl::TimeEntryDetails {
+        toggl::TimeEntryDetails {
+            description: description.to_string(),
+            project_id: None,
+            tag_ids: vec![],
+        }
+    }
+
+    fn meeting(uid: &str, description: &str, previous: Option<&str>) -> Meeting {
+        Meeting {
+            uid: uid.to_string(),
+            description: description.to_string(),
+            previous: previous.map(details),
+        }
+    }
+
+    #[test]
+    fn compile_needs_exactly_one_of_regex_or_keyword() {
+        assert!(Rule::compile(&[rule(Some("^Standup"), None)]).is_ok());
+        assert!(Rule::compile(&[rule(None, Some("standup"))]).is_ok());
+        assert!(Rule::compile(&[rule(None, None)]).is_err());
+        assert!(Rule::compile(&[rule(Some("^Standup"), Some("standup"))]).is_err());
+        assert!(Rule::compile(&[rule(Some("(unclosed"), None)]).is_err());
+    }
+
+    #[test]
+    fn keywords_match_case_insensitively() {
+        let rules = Rule::compile(&[rule(None, Some("StandUp"))]).unwrap();
+
+        assert!(rules[0].details("Daily standup").is_some());
+        assert!(rules[0].details("Planning").is_none());
+    }
+
+    #[test]
+    fn details_fall_back_to_the_summary() {
+        let mut with_description = rule(Some("^1:1"), None);
+        with_description.description = Some("One on one".to_string());
+
+        let rules = Rule::compile(&[rule(Some("^Standup"), None), with_description]).unwrap();
+
+        assert_eq!(
+            rules[0].details("Standup"),
+            Some(toggl::TimeEntryDetails {
+                description: "Standup".to_string(),
+                project_id: Some(7),
+                tag_ids: vec![1],
+            })
+        );
+        assert_eq!(
+            rules[1].details("1:1 with Sam").unwrap().description,
+            "One on one"
+        );
+        assert!(rules[0].details("Not a Standup").is_none());
+    }
+
+    #[test]
+    fn nested_meeting_goes_back_to_what_the_outer_one_interrupted() {
+        // Coding, then Planning, then Standup on top of Planning
+        let planning = meeting("planning", "Planning", Some("Coding"));
+        let mut meetings = vec![meeting("standup", "Standup", Some("Planning"))];
+
+        hand_back(&mut meetings, &planning);
+
+        assert_eq!(meetings[0].previous, Some(details("Coding")));
+    }
+
+    #[test]
+    fn unrelated_meetings_keep_what_they_interrupted() {
+        let planning = meeting("planning", "Planning", Some("Coding"));
+        let mut meetings = vec![meeting("review", "Review", Some("Email"))];
+
+        hand_back(&mut meetings, &planning);
+
+        assert_eq!(meetings[0].previous, Some(details("Email")));
+    }
+}
@@ -0,0 +1,404 @@
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::state_file;
+
+mod datadog;
+mod influxdb;
+mod prometheus;
+
+// Keeps each request well under Datadog's and InfluxDB's size limits
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Datadog,
+    Prometheus,
+    Influxdb,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub prometheus: prometheus::PrometheusConfig,
+    #[serde(default)]
+    pub influxdb: influxdb::InfluxDbConfig,
+    #[serde(default = "default_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    // Points waiting to be sent, kept across restarts
+    #[serde(default = "default_path")]
+    pub path: String,
+    // The oldest points are dropped beyond this while the backend is unreachable
+    #[serde(default = "default_max_buffered_points")]
+    pub max_buffered_points: usize,
+    #[serde(default = "default_host")]
+    pub host: String,
+}
+
+impl MetricsConfig {
+    // Only Datadog keeps its settings outside [metrics], in the [datadog] section
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.flush_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "metrics".to_string(),
+                "flush_interval_secs must be at least 1".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn backend(&self, config: &crate::config::Config) -> Result<BackendConfig, ConfigError> {
+        Ok(match self.backend {
+            BackendKind::Datadog => BackendConfig::Datadog(config.required("datadog")?),
+            BackendKind::Prometheus => BackendConfig::Prometheus(self.prometheus.clone()),
+            BackendKind::Influxdb => BackendConfig::Influxdb(self.influxdb.clone()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BackendConfig {
+    Datadog(crate::config::DatadogConfig),
+    Prometheus(prometheus::PrometheusConfig),
+    Influxdb(influxdb::InfluxDbConfig),
+}
+
+fn default_flush_interval_secs() -> u64 {
+    60
+}
+
+fn default_max_backoff_secs() -> u64 {
+    900
+}
+
+fn default_path() -> String {
+    "metrics.json".to_string()
+}
+
+fn default_max_buffered_points() -> usize {
+    50_000
+}
+
+fn default_host() -> String {
+    "deskpi".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MetricKind {
+    Gauge,
+    Count,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Point {
+    pub name: String,
+    pub kind: MetricKind,
+    pub value: f64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Point {
+    pub fn gauge(name: impl Into<String>, value: f64) -> Self {
+        Self {
+            name: name.into(),
+            kind: MetricKind::Gauge,
+            value,
+            tags: vec![],
+            at: chrono::Utc::now(),
+        }
+    }
+
+    pub fn count(name: impl Into<String>, value: f64) -> Self {
+        Self {
+            kind: MetricKind::Count,
+            ..Self::gauge(name, value)
+        }
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn at(mut self, at: chrono::DateTime<chrono::Utc>) -> Self {
+        self.at = at;
+        self
+    }
+
+    pub async fn report(self, broker_ref: &ActorRef<broker::Broker<crate::BrokerMessage>>) {
+        report(vec![self], broker_ref).await;
+    }
+}
+
+// Goes through the broker so reporting never waits on, or fails with, the metrics backend. Points
+// taken together go as one message.
+pub async fn report(
+    points: Vec<Point>,
+    broker_ref: &ActorRef<broker::Broker<crate::BrokerMessage>>,
+) {
+    let _ = broker_ref
+        .tell(broker::Publish {
+            topic: "metrics".parse().unwrap(),
+            message: crate::BrokerMessage::Metrics(points),
+        })
+        .await;
+}
+
+#[derive(Debug)]
+pub enum MetricsError {
+    Load(state_file::StateFileError),
+    Network(String),
+    Rejected(String),
+    Bind(std::net::SocketAddr, std::io::Error),
+}
+
+impl std::fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetricsError::Load(e) => write!(f, "could not load buffered points: {}", e),
+            MetricsError::Network(e) => write!(f, "network error: {}", e),
+            MetricsError::Rejected(e) => write!(f, "rejected: {}", e),
+            MetricsError::Bind(address, e) => write!(f, "could not listen on {}: {}", address, e),
+        }
+    }
+}
+
+impl std::error::Error for MetricsError {}
+
+// Where flushed points end up: pushed in batches, or kept for Prometheus to scrape
+#[async_trait::async_trait]
+pub trait Backend: Send + Sync {
+    async fn submit(&self, points: &[Point]) -> Result<(), MetricsError>;
+
+    // Stops anything the backend serves itself
+    fn stop(&mut self) {}
+}
+
+impl From<reqwest::Error> for MetricsError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            // A bad key or too many requests clear up without losing the points
+            Some(
+                reqwest::StatusCode::UNAUTHORIZED
+                | reqwest::StatusCode::FORBIDDEN
+                | reqwest::StatusCode::REQUEST_TIMEOUT
+                | reqwest::StatusCode::TOO_MANY_REQUESTS,
+            ) => MetricsError::Network(e.to_string()),
+            Some(status) if status.is_client_error() => MetricsError::Rejected(e.to_string()),
+            _ => MetricsError::Network(e.to_string()),
+        }
+    }
+}
+
+// Collects the points every actor reports, buffers them on disk and sends them in batches every
+// `flush_interval_secs`, backing off while the backend is unreachable
+pub struct Metrics {
+    config: MetricsConfig,
+    backend: Box<dyn Backend>,
+    points: Vec<Point>,
+    // Points arrived or were sent since the buffer was last saved
+    unsaved: bool,
+    backoff: Option<std::time::Duration>,
+    retry_at: Option<std::time::Instant>,
+}
+
+impl Actor for Metrics {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        MetricsConfig,
+        BackendConfig,
+    );
+    type Error = MetricsError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config, backend_config) = state;
+
+        let points: Vec<Point> = state_file::load(&config.path)
+            .await
+            .map_err(MetricsError::Load)?;
+
+        tracing::info!("{} metric points loaded from {}", points.len(), config.path);
+
+        let backend: Box<dyn Backend> = match backend_config {
+            BackendConfig::Datadog(datadog_config) => Box::new(datadog::Datadog::new(
+                &datadog_config,
+                &config.host,
+                config.flush_interval_secs,
+            )),
+            BackendConfig::Prometheus(prometheus_config) => {
+                Box::new(prometheus::Prometheus::start(prometheus_config).await?)
+            }
+            BackendConfig::Influxdb(influxdb_config) => {
+                Box::new(influxdb::InfluxDb::new(&influxdb_config, &config.host))
+            }
+        };
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "metrics".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        let flush_interval = std::time::Duration::from_secs(config.flush_interval_secs);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+
+            loop {
+                interval.tick().await;
+
+                if actor_ref.tell(Flush).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            backend,
+            points,
+            unsaved: false,
+            backoff: None,
+            retry_at: None,
+        })
+    }
+
+    async fn on_stop(
+        &mut self,
+        _actor_ref: WeakActorRef<Self>,
+        _reason: ActorStopReason,
+    ) -> Result<(), Self::Error> {
+        self.backend.stop();
+        self.save().await;
+        Ok(())
+    }
+}
+
+impl Message<crate::BrokerMessage> for Metrics {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let crate::BrokerMessage::Metrics(points) = message else {
+            return;
+        };
+
+        self.points.extend(points);
+
+        let overflow = self
+            .points
+            .len()
+            .saturating_sub(self.config.max_buffered_points);
+
+        if overflow > 0 {
+            tracing::warn!("! dropped {} buffered metric points", overflow);
+            self.points.drain(..overflow);
+        }
+
+        // Saved on the next Flush, rewriting the whole buffer for every point would keep the SD
+        // card busy
+        self.unsaved = true;
+    }
+}
+
+pub struct Flush;
+
+impl Message<Flush> for Metrics {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Flush,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let Some(retry_at) = self.retry_at
+            && std::time::Instant::now() < retry_at
+        {
+            self.save().await;
+            return;
+        }
+
+        let mut sent = 0;
+
+        while sent < self.points.len() {
+            let batch = &self.points[sent..(sent + BATCH_SIZE).min(self.points.len())];
+
+            match self.backend.submit(batch).await {
+                Ok(()) => {
+                    tracing::info!("submitted {} metric points", batch.len());
+                }
+                // Retrying won't change the backend's mind, so the batch is dropped
+                Err(MetricsError::Rejected(e)) => {
+                    tracing::error!("! dropped {} metric points: {}", batch.len(), e);
+                }
+                Err(e) => {
+                    let flush_interval =
+                        std::time::Duration::from_secs(self.config.flush_interval_secs);
+                    let backoff = self
+                        .backoff
+                        .map_or(flush_interval, |backoff| backoff * 2)
+                        .min(std::time::Duration::from_secs(self.config.max_backoff_secs));
+
+                    tracing::warn!(
+                        "! could not submit metrics, retrying in {:?}: {}",
+                        backoff,
+                        e
+                    );
+
+                    self.backoff = Some(backoff);
+                    self.retry_at = Some(std::time::Instant::now() + backoff);
+                    break;
+                }
+            }
+
+            sent += batch.len();
+        }
+
+        if sent == self.points.len() {
+            self.backoff = None;
+            self.retry_at = None;
+        }
+
+        if sent > 0 {
+            self.points.drain(..sent);
+            self.unsaved = true;
+        }
+
+        self.save().await;
+    }
+}
+
+impl Metrics {
+    async fn save(&mut self) {
+        if !self.unsaved {
+            return;
+        }
+
+        match self.write().await {
+            Ok(()) => self.unsaved = false,
+            Err(e) => tracing::error!("! could not save metrics to {}: {}", self.config.path, e),
+        }
+    }
+
+    async fn write(&self) -> std::io::Result<()> {
+        state_file::save(&self.config.path, &self.points).await
+    }
+}
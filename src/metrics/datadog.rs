@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use super::{MetricKind, MetricsError, Point};
+
+pub struct Datadog {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    host: String,
+    flush_interval_secs: u64,
+}
+
+impl Datadog {
+    pub fn new(
+        config: &crate::config::DatadogConfig,
+        host: &str,
+        flush_interval_secs: u64,
+    ) -> Self {
+        let mut headers = reqwest::header::HeaderMap::new();
+
+        headers.insert(
+            "DD-API-KEY",
+            reqwest::header::HeaderValue::from_str(&config.api_key).unwrap(),
+        );
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(std::time::Duration::from_secs(30))
+            .build()
+            .unwrap();
+
+        Self {
+            client,
+            url: config.url.join("/api/v2/series").unwrap(),
+            host: host.to_string(),
+            flush_interval_secs,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Backend for Datadog {
+    async fn submit(&self, points: &[Point]) -> Result<(), MetricsError> {
+        let mut series = BTreeMap::<(&str, MetricKind, &[String]), Vec<serde_json::Value>>::new();
+
+        for point in points {
+            series
+                .entry((point.name.as_str(), point.kind, point.tags.as_slice()))
+                .or_default()
+                .push(serde_json::json!({
+                    "timestamp": point.at.timestamp(),
+                    "value": point.value,
+                }));
+        }
+
+        let series = series
+            .into_iter()
+            .map(|((name, kind, tags), points)| {
+                let mut series = serde_json::json!({
+                    "metric": name,
+                    "type": match kind {
+                        MetricKind::Count => 1,
+                        MetricKind::Gauge => 3,
+                    },
+                    "points": points,
+                    "resources": [{ "name": self.host, "type": "host" }],
+                    "tags": tags,
+                });
+
+                if kind == MetricKind::Count {
+                    series["interval"] = self.flush_interval_secs.into();
+                }
+
+                series
+            })
+            .collect::<Vec<serde_json::Value>>();
+
+        self.client
+            .post(self.url.clone())
+            .json(&serde_json::json!({ "series": series }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
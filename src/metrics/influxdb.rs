@@ -0,0 +1,168 @@
+use serde::Deserialize;
+
+use super::{MetricsError, Point};
+
+// InfluxDB 2's write API; 1.8 accepts the same with `org` ignored and `bucket` as `db/rp`
+#[derive(Debug, Clone, Deserialize)]
+pub struct InfluxDbConfig {
+    #[serde(
+        default = "default_url",
+        deserialize_with = "crate::config::deserialize_url"
+    )]
+    pub url: reqwest::Url,
+    #[serde(default = "default_org")]
+    pub org: String,
+    #[serde(default = "default_bucket")]
+    pub bucket: String,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl Default for InfluxDbConfig {
+    fn default() -> Self {
+        Self {
+            url: default_url(),
+            org: default_org(),
+            bucket: default_bucket(),
+            token: None,
+        }
+    }
+}
+
+fn default_url() -> reqwest::Url {
+    reqwest::Url::parse("http://localhost:8086").unwrap()
+}
+
+fn default_org() -> String {
+    "desk".to_string()
+}
+
+fn default_bucket() -> String {
+    "desk".to_string()
+}
+
+pub struct InfluxDb {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    token: Option<String>,
+    host: String,
+}
+
+impl InfluxDb {
+    pub fn new(config: &InfluxDbConfig, host: &str) -> Self {
+        let mut url = config.url.join("/api/v2/write").unwrap();
+
+        url.query_pairs_mut()
+            .append_pair("org", &config.org)
+            .append_pair("bucket", &config.bucket)
+            .append_pair("precision", "s");
+
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()
+            .unwrap();
+
+        Self {
+            client,
+            url,
+            token: config.token.clone(),
+            host: host.to_string(),
+        }
+    }
+
+    // `urban.temperature,host=deskpi value=25.95 1758353549`, with `key:value` tags as tags
+    fn line(&self, point: &Point) -> String {
+        let mut line = escape(&point.name, &[',', ' ']);
+
+        let tags = std::iter::once(("host", self.host.as_str())).chain(
+            point
+                .tags
+                .iter()
+                .map(|tag| tag.split_once(':').unwrap_or((tag, "true"))),
+        );
+
+        for (key, value) in tags {
+            line.push_str(&format!(
+                ",{}={}",
+                escape(key, &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            ));
+        }
+
+        line.push_str(&format!(" value={} {}", point.value, point.at.timestamp()));
+        line
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Backend for InfluxDb {
+    async fn submit(&self, points: &[Point]) -> Result<(), MetricsError> {
+        let body = points
+            .iter()
+            .map(|point| self.line(point))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let mut request = self.client.post(self.url.clone()).body(body);
+
+        if let Some(token) = &self.token {
+            request = request.header(reqwest::header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        if special.contains(&character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn influxdb() -> InfluxDb {
+        InfluxDb::new(&InfluxDbConfig::default(), "desk pi")
+    }
+
+    #[test]
+    fn line_has_tags_value_and_seconds() {
+        let point = Point::gauge("urban.temperature", 25.95)
+            .tag("input:a1")
+            .tag("watered")
+            .at(chrono::DateTime::from_timestamp(1758353549, 0).unwrap());
+
+        assert_eq!(
+            influxdb().line(&point),
+            "urban.temperature,host=desk\\ pi,input=a1,watered=true value=25.95 1758353549"
+        );
+    }
+
+    #[test]
+    fn line_escapes_names_and_tags() {
+        let point = Point::count("plants watered,total", 1.0)
+            .tag("plant:big fern,left=1")
+            .at(chrono::DateTime::from_timestamp(0, 0).unwrap());
+
+        assert_eq!(
+            influxdb().line(&point),
+            "plants\\ watered\\,total,host=desk\\ pi,plant=big\\ fern\\,left\\=1 value=1 0"
+        );
+    }
+
+    #[test]
+    fn escape_leaves_other_characters_alone() {
+        assert_eq!(escape("a=b\\c", &[',', ' ']), "a=b\\c");
+    }
+}
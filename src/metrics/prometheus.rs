@@ -0,0 +1,191 @@
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use super::{MetricKind, MetricsError, Point};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrometheusConfig {
+    #[serde(
+        default = "default_listen_address",
+        deserialize_with = "crate::config::deserialize_socket_addr"
+    )]
+    pub listen_address: std::net::SocketAddr,
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: default_listen_address(),
+        }
+    }
+}
+
+fn default_listen_address() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([0, 0, 0, 0], 9100))
+}
+
+struct Family {
+    kind: MetricKind,
+    // Rendered labels to the latest gauge value or the count's running total
+    series: BTreeMap<String, f64>,
+}
+
+type Families = Arc<Mutex<BTreeMap<String, Family>>>;
+
+// Nothing is pushed; flushed points update what `/metrics` serves and counts start over from zero
+// when the hub restarts, as Prometheus expects
+pub struct Prometheus {
+    families: Families,
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+impl Prometheus {
+    // Binds before returning, so a taken port stops the actor instead of a detached task
+    pub async fn start(config: PrometheusConfig) -> Result<Self, MetricsError> {
+        let families = Families::default();
+
+        let (shutdown, shutdown_receive) = tokio::sync::oneshot::channel();
+
+        let app = Router::new()
+            .route("/metrics", get(metrics))
+            .with_state(families.clone());
+
+        let listener = tokio::net::TcpListener::bind(config.listen_address)
+            .await
+            .map_err(|e| MetricsError::Bind(config.listen_address, e))?;
+
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app)
+                .with_graceful_shutdown(async move {
+                    let _ = shutdown_receive.await;
+                })
+                .await
+            {
+                tracing::error!("! prometheus endpoint stopped: {}", e);
+            }
+        });
+
+        Ok(Self {
+            families,
+            shutdown: Some(shutdown),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Backend for Prometheus {
+    async fn submit(&self, points: &[Point]) -> Result<(), MetricsError> {
+        let mut families = self.families.lock().unwrap();
+
+        for point in points {
+            let mut name = sanitize(&point.name);
+
+            if point.kind == MetricKind::Count {
+                name.push_str("_total");
+            }
+
+            let family = families.entry(name).or_insert_with(|| Family {
+                kind: point.kind,
+                series: BTreeMap::new(),
+            });
+
+            let value = family.series.entry(labels(&point.tags)).or_default();
+
+            match point.kind {
+                MetricKind::Gauge => *value = point.value,
+                MetricKind::Count => *value += point.value,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+async fn metrics(State(families): State<Families>) -> impl IntoResponse {
+    let mut body = String::new();
+
+    for (name, family) in families.lock().unwrap().iter() {
+        let kind = match family.kind {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Count => "counter",
+        };
+
+        body.push_str(&format!("# TYPE {} {}\n", name, kind));
+
+        for (labels, value) in &family.series {
+            body.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+// `urban.pm2_5` becomes `urban_pm2_5`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|character| {
+            if character.is_ascii_alphanumeric() || character == '_' {
+                character
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// `input:a1` becomes `{input="a1"}`
+fn labels(tags: &[String]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+
+    let labels = tags
+        .iter()
+        .map(|tag| {
+            let (key, value) = tag.split_once(':').unwrap_or((tag, "true"));
+
+            format!(
+                "{}=\"{}\"",
+                sanitize(key),
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect::<Vec<String>>();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_replaces_everything_but_letters_digits_and_underscores() {
+        assert_eq!(sanitize("urban.pm2_5"), "urban_pm2_5");
+        assert_eq!(sanitize("soil capacitance-a1"), "soil_capacitance_a1");
+    }
+
+    #[test]
+    fn labels_escape_values() {
+        assert_eq!(labels(&[]), "");
+        assert_eq!(
+            labels(&["input:a1".to_string(), "watered".to_string()]),
+            "{input=\"a1\",watered=\"true\"}"
+        );
+        assert_eq!(
+            labels(&["actor.name:say \"hi\"\\\nbye".to_string()]),
+            "{actor_name=\"say \\\"hi\\\"\\\\\\nbye\"}"
+        );
+    }
+}
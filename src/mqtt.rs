@@ -0,0 +1,98 @@
+use kameo::prelude::*;
+use serde::Deserialize;
+
+// Shared by everything that talks to the MQTT broker; nothing connects without a `host`
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttConfig {
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "desk".to_string()
+}
+
+#[derive(Debug, Clone)]
+pub enum MqttEvent {
+    // Also sent after every reconnect, subscriptions and retained state have to be renewed
+    Connected,
+    Received { topic: String, payload: Vec<u8> },
+}
+
+// Each connection gets its own client id, `<client_id>-<name>`. The event loop reconnects on its
+// own and runs until the actor is gone.
+pub fn connect<A>(
+    config: &MqttConfig,
+    name: &str,
+    last_will: Option<rumqttc::LastWill>,
+    actor_ref: ActorRef<A>,
+) -> rumqttc::AsyncClient
+where
+    A: Actor + Message<MqttEvent, Reply = ()>,
+{
+    let mut options = rumqttc::MqttOptions::new(
+        format!("{}-{}", config.client_id, name),
+        config.host.clone().unwrap_or_default(),
+        config.port,
+    );
+    options.set_keep_alive(std::time::Duration::from_secs(30));
+
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+
+    if let Some(last_will) = last_will {
+        options.set_last_will(last_will);
+    }
+
+    let (client, mut event_loop) = rumqttc::AsyncClient::new(options, 64);
+
+    let name = name.to_string();
+
+    tokio::spawn(async move {
+        loop {
+            // Errors and pings never reach the actor, so a failed tell isn't the only way to
+            // notice that it's gone
+            if !actor_ref.is_alive() {
+                break;
+            }
+
+            let event = match event_loop.poll().await {
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                    tracing::info!("= mqtt {} connected", name);
+                    MqttEvent::Connected
+                }
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                    MqttEvent::Received {
+                        topic: publish.topic,
+                        payload: publish.payload.to_vec(),
+                    }
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::warn!("! mqtt {}: {}", name, e);
+                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            if actor_ref.tell(event).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    client
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+
+use kameo::error::Infallible;
+use kameo::prelude::*;
+use kameo_actors::broker;
+use rumqttc::QoS;
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+use crate::mqtt::{self, MqttConfig, MqttEvent};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttBridgeConfig {
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    // Broker topics mirrored out to `<prefix>/<topic>`
+    #[serde(default)]
+    pub publish: Vec<String>,
+    // Broker topics other devices can publish into through `<prefix>/<topic>/set`, each with the
+    // `type`s of the messages it takes
+    #[serde(default)]
+    pub subscribe: BTreeMap<String, Vec<String>>,
+}
+
+fn default_prefix() -> String {
+    "desk/broker".to_string()
+}
+
+impl MqttBridgeConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for topic in self.publish.iter().chain(self.subscribe.keys()) {
+            if !is_plain_topic(topic) {
+                return Err(ConfigError::Invalid(
+                    "mqtt_bridge".to_string(),
+                    format!("{:?} is not a plain topic name", topic),
+                ));
+            }
+        }
+
+        for (topic, types) in &self.subscribe {
+            if let Some(message_type) = types
+                .iter()
+                .find(|message_type| !is_message_type(message_type))
+            {
+                return Err(ConfigError::Invalid(
+                    "mqtt_bridge".to_string(),
+                    format!("{} takes unknown message type {:?}", topic, message_type),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Broker topics are glob patterns and MQTT has wildcards of its own, so only names made of
+// letters, digits, `_` and `-` are passed on to either
+fn is_plain_topic(topic: &str) -> bool {
+    !topic.is_empty()
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Serde only lists the variants in its error, so a bare `type` is deserialized and anything but
+// an unknown variant (usually the missing `data`) means the name exists
+fn is_message_type(message_type: &str) -> bool {
+    match serde_json::from_value::<crate::BrokerMessage>(
+        serde_json::json!({ "type": message_type }),
+    ) {
+        Ok(_) => true,
+        Err(e) => !e.to_string().starts_with("unknown variant"),
+    }
+}
+
+// Messages go out and come in as `BrokerMessage` JSON, so other devices on the MQTT broker can take
+// part without an HTTP endpoint of their own
+pub struct MqttBridge {
+    config: MqttBridgeConfig,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    client: rumqttc::AsyncClient,
+}
+
+impl Actor for MqttBridge {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        MqttConfig,
+        MqttBridgeConfig,
+    );
+    type Error = Infallible;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, mqtt_config, config) = state;
+
+        let client = mqtt::connect(&mqtt_config, "bridge", None, actor_ref.clone());
+
+        // Broker messages don't say which topic they were published on, so each topic gets its own
+        // subscriber
+        for topic in &config.publish {
+            let topic_ref =
+                TopicSubscriber::spawn_link(&actor_ref, (topic.clone(), actor_ref.clone())).await;
+
+            broker_ref
+                .tell(broker::Subscribe {
+                    topic: topic.parse().unwrap(),
+                    recipient: topic_ref.recipient(),
+                })
+                .await
+                .unwrap();
+        }
+
+        Ok(Self {
+            config,
+            broker_ref,
+            client,
+        })
+    }
+}
+
+impl Message<MqttEvent> for MqttBridge {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: MqttEvent,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match message {
+            MqttEvent::Connected => {
+                for topic in self.config.subscribe.keys() {
+                    if let Err(e) = self.client.try_subscribe(
+                        format!("{}/{}/set", self.config.prefix, topic),
+                        QoS::AtLeastOnce,
+                    ) {
+                        tracing::warn!("! could not subscribe to {}: {}", topic, e);
+                    }
+                }
+            }
+            MqttEvent::Received { topic, payload } => {
+                let Some((broker_topic, types)) = topic
+                    .strip_prefix(&format!("{}/", self.config.prefix))
+                    .and_then(|topic| topic.strip_suffix("/set"))
+                    .and_then(|topic| self.config.subscribe.get_key_value(topic))
+                else {
+                    return;
+                };
+
+                let payload = match serde_json::from_slice::<serde_json::Value>(&payload) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        tracing::warn!("! rejected mqtt message on {}: {}", topic, e);
+                        return;
+                    }
+                };
+
+                let message_type = payload
+                    .get("type")
+                    .and_then(|message_type| message_type.as_str());
+
+                if !message_type
+                    .is_some_and(|message_type| types.iter().any(|allowed| allowed == message_type))
+                {
+                    tracing::warn!(
+                        "! rejected mqtt message on {}: {:?} not allowed",
+                        topic,
+                        message_type
+                    );
+                    return;
+                }
+
+                let broker_message = match serde_json::from_value::<crate::BrokerMessage>(payload) {
+                    Ok(broker_message) => broker_message,
+                    Err(e) => {
+                        tracing::warn!("! rejected mqtt message on {}: {}", topic, e);
+                        return;
+                    }
+                };
+
+                tracing::info!("mqtt -> {}: {:?}", broker_topic, broker_message);
+
+                self.broker_ref
+                    .tell(broker::Publish {
+                        topic: broker_topic.parse().unwrap(),
+                        message: broker_message,
+                    })
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+}
+
+struct Outgoing {
+    topic: String,
+    message: crate::BrokerMessage,
+}
+
+impl Message<Outgoing> for MqttBridge {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: Outgoing,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let topic = format!("{}/{}", self.config.prefix, message.topic);
+
+        // Doesn't wait for the broker to come back, the bridge only mirrors what's happening now
+        if let Err(e) = self.client.try_publish(
+            &topic,
+            QoS::AtLeastOnce,
+            false,
+            serde_json::to_vec(&message.message).unwrap(),
+        ) {
+            tracing::warn!("! could not publish {}: {}", topic, e);
+        }
+    }
+}
+
+struct TopicSubscriber {
+    topic: String,
+    bridge_ref: ActorRef<MqttBridge>,
+}
+
+impl Actor for TopicSubscriber {
+    type Args = (String, ActorRef<MqttBridge>);
+    type Error = Infallible;
+
+    async fn on_start(state: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (topic, bridge_ref) = state;
+
+        Ok(Self { topic, bridge_ref })
+    }
+}
+
+impl Message<crate::BrokerMessage> for TopicSubscriber {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let _ = self
+            .bridge_ref
+            .tell(Outgoing {
+                topic: self.topic.clone(),
+                message,
+            })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(publish: &[&str], subscribe: &[(&str, &[&str])]) -> MqttBridgeConfig {
+        MqttBridgeConfig {
+            prefix: default_prefix(),
+            publish: publish.iter().map(|topic| topic.to_string()).collect(),
+            subscribe: subscribe
+                .iter()
+                .map(|(topic, types)| {
+                    (
+                        topic.to_string(),
+                        types
+                            .iter()
+                            .map(|message_type| message_type.to_string())
+                            .collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn validate_accepts_plain_topics_and_known_types() {
+        assert!(
+            config(
+                &["toggl", "focus-session"],
+                &[
+                    ("fireworks", &["startFireworks", "stopFireworks"]),
+                    ("message", &["message"])
+                ],
+            )
+            .validate()
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn validate_rejects_patterns_and_wildcards() {
+        for topic in ["", "calendar*", "desk/calendar", "#", "[ab]"] {
+            assert!(config(&[topic], &[]).validate().is_err(), "{:?}", topic);
+            assert!(
+                config(&[], &[(topic, &["message"])]).validate().is_err(),
+                "{:?}",
+                topic
+            );
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unknown_types() {
+        assert!(
+            config(&[], &[("fireworks", &["StartFireworks"])])
+                .validate()
+                .is_err()
+        );
+        assert!(
+            config(&[], &[("fireworks", &["launch"])])
+                .validate()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use serde::{Deserialize, Serialize};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Malformed(String),
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Malformed(reason) => write!(f, "malformed frame: {}", reason),
+            ProtocolError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version: {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl ProtocolError {
+    pub fn reason(&self) -> ErrorReason {
+        match self {
+            ProtocolError::Malformed(_) => ErrorReason::Malformed,
+            ProtocolError::UnsupportedVersion(_) => ErrorReason::UnsupportedVersion,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorReason {
+    Malformed,
+    UnsupportedVersion,
+    RequestFailed,
+    Unauthorized,
+    RateLimited,
+    NotFound,
+    Unavailable,
+    Conflict,
+}
+
+pub fn check_version(version: u32) -> Result<(), ProtocolError> {
+    if version != PROTOCOL_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeEntry {
+    pub description: String,
+    #[serde(default)]
+    pub project_id: Option<i64>,
+    #[serde(default)]
+    pub tag_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectOption {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagOption {
+    pub id: i64,
+    pub name: String,
+}
+
+// Missing fields fall back to the hub's [focus] settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusSession {
+    pub time_entry: Option<TimeEntry>,
+    pub work_minutes: Option<i64>,
+    pub break_minutes: Option<i64>,
+    pub rounds: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdleTimeEntry {
+    pub description: String,
+    pub running_minutes: i64,
+    pub idle_minutes: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IdleResolution {
+    Keep,
+    Stop,
+    Trim,
+}
+
+// Macropad -> hub
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacropadFrame {
+    Heartbeat,
+    Potentiometer(f32),
+    AxisX(f32),
+    AxisY(f32),
+    Request(MacropadRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum MacropadRequest {
+    Hello { version: u32 },
+    GetTimeEntries,
+    GetProjects,
+    GetTags,
+    StartTimeEntry { time_entry: TimeEntry },
+    StopTimeEntry,
+    ContinueTimeEntry,
+    AdjustTime { minutes: i64 },
+    ReadInbox,
+    ClearInbox,
+    StartClock,
+    StartCountdown { minutes: i64 },
+    CancelAnimation,
+    StartFocusSession { session: FocusSession },
+    StopFocusSession,
+    ResolveIdleTimeEntry { resolution: IdleResolution },
+}
+
+impl std::str::FromStr for MacropadFrame {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        if line == "h" {
+            return Ok(MacropadFrame::Heartbeat);
+        }
+
+        if let Some(value) = line.strip_prefix("p") {
+            return Ok(MacropadFrame::Potentiometer(parse_value(value)?));
+        }
+
+        if let Some(value) = line.strip_prefix("x") {
+            return Ok(MacropadFrame::AxisX(parse_value(value)?));
+        }
+
+        if let Some(value) = line.strip_prefix("y") {
+            return Ok(MacropadFrame::AxisY(parse_value(value)?));
+        }
+
+        serde_json::from_str(line)
+            .map(MacropadFrame::Request)
+            .map_err(|e| ProtocolError::Malformed(e.to_string()))
+    }
+}
+
+// Hub -> Macropad
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum MacropadMessage {
+    Hello { version: u32 },
+    Heartbeat,
+    Success,
+    Error { reason: ErrorReason },
+    TimeEntry { time_entry: TimeEntry },
+    Project { project: ProjectOption },
+    Tag { tag: TagOption },
+    IdleTimeEntry { idle: IdleTimeEntry },
+}
+
+// ThinkInk -> hub
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThinkInkFrame {
+    Heartbeat,
+    Event(ThinkInkEvent),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ThinkInkEvent {
+    Hello { version: u32 },
+}
+
+impl std::str::FromStr for ThinkInkFrame {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        if line == "t" {
+            return Ok(ThinkInkFrame::Heartbeat);
+        }
+
+        serde_json::from_str(line)
+            .map(ThinkInkFrame::Event)
+            .map_err(|e| ProtocolError::Malformed(e.to_string()))
+    }
+}
+
+// Hub -> ThinkInk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ThinkInkMessage {
+    Hello { version: u32 },
+    Error { reason: ErrorReason },
+    Light { target_value: u32, speed: u32 },
+    ServoX { target_value: u32, speed: u32 },
+    ServoY { target_value: u32, speed: u32 },
+    StartFireworks,
+    StopFireworks,
+    StartAnimation,
+    StopAnimation,
+    AdjustAnimationTime { minutes: i64 },
+    DisplayData { offset: usize, data: String },
+    RefreshDisplay,
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> Result<T, ProtocolError> {
+    value
+        .parse::<T>()
+        .map_err(|_| ProtocolError::Malformed(format!("invalid value: {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_version_accepts_only_the_current_version() {
+        assert!(check_version(PROTOCOL_VERSION).is_ok());
+
+        let error = check_version(PROTOCOL_VERSION + 1).unwrap_err();
+        assert_eq!(error.reason(), ErrorReason::UnsupportedVersion);
+    }
+
+    #[test]
+    fn macropad_frames() {
+        assert_eq!(
+            "h".parse::<MacropadFrame>().unwrap(),
+            MacropadFrame::Heartbeat
+        );
+        assert_eq!(
+            "p0.5".parse::<MacropadFrame>().unwrap(),
+            MacropadFrame::Potentiometer(0.5)
+        );
+        assert_eq!(
+            r#"{"kind":"hello","version":1}"#.parse::<MacropadFrame>().unwrap(),
+            MacropadFrame::Request(MacropadRequest::Hello { version: 1 })
+        );
+        assert_eq!(
+            r#"{"kind":"adjustTime","minutes":-5}"#.parse::<MacropadFrame>().unwrap(),
+            MacropadFrame::Request(MacropadRequest::AdjustTime { minutes: -5 })
+        );
+    }
+
+    #[test]
+    fn macropad_frames_rejected() {
+        for line in [
+            r#"{"kind":"launchRockets"}"#,
+            r#"{"kind":"adjustTime"}"#,
+            r#"{"kind":"adjustTime","minutes":"five"}"#,
+            r#"{"minutes":5}"#,
+            "{",
+            "",
+            "pfast",
+            "x",
+        ] {
+            let error = line.parse::<MacropadFrame>().unwrap_err();
+            assert_eq!(error.reason(), ErrorReason::Malformed, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn thinkink_frames() {
+        assert_eq!(
+            "t".parse::<ThinkInkFrame>().unwrap(),
+            ThinkInkFrame::Heartbeat
+        );
+        assert_eq!(
+            r#"{"kind":"hello","version":2}"#.parse::<ThinkInkFrame>().unwrap(),
+            ThinkInkFrame::Event(ThinkInkEvent::Hello { version: 2 })
+        );
+
+        for line in [r#"{"kind":"refreshDisplay"}"#, "h", "not json"] {
+            let error = line.parse::<ThinkInkFrame>().unwrap_err();
+            assert_eq!(error.reason(), ErrorReason::Malformed, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn messages_serialize_camel_case() {
+        assert_eq!(
+            serde_json::to_string(&MacropadMessage::Error {
+                reason: ErrorReason::UnsupportedVersion
+            })
+            .unwrap(),
+            r#"{"kind":"error","reason":"unsupportedVersion"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&ThinkInkMessage::DisplayData {
+                offset: 256,
+                data: "AA==".to_string()
+            })
+            .unwrap(),
+            r#"{"kind":"displayData","offset":256,"data":"AA=="}"#
+        );
+    }
+}
@@ -20,19 +20,27 @@ impl Actor for RaylibManager {
     }
 }
 
-pub struct RenderThinkInkImage;
+pub struct RenderThinkInkImage {
+    pub weather_config: crate::apps::thinkink_image::WeatherConfig,
+    pub next_event: Option<crate::CalendarEventUpcoming>,
+    pub alerts: Vec<crate::Alert>,
+}
 
 impl Message<RenderThinkInkImage> for RaylibManager {
     type Reply = Vec<u8>;
 
     async fn handle(
         &mut self,
-        _message: RenderThinkInkImage,
+        message: RenderThinkInkImage,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("-> render thinkink image");
         self.transmit
-            .send(crate::RaylibRequest::RenderThinkInkImage)
+            .send(crate::RaylibRequest::RenderThinkInkImage(
+                message.weather_config,
+                message.next_event,
+                message.alerts,
+            ))
             .await
             .unwrap();
 
@@ -46,3 +54,34 @@ impl Message<RenderThinkInkImage> for RaylibManager {
         }
     }
 }
+
+pub struct RenderTimeSummaryImage {
+    pub summary: crate::apps::thinkink_image::TimeSummary,
+}
+
+impl Message<RenderTimeSummaryImage> for RaylibManager {
+    type Reply = Vec<u8>;
+
+    async fn handle(
+        &mut self,
+        message: RenderTimeSummaryImage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!("-> render time summary image");
+        self.transmit
+            .send(crate::RaylibRequest::RenderTimeSummaryImage(
+                message.summary,
+            ))
+            .await
+            .unwrap();
+
+        let response = self.receive.recv().await.unwrap();
+
+        match response {
+            crate::RaylibResponse::ThinkInkImage(data) => {
+                tracing::info!("<- time summary image");
+                data
+            }
+        }
+    }
+}
@@ -1,26 +1,173 @@
 use kameo::actor::Actor;
 use kameo::error::Infallible;
 use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartStrategy {
+    Immediate,
+    Backoff,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrashLoopAction {
+    Alert,
+    GiveUp,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RestartPolicy {
+    pub strategy: RestartStrategy,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub max_restarts: usize,
+    pub window_secs: u64,
+    pub on_crash_loop: CrashLoopAction,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: RestartStrategy::Backoff,
+            initial_backoff_secs: 1,
+            max_backoff_secs: 300,
+            max_restarts: 5,
+            window_secs: 600,
+            on_crash_loop: CrashLoopAction::Alert,
+        }
+    }
+}
+
+impl RestartPolicy {
+    fn delay(&self, recent_restarts: usize) -> std::time::Duration {
+        match self.strategy {
+            RestartStrategy::Immediate => std::time::Duration::ZERO,
+            RestartStrategy::Backoff => {
+                let factor = 2u64.saturating_pow(recent_restarts.min(32) as u32);
+                std::time::Duration::from_secs(
+                    self.initial_backoff_secs
+                        .saturating_mul(factor)
+                        .min(self.max_backoff_secs),
+                )
+            }
+        }
+    }
+}
+
+// [restart] holds the default policy, [restart.actors.<name>] replaces it for one actor
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RestartConfig {
+    #[serde(flatten)]
+    pub default: RestartPolicy,
+    #[serde(default)]
+    pub actors: HashMap<String, RestartPolicy>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChildStatus {
+    pub alive: bool,
+    pub restarts: u64,
+    pub last_stop_reason: Option<String>,
+    pub last_stopped_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub crash_looping: bool,
+    pub gave_up: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Registry(std::sync::Arc<std::sync::Mutex<BTreeMap<String, ChildStatus>>>);
+
+impl Registry {
+    fn update(&self, name: &str, update: impl FnOnce(&mut ChildStatus)) {
+        update(self.0.lock().unwrap().entry(name.to_string()).or_default());
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ChildStatus> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    config: RestartConfig,
+    registry: Registry,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+}
+
+impl Supervisor {
+    pub fn new(
+        config: RestartConfig,
+        broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    ) -> Self {
+        Self {
+            config,
+            registry: Registry::default(),
+            broker_ref,
+        }
+    }
+
+    pub fn registry(&self) -> Registry {
+        self.registry.clone()
+    }
+
+    pub fn child(&self, name: &'static str) -> ChildSettings {
+        ChildSettings {
+            name,
+            policy: self
+                .config
+                .actors
+                .get(name)
+                .unwrap_or(&self.config.default)
+                .clone(),
+            registry: self.registry.clone(),
+            broker_ref: self.broker_ref.clone(),
+        }
+    }
+}
+
+pub struct ChildSettings {
+    name: &'static str,
+    policy: RestartPolicy,
+    registry: Registry,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+}
 
 pub struct RestartingManager<A: Actor> {
+    settings: ChildSettings,
     get_child_args: Box<dyn Fn() -> A::Args + Send + Sync>,
     child_ref: ActorRef<A>,
+    restarts: VecDeque<std::time::Instant>,
+    restart_pending: bool,
+    crash_looping: bool,
+    gave_up: bool,
 }
 
 struct Tick;
 
+struct Restart;
+
+pub struct GetChild;
+
 impl<A> Actor for RestartingManager<A>
 where
     A: Actor,
 {
-    type Args = (Box<dyn Fn() -> A::Args + Send + Sync>,);
+    type Args = (ChildSettings, Box<dyn Fn() -> A::Args + Send + Sync>);
     type Error = Infallible;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let get_child_args = state.0;
+        let (settings, get_child_args) = state;
 
         let child_ref = RestartingManager::spawn_link(&get_child_args, &actor_ref).await;
 
+        settings.registry.update(settings.name, |status| {
+            status.alive = true;
+        });
+
         actor_ref.tell(Tick).try_send().unwrap();
 
         tokio::spawn(async move {
@@ -36,8 +183,13 @@ where
         });
 
         Ok(Self {
+            settings,
             get_child_args,
             child_ref,
+            restarts: VecDeque::new(),
+            restart_pending: false,
+            crash_looping: false,
+            gave_up: false,
         })
     }
 
@@ -45,12 +197,24 @@ where
         &mut self,
         actor_ref: WeakActorRef<Self>,
         id: ActorID,
-        _reason: ActorStopReason,
+        reason: ActorStopReason,
     ) -> Result<::core::ops::ControlFlow<kameo::error::ActorStopReason>, Self::Error> {
-        tracing::warn!("link died - {:?}", id);
-        self.child_ref =
-            RestartingManager::spawn_link(&self.get_child_args, &actor_ref.upgrade().unwrap()).await;
-        tracing::info!("spawned - {:?}", id);
+        if id != self.child_ref.id() {
+            return Ok(::core::ops::ControlFlow::Continue(()));
+        }
+
+        tracing::warn!("{} link died - {:?}: {:?}", self.settings.name, id, reason);
+
+        self.settings.registry.update(self.settings.name, |status| {
+            status.alive = false;
+            status.last_stop_reason = Some(format!("{:?}", reason));
+            status.last_stopped_at = Some(chrono::Utc::now());
+        });
+
+        if let Some(actor_ref) = actor_ref.upgrade() {
+            self.schedule_restart(actor_ref).await;
+        }
+
         Ok(::core::ops::ControlFlow::Continue(()))
     }
 }
@@ -66,14 +230,72 @@ where
         _message: Tick,
         context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
+        if self.child_ref.is_alive() {
+            self.check_recovered().await;
+            return;
+        }
+
+        if self.restart_pending || self.gave_up {
+            return;
+        }
+
+        tracing::warn!(
+            "{} link found dead - {:?}",
+            self.settings.name,
+            self.child_ref.id()
+        );
+        self.schedule_restart(context.actor_ref().clone()).await;
+    }
+}
+
+impl<A> Message<Restart> for RestartingManager<A>
+where
+    A: Actor,
+{
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Restart,
+        context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.restart_pending = false;
+
         if self.child_ref.is_alive() {
             return;
         }
 
-        tracing::warn!("link found dead - {:?}", self.child_ref.id());
         self.child_ref =
             RestartingManager::spawn_link(&self.get_child_args, &context.actor_ref()).await;
-        tracing::info!("spawned - {:?}", self.child_ref.id());
+        tracing::info!("{} spawned - {:?}", self.settings.name, self.child_ref.id());
+
+        let crash_looping = self.crash_looping;
+
+        self.settings.registry.update(self.settings.name, |status| {
+            status.alive = true;
+            status.restarts += 1;
+            status.crash_looping = crash_looping;
+        });
+
+        crate::metrics::Point::count("actor.restarts", 1.0)
+            .tag(format!("actor:{}", self.settings.name))
+            .report(&self.settings.broker_ref)
+            .await;
+    }
+}
+
+impl<A> Message<GetChild> for RestartingManager<A>
+where
+    A: Actor,
+{
+    type Reply = Result<ActorRef<A>, Infallible>;
+
+    async fn handle(
+        &mut self,
+        _message: GetChild,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        Ok(self.child_ref.clone())
     }
 }
 
@@ -92,4 +314,110 @@ where
 
         child_ref
     }
+
+    async fn schedule_restart(&mut self, actor_ref: ActorRef<Self>) {
+        let policy = &self.settings.policy;
+        let now = std::time::Instant::now();
+        let window = std::time::Duration::from_secs(policy.window_secs);
+
+        while let Some(restarted_at) = self.restarts.front()
+            && now.duration_since(*restarted_at) > window
+        {
+            self.restarts.pop_front();
+        }
+
+        if self.restarts.len() >= policy.max_restarts {
+            if !self.crash_looping {
+                self.crash_looping = true;
+                self.alert().await;
+            }
+
+            if policy.on_crash_loop == CrashLoopAction::GiveUp {
+                tracing::error!("{} gave up restarting", self.settings.name);
+                self.gave_up = true;
+                self.settings.registry.update(self.settings.name, |status| {
+                    status.crash_looping = true;
+                    status.gave_up = true;
+                });
+                return;
+            }
+        } else if self.crash_looping {
+            self.crash_looping = false;
+            self.clear_alert().await;
+        }
+
+        let delay = policy.delay(self.restarts.len());
+
+        self.restarts.push_back(now);
+        self.restart_pending = true;
+
+        tracing::info!("{} restarting in {:?}", self.settings.name, delay);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _ = actor_ref.tell(Restart).await;
+        });
+    }
+
+    // A child that stayed up for a whole window since its last restart is no longer crash looping
+    async fn check_recovered(&mut self) {
+        if !self.crash_looping {
+            return;
+        }
+
+        let window = std::time::Duration::from_secs(self.settings.policy.window_secs);
+
+        if let Some(restarted_at) = self.restarts.back()
+            && restarted_at.elapsed() <= window
+        {
+            return;
+        }
+
+        self.restarts.clear();
+        self.crash_looping = false;
+        self.settings.registry.update(self.settings.name, |status| {
+            status.crash_looping = false;
+        });
+        self.clear_alert().await;
+    }
+
+    // Shown on the ThinkInk rather than sent as a message: the Unicorn takes those, and a message
+    // it can't deliver shouldn't keep it crashing
+    async fn alert(&self) {
+        tracing::error!(
+            "{} is crash looping: {} restarts in {}s",
+            self.settings.name,
+            self.restarts.len(),
+            self.settings.policy.window_secs
+        );
+
+        self.publish_alert(crate::BrokerMessage::AlertRaised(crate::Alert {
+            name: self.alert_name(),
+            series: "supervisor".to_string(),
+            value: self.restarts.len() as f64,
+        }))
+        .await;
+    }
+
+    async fn clear_alert(&self) {
+        tracing::info!("{} stopped crash looping", self.settings.name);
+
+        self.publish_alert(crate::BrokerMessage::AlertCleared(self.alert_name()))
+            .await;
+    }
+
+    async fn publish_alert(&self, message: crate::BrokerMessage) {
+        let _ = self
+            .settings
+            .broker_ref
+            .tell(broker::Publish {
+                topic: "alert".parse().unwrap(),
+                message,
+            })
+            .await;
+    }
+
+    fn alert_name(&self) -> String {
+        format!("{} restarts", self.settings.name)
+    }
 }
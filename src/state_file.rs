@@ -0,0 +1,102 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+#[derive(Debug)]
+pub enum StateFileError {
+    Read(String, std::io::Error),
+    Parse(String, serde_json::Error),
+}
+
+impl std::fmt::Display for StateFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateFileError::Read(path, e) => write!(f, "could not read {}: {}", path, e),
+            StateFileError::Parse(path, e) => write!(f, "could not parse {}: {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for StateFileError {}
+
+// A missing file is a first start; one that can't be loaded is an error rather than something the
+// next save quietly overwrites
+pub async fn load<T>(path: &str) -> Result<T, StateFileError>
+where
+    T: DeserializeOwned + Default,
+{
+    match tokio::fs::read_to_string(path).await {
+        Ok(data) => {
+            serde_json::from_str(&data).map_err(|e| StateFileError::Parse(path.to_string(), e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(StateFileError::Read(path.to_string(), e)),
+    }
+}
+
+// Written to a temporary file and renamed over the old one, so a crash mid-save never leaves a
+// truncated file behind
+pub async fn save<T>(path: &str, value: &T) -> std::io::Result<()>
+where
+    T: Serialize,
+{
+    write(path, serde_json::to_vec(value)?).await
+}
+
+// For files that are worth reading by hand
+pub async fn save_pretty<T>(path: &str, value: &T) -> std::io::Result<()>
+where
+    T: Serialize,
+{
+    write(path, serde_json::to_vec_pretty(value)?).await
+}
+
+async fn write(path: &str, data: Vec<u8>) -> std::io::Result<()> {
+    let temporary_path = format!("{}.tmp", path);
+
+    tokio::fs::write(&temporary_path, data).await?;
+    tokio::fs::rename(&temporary_path, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("desk-state-file-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[tokio::test]
+    async fn missing_file_loads_default() {
+        let loaded: Vec<u32> = load(&path("missing")).await.unwrap();
+
+        assert!(loaded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn saved_value_loads_back() {
+        let path = path("round-trip");
+
+        save(&path, &vec![1, 2, 3]).await.unwrap();
+        let loaded: Vec<u32> = load(&path).await.unwrap();
+
+        assert_eq!(loaded, vec![1, 2, 3]);
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unparseable_file_is_an_error() {
+        let path = path("unparseable");
+
+        tokio::fs::write(&path, "not json").await.unwrap();
+        let loaded: Result<Vec<u32>, _> = load(&path).await;
+
+        assert!(matches!(loaded, Err(StateFileError::Parse(..))));
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
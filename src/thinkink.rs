@@ -3,30 +3,89 @@ use kameo::error::Infallible;
 use kameo::message::StreamMessage;
 use kameo::prelude::*;
 use kameo_actors::broker;
+use serde::Deserialize;
+
+use crate::apps::thinkink_image::{TimeSummary, WeatherConfig};
+use crate::protocol::{self, ThinkInkEvent, ThinkInkFrame, ThinkInkMessage};
+use crate::restarting_manager::{self, RestartingManager};
+use crate::{calendar, toggl, transport};
+
+pub const DEVICE: transport::Device = transport::Device {
+    name: "thinkink",
+    baud_rate: 115200,
+    usb_vid: 0x239A,
+    usb_pid: 0x80F1,
+    heartbeat: "t",
+};
+
+// The e-paper display shouldn't be refreshed more often than every few minutes
+const MINIMUM_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3 * 60);
+const RUNNING_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Handshake {
+    Pending,
+    Complete,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThinkInkConfig {
+    #[serde(flatten)]
+    pub transport: transport::TransportSettings,
+    #[serde(default)]
+    pub panel: ThinkInkPanel,
+}
 
-#[cfg(feature = "pi")]
-use futures::stream::StreamExt;
-#[cfg(feature = "pi")]
-use tokio_serial::SerialPortBuilderExt;
-#[cfg(feature = "pi")]
-use tokio_util::codec::{Framed, LinesCodec};
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkInkPanel {
+    #[default]
+    Overview,
+    TimeSummary,
+}
 
 pub struct ThinkInk {
-    transmit: Box<dyn crate::serial_sink::Sink>,
+    transmit: Box<dyn transport::Sink>,
     raylib_manager_ref: ActorRef<crate::raylib_manager::RaylibManager>,
+    weather_config: WeatherConfig,
+    panel: ThinkInkPanel,
+    toggl_manager_ref: Option<ActorRef<RestartingManager<toggl::Toggl>>>,
     last_date_string: Option<String>,
+    summary_stale: bool,
+    last_refresh_at: Option<std::time::Instant>,
+    time_entry_running: bool,
+    next_event: Option<crate::CalendarEventUpcoming>,
+    next_event_stale: bool,
+    alerts: std::collections::BTreeMap<String, crate::Alert>,
+    alerts_stale: bool,
+    handshake: Handshake,
 }
 
 impl Actor for ThinkInk {
     type Args = (
         ActorRef<broker::Broker<crate::BrokerMessage>>,
         ActorRef<crate::raylib_manager::RaylibManager>,
+        transport::TransportConfig,
+        WeatherConfig,
+        ThinkInkPanel,
+        Option<ActorRef<RestartingManager<toggl::Toggl>>>,
+        Option<ActorRef<RestartingManager<calendar::Calendar>>>,
     );
     type Error = Infallible;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
         let broker_ref = state.0;
         let raylib_manager_ref = state.1;
+        let transport_config = state.2;
+        let weather_config = state.3;
+        let panel = state.4;
+        let toggl_manager_ref = state.5;
+        let calendar_manager_ref = state.6;
+
+        if panel == ThinkInkPanel::TimeSummary && toggl_manager_ref.is_none() {
+            tracing::warn!("! the time summary panel needs the toggl section enabled");
+        }
 
         broker_ref
             .tell(broker::Subscribe {
@@ -36,6 +95,22 @@ impl Actor for ThinkInk {
             .await
             .unwrap();
 
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "focus".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "schedule".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
         broker_ref
             .tell(broker::Subscribe {
                 topic: "servo".parse().unwrap(),
@@ -52,7 +127,24 @@ impl Actor for ThinkInk {
             .await
             .unwrap();
 
-        crate::light::Light::spawn_link(&actor_ref, (actor_ref.clone(),)).await;
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "alert".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        // Alerts raised before this started are only known to whatever raised them
+        broker_ref
+            .tell(broker::Publish {
+                topic: "alert".parse().unwrap(),
+                message: crate::BrokerMessage::AlertsRequested,
+            })
+            .await
+            .unwrap();
+
+        crate::light::Light::spawn_link(&actor_ref, (actor_ref.clone(), broker_ref.clone())).await;
 
         actor_ref.tell(UpdateImage).try_send().unwrap();
 
@@ -72,6 +164,11 @@ impl Actor for ThinkInk {
             });
         }
 
+        let next_event = match &calendar_manager_ref {
+            Some(calendar_manager_ref) => next_event(calendar_manager_ref).await,
+            None => None,
+        };
+
         let last_date_string = match tokio::fs::read_to_string("date.txt").await {
             Ok(data) => Some(data),
             Err(_) => None,
@@ -81,60 +178,75 @@ impl Actor for ThinkInk {
         {
             // Wait if new ThinkInk code is being deployed
             tokio::time::sleep(std::time::Duration::from_secs(20)).await;
-
-            let serial_port =
-                tokio_serial::new(std::env::var("THINKINK_SERIAL_PATH").unwrap(), 115200)
-                    .open_native_async()
-                    .unwrap();
-            tracing::info!("serial opened");
-
-            let device = Framed::new(serial_port, LinesCodec::new());
-            let (transmit, receive) = device.split::<String>();
-
-            actor_ref.attach_stream(receive, (), ());
-
-            let serial_sink = crate::serial_sink::SerialSink::new(transmit);
-
-            Ok(Self {
-                transmit: Box::new(serial_sink),
-                raylib_manager_ref,
-                last_date_string,
-            })
         }
 
-        #[cfg(not(feature = "pi"))]
-        {
-            Ok(Self {
-                transmit: Box::new(crate::serial_sink::DummySink),
-                raylib_manager_ref,
-                last_date_string,
-            })
-        }
+        // The ThinkInk answers with its own hello
+        let greeting = serde_json::to_string(&ThinkInkMessage::Hello {
+            version: protocol::PROTOCOL_VERSION,
+        })
+        .unwrap();
+
+        let link = transport_config.connect(Some(greeting));
+
+        actor_ref.attach_stream(link.receive, (), ());
+
+        Ok(Self {
+            transmit: link.transmit,
+            raylib_manager_ref,
+            weather_config,
+            panel,
+            toggl_manager_ref,
+            last_date_string,
+            summary_stale: true,
+            last_refresh_at: None,
+            time_entry_running: false,
+            next_event_stale: next_event.is_some(),
+            next_event,
+            alerts: std::collections::BTreeMap::new(),
+            alerts_stale: false,
+            handshake: Handshake::Pending,
+        })
     }
 }
 
-impl Message<StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (), ()>>
-    for ThinkInk
-{
+impl Message<StreamMessage<transport::Received, (), ()>> for ThinkInk {
     type Reply = ();
 
     async fn handle(
         &mut self,
-        message: StreamMessage<Result<String, tokio_util::codec::LinesCodecError>, (), ()>,
+        message: StreamMessage<transport::Received, (), ()>,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         match message {
-            StreamMessage::Next(Ok(line)) => {
-                if line == "t" {
-                    return;
-                }
-
-                let message: serde_json::Value = serde_json::from_str(&line).unwrap();
-
-                tracing::info!("<- message: {:?}", message);
+            StreamMessage::Next(transport::Received::Connected) => {
+                self.handshake = Handshake::Pending;
             }
-            StreamMessage::Next(Err(e)) => {
-                tracing::error!("! serial error: {}", e);
+            StreamMessage::Next(transport::Received::Line(line)) => {
+                match line.parse::<ThinkInkFrame>() {
+                    Ok(ThinkInkFrame::Heartbeat) => {}
+                    // Answers the hub's greeting, or is sent on its own when the ThinkInk boots
+                    Ok(ThinkInkFrame::Event(ThinkInkEvent::Hello { version })) => {
+                        tracing::info!("<- hello: {}", version);
+
+                        match protocol::check_version(version) {
+                            Ok(()) => {
+                                tracing::info!("= handshake complete, version {}", version);
+                                self.handshake = Handshake::Complete;
+                            }
+                            Err(e) => {
+                                tracing::warn!("! handshake failed: {}", e);
+                                self.handshake = Handshake::Rejected;
+                                self.send_message(ThinkInkMessage::Error { reason: e.reason() })
+                                    .await;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("! rejected frame {:?}: {}", line, e);
+                        self.send_message(ThinkInkMessage::Error { reason: e.reason() })
+                            .await;
+                    }
+                }
             }
             StreamMessage::Started(_) => {
                 tracing::info!("= serial started");
@@ -156,51 +268,64 @@ impl Message<crate::BrokerMessage> for ThinkInk {
     ) -> Self::Reply {
         match message {
             crate::BrokerMessage::TimeEntryStarted(_) => {
-                self.send_message(serde_json::json!({
-                    "kind": "startAnimation",
-                }))
-                .await
+                self.summary_stale = true;
+                self.time_entry_running = true;
+                self.send_message(ThinkInkMessage::StartAnimation).await
             }
             crate::BrokerMessage::TimeEntryStopped => {
-                self.send_message(serde_json::json!({
-                    "kind": "stopAnimation",
-                }))
-                .await;
+                self.summary_stale = true;
+                self.time_entry_running = false;
+                self.send_message(ThinkInkMessage::StopAnimation).await;
             }
             crate::BrokerMessage::TimeEntryTimeUpdated(update) => {
-                self.send_message(serde_json::json!({
-                    "kind": "adjustAnimationTime",
-                    "minutes": update.minutes,
-                }))
+                self.summary_stale = true;
+                self.send_message(ThinkInkMessage::AdjustAnimationTime {
+                    minutes: update.minutes,
+                })
                 .await;
             }
+            // Breaks stop the Toggl entry too, this also covers focus sessions without Toggl
+            crate::BrokerMessage::FocusPhaseStarted(phase_started) => match phase_started.phase {
+                crate::focus::Phase::Work => {
+                    self.send_message(ThinkInkMessage::StartAnimation).await;
+                }
+                crate::focus::Phase::ShortBreak | crate::focus::Phase::LongBreak => {
+                    self.send_message(ThinkInkMessage::StopAnimation).await;
+                }
+            },
+            crate::BrokerMessage::FocusSessionEnded => {
+                self.send_message(ThinkInkMessage::StopAnimation).await;
+            }
+            crate::BrokerMessage::NextCalendarEvent(next_event) => {
+                self.next_event = next_event;
+                self.next_event_stale = true;
+            }
+            crate::BrokerMessage::AlertRaised(alert) => {
+                self.alerts.insert(alert.name.clone(), alert);
+                self.alerts_stale = true;
+            }
+            crate::BrokerMessage::AlertCleared(name) => {
+                self.alerts_stale |= self.alerts.remove(&name).is_some();
+            }
             crate::BrokerMessage::ServoX(value) => {
-                self.send_message(serde_json::json!({
-                    "kind": "servoX",
-                    "targetValue": value,
-                    "speed": 5,
-                }))
+                self.send_message(ThinkInkMessage::ServoX {
+                    target_value: value,
+                    speed: 5,
+                })
                 .await;
             }
             crate::BrokerMessage::ServoY(value) => {
-                self.send_message(serde_json::json!({
-                    "kind": "servoY",
-                    "targetValue": value,
-                    "speed": 5,
-                }))
+                self.send_message(ThinkInkMessage::ServoY {
+                    target_value: value,
+                    speed: 5,
+                })
                 .await;
             }
             crate::BrokerMessage::StartFireworks => {
-                self.send_message(serde_json::json!({
-                    "kind": "startFireworks",
-                }))
-                .await;
+                self.send_message(ThinkInkMessage::StartFireworks).await;
             }
             crate::BrokerMessage::StopFireworks => {
-                self.send_message(serde_json::json!({
-                    "kind": "stopFireworks",
-                }))
-                .await;
+                self.send_message(ThinkInkMessage::StopFireworks).await;
             }
             _ => {}
         }
@@ -220,11 +345,10 @@ impl Message<UpdateLight> for ThinkInk {
         message: UpdateLight,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        self.send_message(serde_json::json!({
-            "kind": "light",
-            "targetValue": message.target_value,
-            "speed": message.speed,
-        }))
+        self.send_message(ThinkInkMessage::Light {
+            target_value: message.target_value,
+            speed: message.speed,
+        })
         .await;
     }
 }
@@ -239,44 +363,170 @@ impl Message<UpdateImage> for ThinkInk {
         _message: UpdateImage,
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
+        match self.panel {
+            ThinkInkPanel::Overview => self.update_overview().await,
+            ThinkInkPanel::TimeSummary => self.update_time_summary().await,
+        }
+    }
+}
+
+impl ThinkInk {
+    // Redrawn when the day rolls over, or when the next calendar event or the alerts change as long
+    // as the display hasn't just been refreshed
+    async fn update_overview(&mut self) {
         let now = chrono::Local::now();
 
         let current_date_string = format!("{}", now.format("%m-%d"));
 
-        if let Some(last_date_string) = self.last_date_string.as_ref()
-            && *last_date_string == current_date_string
-        {
+        let date_changed = self.last_date_string.as_ref() != Some(&current_date_string);
+        let next_event_due = (self.next_event_stale || self.alerts_stale)
+            && self
+                .last_refresh_at
+                .is_none_or(|at| at.elapsed() >= MINIMUM_REFRESH_INTERVAL);
+
+        if !date_changed && !next_event_due {
+            return;
+        }
+
+        self.last_refresh_at = Some(std::time::Instant::now());
+        self.next_event_stale = false;
+        self.alerts_stale = false;
+
+        let data = self
+            .raylib_manager_ref
+            .ask(crate::raylib_manager::RenderThinkInkImage {
+                weather_config: self.weather_config.clone(),
+                next_event: self.next_event.clone(),
+                alerts: self.alerts.values().cloned().collect(),
+            })
+            .await
+            .unwrap();
+
+        self.send_image(data).await;
+
+        self.last_date_string = Some(current_date_string);
+        tokio::fs::write("date.txt", self.last_date_string.as_ref().unwrap())
+            .await
+            .unwrap();
+    }
+
+    // Redrawn when the day rolls over or Toggl reports a change, and periodically while an
+    // entry is running so its time keeps counting
+    async fn update_time_summary(&mut self) {
+        let now = chrono::Local::now();
+
+        let current_date_string = format!("{}", now.format("%m-%d"));
+
+        let due = match self.last_refresh_at {
+            None => true,
+            Some(at) if at.elapsed() < MINIMUM_REFRESH_INTERVAL => false,
+            Some(at) => {
+                self.summary_stale
+                    || self.last_date_string.as_ref() != Some(&current_date_string)
+                    || (self.time_entry_running && at.elapsed() >= RUNNING_REFRESH_INTERVAL)
+            }
+        };
+
+        if !due {
             return;
         }
 
+        self.last_refresh_at = Some(std::time::Instant::now());
+
+        let Some(summary) = self.time_summary(now).await else {
+            return;
+        };
+
         let data = self
             .raylib_manager_ref
-            .ask(crate::raylib_manager::RenderThinkInkImage)
+            .ask(crate::raylib_manager::RenderTimeSummaryImage { summary })
             .await
             .unwrap();
 
+        self.send_image(data).await;
+
+        self.summary_stale = false;
+        self.last_date_string = Some(current_date_string);
+    }
+
+    async fn time_summary(&mut self, now: chrono::DateTime<chrono::Local>) -> Option<TimeSummary> {
+        let toggl_ref = self
+            .toggl_manager_ref
+            .as_ref()?
+            .ask(restarting_manager::GetChild)
+            .await
+            .ok()?;
+
+        let time_entries = match toggl_ref
+            .ask(toggl::GetTimeEntriesBetween {
+                start: crate::apps::thinkink_image::start_of_week(now).to_utc(),
+                end: (now + chrono::Duration::days(1)).to_utc(),
+            })
+            .await
+        {
+            Ok(time_entries) => time_entries,
+            Err(e) => {
+                tracing::warn!("! could not get time entries: {}", e);
+                return None;
+            }
+        };
+
+        // Archived projects just lose their name on the chart
+        let projects = toggl_ref.ask(toggl::GetProjects).await.unwrap_or_default();
+
+        self.time_entry_running = time_entries
+            .iter()
+            .any(|time_entry| time_entry.stop.is_none());
+
+        Some(TimeSummary::new(&time_entries, &projects, now))
+    }
+
+    async fn send_image(&mut self, data: Vec<u8>) {
         for (index, chunk_data) in data.as_slice().chunks(256).enumerate() {
-            self.send_message(serde_json::json!({
-                "kind": "displayData",
-                "offset": index * 256,
-                "data": BASE64_STANDARD.encode(chunk_data),
-            }))
+            self.send_message(ThinkInkMessage::DisplayData {
+                offset: index * 256,
+                data: BASE64_STANDARD.encode(chunk_data),
+            })
             .await;
         }
 
-        self.send_message(serde_json::json!({ "kind": "refreshDisplay", }))
-            .await;
+        self.send_message(ThinkInkMessage::RefreshDisplay).await;
+    }
 
-        self.last_date_string = Some(current_date_string);
-        tokio::fs::write("date.txt", self.last_date_string.as_ref().unwrap())
+    async fn send_message(&mut self, message: ThinkInkMessage) {
+        // A ThinkInk running incompatible code only hears about that, until it reconnects
+        let rejected = self.handshake == Handshake::Rejected
+            && !matches!(
+                message,
+                ThinkInkMessage::Hello { .. } | ThinkInkMessage::Error { .. }
+            );
+
+        if rejected {
+            tracing::debug!("! dropped message for incompatible thinkink: {:?}", message);
+            return;
+        }
+
+        tracing::info!("-> message: {:?}", message);
+        self.transmit
+            .send(serde_json::to_string(&message).unwrap())
             .await
             .unwrap();
     }
 }
 
-impl ThinkInk {
-    async fn send_message(&mut self, message: serde_json::Value) {
-        tracing::info!("-> message: {:?}", message);
-        self.transmit.send(message.to_string()).await.unwrap();
+async fn next_event(
+    calendar_manager_ref: &ActorRef<RestartingManager<calendar::Calendar>>,
+) -> Option<crate::CalendarEventUpcoming> {
+    let calendar_ref = calendar_manager_ref
+        .ask(restarting_manager::GetChild)
+        .await
+        .ok()?;
+
+    match calendar_ref.ask(calendar::GetNextEvent).await {
+        Ok(next_event) => next_event,
+        Err(e) => {
+            tracing::warn!("! could not get the next calendar event: {}", e);
+            None
+        }
     }
 }
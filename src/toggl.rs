@@ -1,7 +1,135 @@
 use base64::prelude::*;
-use kameo::error::Infallible;
 use kameo::prelude::*;
 use kameo_actors::broker;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::protocol::ErrorReason;
+use crate::state_file;
+
+mod models;
+
+pub use models::{Project, Tag, TimeEntry, Workspace};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TogglConfig {
+    pub auth: String,
+    pub workspace_id: i64,
+    pub project_id: i64,
+    #[serde(default = "default_queue_path")]
+    pub queue_path: String,
+}
+
+fn default_queue_path() -> String {
+    "toggl-queue.json".to_string()
+}
+
+// What a new time entry is created with; no project falls back to the configured one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeEntryDetails {
+    pub description: String,
+    #[serde(default)]
+    pub project_id: Option<i64>,
+    #[serde(default)]
+    pub tag_ids: Vec<i64>,
+}
+
+impl From<&TimeEntry> for TimeEntryDetails {
+    fn from(time_entry: &TimeEntry) -> Self {
+        Self {
+            description: time_entry.description().to_string(),
+            project_id: time_entry.project_id,
+            tag_ids: time_entry.tag_ids.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Operations made while Toggl was unreachable, replayed in order with their original timestamps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum PendingOperation {
+    Start {
+        #[serde(flatten)]
+        details: TimeEntryDetails,
+        start: chrono::DateTime<chrono::Utc>,
+    },
+    Stop {
+        stop: chrono::DateTime<chrono::Utc>,
+    },
+    Adjust {
+        minutes: i64,
+        requested_at: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+#[derive(Debug)]
+pub enum TogglError {
+    Unauthorized,
+    RateLimited,
+    NotFound,
+    Network(String),
+    Rejected(String),
+    Conflict(String),
+    Broker,
+}
+
+impl std::fmt::Display for TogglError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TogglError::Unauthorized => write!(f, "unauthorized"),
+            TogglError::RateLimited => write!(f, "rate limited"),
+            TogglError::NotFound => write!(f, "not found"),
+            TogglError::Network(e) => write!(f, "network error: {}", e),
+            TogglError::Rejected(e) => write!(f, "rejected: {}", e),
+            TogglError::Conflict(reason) => write!(f, "conflict: {}", reason),
+            TogglError::Broker => write!(f, "could not publish to broker"),
+        }
+    }
+}
+
+impl std::error::Error for TogglError {}
+
+impl From<reqwest::Error> for TogglError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => {
+                TogglError::Unauthorized
+            }
+            Some(reqwest::StatusCode::TOO_MANY_REQUESTS) => TogglError::RateLimited,
+            Some(reqwest::StatusCode::NOT_FOUND) => TogglError::NotFound,
+            Some(status) if status.is_server_error() => TogglError::Network(e.to_string()),
+            Some(_) => TogglError::Rejected(e.to_string()),
+            None if e.is_connect() || e.is_timeout() || e.is_request() => {
+                TogglError::Network(e.to_string())
+            }
+            None => TogglError::Rejected(e.to_string()),
+        }
+    }
+}
+
+impl TogglError {
+    // Worth queueing and retrying later, as opposed to a request Toggl rejected outright
+    fn is_retryable(&self) -> bool {
+        matches!(self, TogglError::Network(_) | TogglError::RateLimited)
+    }
+
+    pub fn reason(&self) -> ErrorReason {
+        match self {
+            TogglError::Unauthorized => ErrorReason::Unauthorized,
+            TogglError::RateLimited => ErrorReason::RateLimited,
+            TogglError::NotFound => ErrorReason::NotFound,
+            TogglError::Network(_) => ErrorReason::Unavailable,
+            TogglError::Conflict(_) => ErrorReason::Conflict,
+            TogglError::Rejected(_) | TogglError::Broker => ErrorReason::RequestFailed,
+        }
+    }
+}
 
 pub struct Toggl {
     client: reqwest::Client,
@@ -9,22 +137,24 @@ pub struct Toggl {
     workspace_id: i64,
     project_id: i64,
     broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
-    current_time_entry: Option<serde_json::Value>,
+    current_time_entry: Option<TimeEntry>,
+    last_details: Option<TimeEntryDetails>,
+    queue_path: String,
+    queue: VecDeque<PendingOperation>,
 }
 
-#[derive(Debug)]
-pub struct TogglError;
-
 impl Actor for Toggl {
-    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>,);
-    type Error = Infallible;
+    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>, TogglConfig);
+    type Error = state_file::StateFileError;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
+
         let mut headers = reqwest::header::HeaderMap::new();
 
         let mut authorization = reqwest::header::HeaderValue::from_str(&format!(
             "Basic {}",
-            BASE64_STANDARD.encode(std::env::var("TOGGL_AUTH").unwrap())
+            BASE64_STANDARD.encode(&config.auth)
         ))
         .unwrap();
 
@@ -37,9 +167,29 @@ impl Actor for Toggl {
             .build()
             .unwrap();
 
-        tokio::time::sleep(std::time::Duration::from_secs(30 * 60)).await;
+        let queue: VecDeque<PendingOperation> = state_file::load(&config.queue_path).await?;
+
+        if !queue.is_empty() {
+            tracing::info!("{} queued toggl operations", queue.len());
+        }
+
+        let replay_actor_ref = actor_ref.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+
+                if replay_actor_ref.tell(ReplayQueue).await.is_err() {
+                    break;
+                }
+            }
+        });
 
         tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_secs(30 * 60)).await;
+
             let mut interval = tokio::time::interval(std::time::Duration::from_secs(30 * 60));
 
             loop {
@@ -54,12 +204,13 @@ impl Actor for Toggl {
         Ok(Self {
             client,
             base_url: reqwest::Url::parse("https://api.track.toggl.com").unwrap(),
-            workspace_id: i64::from_str_radix(&std::env::var("TOGGL_WORKSPACE_ID").unwrap(), 10)
-                .unwrap(),
-            project_id: i64::from_str_radix(&std::env::var("TOGGL_PROJECT_ID").unwrap(), 10)
-                .unwrap(),
-            broker_ref: state.0,
+            workspace_id: config.workspace_id,
+            project_id: config.project_id,
+            broker_ref,
             current_time_entry: None,
+            last_details: None,
+            queue_path: config.queue_path,
+            queue,
         })
     }
 }
@@ -67,7 +218,7 @@ impl Actor for Toggl {
 pub struct GetTimeEntries;
 
 impl Message<GetTimeEntries> for Toggl {
-    type Reply = Result<serde_json::Value, reqwest::Error>;
+    type Reply = Result<Vec<TimeEntry>, TogglError>;
 
     async fn handle(
         &mut self,
@@ -75,21 +226,95 @@ impl Message<GetTimeEntries> for Toggl {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("Get time entries");
-        let result = self
-            .client
-            .get(self.base_url.join("/api/v9/me/time_entries").unwrap())
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<serde_json::Value>()
-            .await?;
+        self.get("/api/v9/me/time_entries").await
+    }
+}
+
+// The plain time entries endpoint only covers recent history, so summaries ask for a range
+pub struct GetTimeEntriesBetween {
+    pub start: chrono::DateTime<chrono::Utc>,
+    pub end: chrono::DateTime<chrono::Utc>,
+}
+
+impl Message<GetTimeEntriesBetween> for Toggl {
+    type Reply = Result<Vec<TimeEntry>, TogglError>;
+
+    async fn handle(
+        &mut self,
+        message: GetTimeEntriesBetween,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!(
+            "Get time entries between {} and {}",
+            message.start,
+            message.end
+        );
+        self.get(&format!(
+            "/api/v9/me/time_entries?start_date={}&end_date={}",
+            message
+                .start
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            message
+                .end
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ))
+        .await
+    }
+}
+
+pub struct GetProjects;
+
+impl Message<GetProjects> for Toggl {
+    type Reply = Result<Vec<Project>, TogglError>;
+
+    async fn handle(
+        &mut self,
+        _message: GetProjects,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!("Get projects");
+        self.get(&format!(
+            "/api/v9/workspaces/{}/projects?active=true",
+            self.workspace_id
+        ))
+        .await
+    }
+}
+
+pub struct GetTags;
+
+impl Message<GetTags> for Toggl {
+    type Reply = Result<Vec<Tag>, TogglError>;
+
+    async fn handle(
+        &mut self,
+        _message: GetTags,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!("Get tags");
+        self.get(&format!("/api/v9/workspaces/{}/tags", self.workspace_id))
+            .await
+    }
+}
+
+pub struct GetWorkspace;
+
+impl Message<GetWorkspace> for Toggl {
+    type Reply = Result<Workspace, TogglError>;
 
-        Ok(result)
+    async fn handle(
+        &mut self,
+        _message: GetWorkspace,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!("Get workspace");
+        self.get(&format!("/api/v9/workspaces/{}", self.workspace_id))
+            .await
     }
 }
 
 pub struct StartTimeEntry {
-    pub description: String,
+    pub details: TimeEntryDetails,
 }
 
 impl Message<StartTimeEntry> for Toggl {
@@ -101,46 +326,18 @@ impl Message<StartTimeEntry> for Toggl {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("Start time entry");
-        let time_entry = self
-            .client
-            .post(
-                self.base_url
-                    .join(&format!(
-                        "/api/v9/workspaces/{}/time_entries",
-                        &self.workspace_id
-                    ))
-                    .unwrap(),
-            )
-            .json(&serde_json::json!({
-                "created_with": "desk",
-                "project_id": self.project_id,
-                "workspace_id": self.workspace_id,
-                "start": chrono::Utc::now().to_rfc3339(),
-                "duration": -1,
-                "description": message.description,
-            }))
-            .send()
-            .await
-            .map_err(|_| TogglError)?
-            .error_for_status()
-            .map_err(|_| TogglError)?
-            .json::<serde_json::Value>()
-            .await
-            .map_err(|_| TogglError)?;
-
-        self.current_time_entry = Some(time_entry.clone());
+        let description = message.details.description.clone();
 
-        self.broker_ref
-            .tell(broker::Publish {
-                topic: "toggl".parse().unwrap(),
-                message: crate::BrokerMessage::TimeEntryStarted(crate::TimeEntryStarted {
-                    description: message.description,
-                }),
-            })
-            .await
-            .map_err(|_| TogglError)?;
+        self.apply_or_enqueue(PendingOperation::Start {
+            details: message.details,
+            start: chrono::Utc::now(),
+        })
+        .await?;
 
-        Ok(())
+        self.publish(crate::BrokerMessage::TimeEntryStarted(
+            crate::TimeEntryStarted { description },
+        ))
+        .await
     }
 }
 
@@ -155,53 +352,33 @@ impl Message<StopTimeEntry> for Toggl {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("Stop time entry");
-        let current_time_entry = self
-            .get_current_time_entry()
-            .await
-            .map_err(|_| TogglError)?;
-
-        if current_time_entry.is_null() {
-            self.current_time_entry = None;
-
-            self.broker_ref
-                .tell(broker::Publish {
-                    topic: "toggl".parse().unwrap(),
-                    message: crate::BrokerMessage::TimeEntryStopped,
-                })
-                .await
-                .map_err(|_| TogglError)?;
+        self.apply_or_enqueue(PendingOperation::Stop {
+            stop: chrono::Utc::now(),
+        })
+        .await?;
 
-            return Ok(());
-        }
+        self.publish(crate::BrokerMessage::TimeEntryStopped).await
+    }
+}
 
-        self.client
-            .patch(
-                self.base_url
-                    .join(&format!(
-                        "/api/v9/workspaces/{}/time_entries/{}/stop",
-                        &self.workspace_id,
-                        current_time_entry["id"].as_i64().unwrap()
-                    ))
-                    .unwrap(),
-            )
-            .json(&serde_json::json!({}))
-            .send()
-            .await
-            .map_err(|_| TogglError)?
-            .error_for_status()
-            .map_err(|_| TogglError)?;
+// Stops the running entry at an earlier time, e.g. when it was left running by mistake
+pub struct StopTimeEntryAt {
+    pub stop: chrono::DateTime<chrono::Utc>,
+}
 
-        self.current_time_entry = None;
+impl Message<StopTimeEntryAt> for Toggl {
+    type Reply = Result<(), TogglError>;
 
-        self.broker_ref
-            .tell(broker::Publish {
-                topic: "toggl".parse().unwrap(),
-                message: crate::BrokerMessage::TimeEntryStopped,
-            })
-            .await
-            .map_err(|_| TogglError)?;
+    async fn handle(
+        &mut self,
+        message: StopTimeEntryAt,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        tracing::info!("Stop time entry at {}", message.stop);
+        self.apply_or_enqueue(PendingOperation::Stop { stop: message.stop })
+            .await?;
 
-        Ok(())
+        self.publish(crate::BrokerMessage::TimeEntryStopped).await
     }
 }
 
@@ -216,62 +393,34 @@ impl Message<ContinueTimeEntry> for Toggl {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("Continue time entry");
-        let result = self
-            .client
-            .get(self.base_url.join("/api/v9/me/time_entries").unwrap())
-            .send()
-            .await
-            .map_err(|_| TogglError)?
-            .error_for_status()
-            .map_err(|_| TogglError)?
-            .json::<serde_json::Value>()
-            .await
-            .map_err(|_| TogglError)?;
-
-        let entry = result.as_array().unwrap().first().unwrap();
-
-        let description = entry["description"].as_str().unwrap();
-
-        let time_entry = self
-            .client
-            .post(
-                self.base_url
-                    .join(&format!(
-                        "/api/v9/workspaces/{}/time_entries",
-                        &self.workspace_id
-                    ))
-                    .unwrap(),
-            )
-            .json(&serde_json::json!({
-                "created_with": "desk",
-                "project_id": self.project_id,
-                "workspace_id": self.workspace_id,
-                "start": chrono::Utc::now().to_rfc3339(),
-                "duration": -1,
-                "description": description,
-            }))
-            .send()
-            .await
-            .map_err(|_| TogglError)?
-            .error_for_status()
-            .map_err(|_| TogglError)?
-            .json::<serde_json::Value>()
-            .await
-            .map_err(|_| TogglError)?;
-
-        self.current_time_entry = Some(time_entry.clone());
-
-        self.broker_ref
-            .tell(broker::Publish {
-                topic: "toggl".parse().unwrap(),
-                message: crate::BrokerMessage::TimeEntryStarted(crate::TimeEntryStarted {
-                    description: description.to_string(),
-                }),
-            })
-            .await
-            .map_err(|_| TogglError)?;
+        let latest_details = if self.queue.is_empty() {
+            match self.get::<Vec<TimeEntry>>("/api/v9/me/time_entries").await {
+                Ok(time_entries) => Some(TimeEntryDetails::from(
+                    time_entries.first().ok_or(TogglError::NotFound)?,
+                )),
+                Err(e) if e.is_retryable() => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+
+        // Offline, continue whatever this hub last saw running
+        let details = latest_details
+            .or_else(|| self.last_details.clone())
+            .ok_or(TogglError::NotFound)?;
+        let description = details.description.clone();
+
+        self.apply_or_enqueue(PendingOperation::Start {
+            details,
+            start: chrono::Utc::now(),
+        })
+        .await?;
 
-        Ok(())
+        self.publish(crate::BrokerMessage::TimeEntryStarted(
+            crate::TimeEntryStarted { description },
+        ))
+        .await
     }
 }
 
@@ -279,14 +428,8 @@ pub struct AdjustTime {
     pub minutes: i64,
 }
 
-#[derive(Debug)]
-pub enum AdjustTimeError {
-    NotFound,
-    RequestError,
-}
-
 impl Message<AdjustTime> for Toggl {
-    type Reply = Result<(), AdjustTimeError>;
+    type Reply = Result<(), TogglError>;
 
     async fn handle(
         &mut self,
@@ -294,63 +437,84 @@ impl Message<AdjustTime> for Toggl {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("Adjust time: {:?}", message.minutes);
-        let current_time_entry = self
-            .get_current_time_entry()
-            .await
-            .map_err(|_error| AdjustTimeError::RequestError)?;
+        self.apply_or_enqueue(PendingOperation::Adjust {
+            minutes: message.minutes,
+            requested_at: chrono::Utc::now(),
+        })
+        .await?;
 
-        if current_time_entry.is_null() {
-            return Err(AdjustTimeError::NotFound);
-        }
+        self.publish(crate::BrokerMessage::TimeEntryTimeUpdated(
+            crate::TimeEntryTimeUpdated {
+                minutes: message.minutes,
+            },
+        ))
+        .await
+    }
+}
 
-        let current_start =
-            chrono::DateTime::parse_from_rfc3339(current_time_entry["start"].as_str().unwrap())
-                .unwrap();
-        let updated_start = current_start - chrono::Duration::minutes(message.minutes);
+pub struct ReplayQueue;
 
-        let time_entry = self
-            .client
-            .put(
-                self.base_url
-                    .join(&format!(
-                        "/api/v9/workspaces/{}/time_entries/{}",
-                        &self.workspace_id,
-                        current_time_entry["id"].as_i64().unwrap()
+impl Message<ReplayQueue> for Toggl {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: ReplayQueue,
+        context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if self.queue.is_empty() {
+            return;
+        }
+
+        while let Some(operation) = self.queue.front().cloned() {
+            match self.apply(&operation).await {
+                Ok(()) => tracing::info!("Replayed {:?}", operation),
+                Err(e) if e.is_retryable() => {
+                    tracing::warn!("! toggl still unreachable: {}", e);
+                    return;
+                }
+                Err(TogglError::Conflict(reason)) => {
+                    self.notify(format!(
+                        "Toggl skipped queued {}: {}",
+                        operation.name(),
+                        reason
                     ))
-                    .unwrap(),
-            )
-            .json(&serde_json::json!({
-                "start": updated_start.to_rfc3339()
-            }))
-            .send()
-            .await
-            .map_err(|_error| AdjustTimeError::RequestError)?
-            .error_for_status()
-            .map_err(|_error| AdjustTimeError::RequestError)?
-            .json::<serde_json::Value>()
-            .await
-            .map_err(|_error| AdjustTimeError::RequestError)?;
+                    .await;
+                }
+                Err(e) => {
+                    self.notify(format!("Toggl rejected queued {}: {}", operation.name(), e))
+                        .await;
+                }
+            }
 
-        self.current_time_entry = Some(time_entry.clone());
+            self.queue.pop_front();
+            self.save_queue().await;
+        }
 
-        self.broker_ref
-            .tell(broker::Publish {
-                topic: "toggl".parse().unwrap(),
-                message: crate::BrokerMessage::TimeEntryTimeUpdated(crate::TimeEntryTimeUpdated {
-                    minutes: message.minutes,
-                }),
-            })
-            .await
-            .map_err(|_| AdjustTimeError::RequestError)?;
+        // The server is authoritative again, so let the usual sync correct the displays
+        let _ = context.actor_ref().tell(GetCurrentTimeEntry).try_send();
+    }
+}
 
-        Ok(())
+// The entry this hub last saw running, without asking Toggl
+pub struct GetRunningTimeEntry;
+
+impl Message<GetRunningTimeEntry> for Toggl {
+    type Reply = Option<TimeEntry>;
+
+    async fn handle(
+        &mut self,
+        _message: GetRunningTimeEntry,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.current_time_entry.clone()
     }
 }
 
 pub struct GetCurrentTimeEntry;
 
 impl Message<GetCurrentTimeEntry> for Toggl {
-    type Reply = Result<serde_json::Value, TogglError>;
+    type Reply = Result<Option<TimeEntry>, TogglError>;
 
     async fn handle(
         &mut self,
@@ -358,109 +522,451 @@ impl Message<GetCurrentTimeEntry> for Toggl {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         tracing::info!("Get current time entry");
-        let new_time_entry = self
-            .get_current_time_entry()
-            .await
-            .map_err(|_| TogglError)?;
+        if !self.queue.is_empty() {
+            return Err(TogglError::Conflict(format!(
+                "{} operations are still queued",
+                self.queue.len()
+            )));
+        }
 
-        let current_date = chrono::Utc::now().fixed_offset();
+        let new_time_entry = self.get_current_time_entry().await?;
 
-        if self.current_time_entry.is_none() {
-            if new_time_entry.is_null() {
+        match (&self.current_time_entry, &new_time_entry) {
+            (None, None) => {
                 tracing::info!("Nothing to do");
-            } else {
+            }
+            (None, Some(new_time_entry)) => {
                 tracing::info!("Switching to new time entry");
-                self.broker_ref
-                    .tell(broker::Publish {
-                        topic: "toggl".parse().unwrap(),
-                        message: crate::BrokerMessage::TimeEntryStarted(crate::TimeEntryStarted {
-                            description: new_time_entry["description"]
-                                .as_str()
-                                .unwrap()
-                                .to_string(),
-                        }),
-                    })
-                    .await
-                    .map_err(|_| TogglError)?;
-
-                let new_start =
-                    chrono::DateTime::parse_from_rfc3339(new_time_entry["start"].as_str().unwrap())
-                        .unwrap();
-
-                self.broker_ref
-                    .tell(broker::Publish {
-                        topic: "toggl".parse().unwrap(),
-                        message: crate::BrokerMessage::TimeEntryTimeUpdated(
-                            crate::TimeEntryTimeUpdated {
-                                minutes: (current_date - new_start).num_minutes() as i64,
-                            },
-                        ),
-                    })
-                    .await
-                    .map_err(|_| TogglError)?;
+                self.publish(crate::BrokerMessage::TimeEntryStarted(
+                    crate::TimeEntryStarted {
+                        description: new_time_entry.description().to_string(),
+                    },
+                ))
+                .await?;
+
+                self.publish(crate::BrokerMessage::TimeEntryTimeUpdated(
+                    crate::TimeEntryTimeUpdated {
+                        minutes: (chrono::Utc::now() - new_time_entry.start).num_minutes(),
+                    },
+                ))
+                .await?;
             }
-        } else {
-            if new_time_entry.is_null() {
+            (Some(_), None) => {
                 tracing::info!("Stopping time entry");
-                self.broker_ref
-                    .tell(broker::Publish {
-                        topic: "toggl".parse().unwrap(),
-                        message: crate::BrokerMessage::TimeEntryStopped,
-                    })
-                    .await
-                    .map_err(|_| TogglError)?;
-            } else {
+                self.publish(crate::BrokerMessage::TimeEntryStopped).await?;
+            }
+            (Some(current_time_entry), Some(new_time_entry)) => {
                 tracing::info!("Updating time entry");
-                let current_time_entry = self.current_time_entry.as_ref().unwrap();
-
-                let current_start = chrono::DateTime::parse_from_rfc3339(
-                    current_time_entry["start"].as_str().unwrap(),
-                )
-                .unwrap();
-
-                let new_start =
-                    chrono::DateTime::parse_from_rfc3339(new_time_entry["start"].as_str().unwrap())
-                        .unwrap();
-
-                self.broker_ref
-                    .tell(broker::Publish {
-                        topic: "toggl".parse().unwrap(),
-                        message: crate::BrokerMessage::TimeEntryTimeUpdated(
-                            crate::TimeEntryTimeUpdated {
-                                minutes: (current_start - new_start).num_minutes() as i64,
-                            },
-                        ),
-                    })
-                    .await
-                    .map_err(|_| TogglError)?;
+                self.publish(crate::BrokerMessage::TimeEntryTimeUpdated(
+                    crate::TimeEntryTimeUpdated {
+                        minutes: (current_time_entry.start - new_time_entry.start).num_minutes(),
+                    },
+                ))
+                .await?;
             }
         }
 
-        if new_time_entry.is_null() {
-            self.current_time_entry = None;
-        } else {
-            self.current_time_entry = Some(new_time_entry.clone());
+        if let Some(new_time_entry) = &new_time_entry {
+            self.last_details = Some(TimeEntryDetails::from(new_time_entry));
         }
 
+        self.current_time_entry = new_time_entry.clone();
+
         Ok(new_time_entry)
     }
 }
 
+// The requests an operation comes down to, given the entry running on Toggl when it's applied
+#[derive(Debug, PartialEq)]
+enum Step<'a> {
+    // Something else was started after this entry, so it only ran until then
+    Backfill {
+        details: &'a TimeEntryDetails,
+        start: chrono::DateTime<chrono::Utc>,
+        stop: chrono::DateTime<chrono::Utc>,
+    },
+    Start {
+        details: &'a TimeEntryDetails,
+        start: chrono::DateTime<chrono::Utc>,
+        stop_running: Option<i64>,
+    },
+    // Nothing to stop when nothing is running
+    Stop {
+        running: Option<i64>,
+        stop: chrono::DateTime<chrono::Utc>,
+    },
+    MoveStart {
+        id: i64,
+        start: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+impl PendingOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            PendingOperation::Start { .. } => "start",
+            PendingOperation::Stop { .. } => "stop",
+            PendingOperation::Adjust { .. } => "adjustment",
+        }
+    }
+
+    fn step(&self, current_time_entry: Option<&TimeEntry>) -> Result<Step<'_>, TogglError> {
+        match self {
+            PendingOperation::Start { details, start } => Ok(match current_time_entry {
+                Some(current_time_entry) if current_time_entry.start > *start => Step::Backfill {
+                    details,
+                    start: *start,
+                    stop: current_time_entry.start,
+                },
+                current_time_entry => Step::Start {
+                    details,
+                    start: *start,
+                    stop_running: current_time_entry.map(|time_entry| time_entry.id),
+                },
+            }),
+            PendingOperation::Stop { stop } => match current_time_entry {
+                Some(current_time_entry) if current_time_entry.start > *stop => {
+                    Err(TogglError::Conflict(format!(
+                        "{:?} was started after it",
+                        current_time_entry.description()
+                    )))
+                }
+                current_time_entry => Ok(Step::Stop {
+                    running: current_time_entry.map(|time_entry| time_entry.id),
+                    stop: *stop,
+                }),
+            },
+            PendingOperation::Adjust {
+                minutes,
+                requested_at,
+            } => {
+                let Some(current_time_entry) = current_time_entry else {
+                    return Err(TogglError::NotFound);
+                };
+
+                if current_time_entry.start > *requested_at {
+                    return Err(TogglError::Conflict(
+                        "the adjusted time entry is no longer running".to_string(),
+                    ));
+                }
+
+                Ok(Step::MoveStart {
+                    id: current_time_entry.id,
+                    start: current_time_entry.start - chrono::Duration::minutes(*minutes),
+                })
+            }
+        }
+    }
+}
+
 impl Toggl {
-    async fn get_current_time_entry(&self) -> Result<serde_json::Value, reqwest::Error> {
-        let result = self
+    // Runs the operation now unless Toggl is unreachable or older operations are still waiting,
+    // in which case it joins the queue so replay keeps the original order
+    async fn apply_or_enqueue(&mut self, operation: PendingOperation) -> Result<(), TogglError> {
+        if self.queue.is_empty() {
+            match self.apply(&operation).await {
+                Err(e) if e.is_retryable() => {
+                    tracing::warn!("! toggl unreachable, queueing {}: {}", operation.name(), e);
+                }
+                result => return result,
+            }
+        }
+
+        if let PendingOperation::Start { details, .. } = &operation {
+            self.last_details = Some(details.clone());
+        }
+
+        self.queue.push_back(operation);
+        self.save_queue().await;
+
+        Ok(())
+    }
+
+    async fn apply(&mut self, operation: &PendingOperation) -> Result<(), TogglError> {
+        let current_time_entry = self.get_current_time_entry().await?;
+
+        match operation.step(current_time_entry.as_ref())? {
+            Step::Backfill {
+                details,
+                start,
+                stop,
+            } => {
+                self.create_time_entry(details, start, Some(stop)).await?;
+            }
+            Step::Start {
+                details,
+                start,
+                stop_running,
+            } => {
+                if let Some(id) = stop_running {
+                    self.update_time_entry(id, serde_json::json!({ "stop": start.to_rfc3339() }))
+                        .await?;
+                }
+
+                let time_entry = self.create_time_entry(details, start, None).await?;
+
+                self.last_details = Some(details.clone());
+                self.current_time_entry = Some(time_entry);
+            }
+            Step::Stop { running, stop } => {
+                if let Some(id) = running {
+                    self.update_time_entry(id, serde_json::json!({ "stop": stop.to_rfc3339() }))
+                        .await?;
+                }
+
+                self.current_time_entry = None;
+            }
+            Step::MoveStart { id, start } => {
+                let time_entry = self
+                    .update_time_entry(id, serde_json::json!({ "start": start.to_rfc3339() }))
+                    .await?;
+
+                self.current_time_entry = Some(time_entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn create_time_entry(
+        &self,
+        details: &TimeEntryDetails,
+        start: chrono::DateTime<chrono::Utc>,
+        stop: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<TimeEntry, TogglError> {
+        let mut body = serde_json::json!({
+            "created_with": "desk",
+            "project_id": details.project_id.unwrap_or(self.project_id),
+            "workspace_id": self.workspace_id,
+            "start": start.to_rfc3339(),
+            "duration": -1,
+            "description": details.description,
+            "tag_ids": details.tag_ids,
+        });
+
+        if let Some(stop) = stop {
+            body["stop"] = serde_json::json!(stop.to_rfc3339());
+            body["duration"] = serde_json::json!((stop - start).num_seconds());
+        }
+
+        Ok(self
+            .client
+            .post(
+                self.base_url
+                    .join(&format!(
+                        "/api/v9/workspaces/{}/time_entries",
+                        &self.workspace_id
+                    ))
+                    .unwrap(),
+            )
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TimeEntry>()
+            .await?)
+    }
+
+    async fn update_time_entry(
+        &self,
+        id: i64,
+        body: serde_json::Value,
+    ) -> Result<TimeEntry, TogglError> {
+        Ok(self
             .client
-            .get(
+            .put(
                 self.base_url
-                    .join("/api/v9/me/time_entries/current")
+                    .join(&format!(
+                        "/api/v9/workspaces/{}/time_entries/{}",
+                        &self.workspace_id, id
+                    ))
                     .unwrap(),
             )
+            .json(&body)
             .send()
             .await?
             .error_for_status()?
-            .json::<serde_json::Value>()
-            .await?;
+            .json::<TimeEntry>()
+            .await?)
+    }
+
+    async fn get_current_time_entry(&self) -> Result<Option<TimeEntry>, TogglError> {
+        self.get("/api/v9/me/time_entries/current").await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, TogglError> {
+        Ok(self
+            .client
+            .get(self.base_url.join(path).unwrap())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await?)
+    }
+
+    async fn publish(&self, message: crate::BrokerMessage) -> Result<(), TogglError> {
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "toggl".parse().unwrap(),
+                message,
+            })
+            .await
+            .map_err(|_| TogglError::Broker)
+    }
+
+    async fn save_queue(&self) {
+        if let Err(e) = self.write_queue().await {
+            tracing::error!("! could not save toggl queue to {}: {}", self.queue_path, e);
+        }
+    }
+
+    async fn write_queue(&self) -> std::io::Result<()> {
+        state_file::save_pretty(&self.queue_path, &self.queue).await
+    }
+
+    async fn notify(&self, text: String) {
+        tracing::warn!("! {}", text);
+
+        let _ = self
+            .broker_ref
+            .tell(broker::Publish {
+                topic: "message".parse().unwrap(),
+                message: crate::BrokerMessage::Message(crate::Message {
+                    text,
+                    effects: vec![],
+                    read: false,
+                    source: "toggl".to_string(),
+                }),
+            })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(1_700_000_000 + minutes * 60, 0).unwrap()
+    }
+
+    fn running(id: i64, start: chrono::DateTime<chrono::Utc>) -> TimeEntry {
+        TimeEntry {
+            id,
+            workspace_id: 1,
+            project_id: None,
+            description: Some("Email".to_string()),
+            tag_ids: None,
+            start,
+            stop: None,
+            duration: -1,
+        }
+    }
+
+    fn details() -> TimeEntryDetails {
+        TimeEntryDetails {
+            description: "Review".to_string(),
+            project_id: None,
+            tag_ids: vec![],
+        }
+    }
+
+    #[test]
+    fn queued_start_stops_what_ran_before_it() {
+        let details = details();
+        let operation = PendingOperation::Start {
+            details: details.clone(),
+            start: at(10),
+        };
+
+        assert_eq!(
+            operation.step(Some(&running(7, at(0)))).unwrap(),
+            Step::Start {
+                details: &details,
+                start: at(10),
+                stop_running: Some(7),
+            }
+        );
+        assert_eq!(
+            operation.step(None).unwrap(),
+            Step::Start {
+                details: &details,
+                start: at(10),
+                stop_running: None,
+            }
+        );
+    }
+
+    #[test]
+    fn queued_start_is_backfilled_under_a_later_entry() {
+        let details = details();
+        let operation = PendingOperation::Start {
+            details: details.clone(),
+            start: at(10),
+        };
+
+        assert_eq!(
+            operation.step(Some(&running(7, at(20)))).unwrap(),
+            Step::Backfill {
+                details: &details,
+                start: at(10),
+                stop: at(20),
+            }
+        );
+    }
+
+    #[test]
+    fn queued_stop_conflicts_with_a_later_entry() {
+        let operation = PendingOperation::Stop { stop: at(10) };
+
+        assert_eq!(
+            operation.step(Some(&running(7, at(0)))).unwrap(),
+            Step::Stop {
+                running: Some(7),
+                stop: at(10),
+            }
+        );
+        assert_eq!(
+            operation.step(None).unwrap(),
+            Step::Stop {
+                running: None,
+                stop: at(10),
+            }
+        );
+        assert!(matches!(
+            operation.step(Some(&running(7, at(20)))),
+            Err(TogglError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn queued_adjustment_needs_the_same_entry_running() {
+        let operation = PendingOperation::Adjust {
+            minutes: 5,
+            requested_at: at(10),
+        };
+
+        assert_eq!(
+            operation.step(Some(&running(7, at(0)))).unwrap(),
+            Step::MoveStart {
+                id: 7,
+                start: at(-5),
+            }
+        );
+        assert!(matches!(operation.step(None), Err(TogglError::NotFound)));
+        assert!(matches!(
+            operation.step(Some(&running(8, at(20)))),
+            Err(TogglError::Conflict(_))
+        ));
+    }
 
-        Ok(result)
+    #[test]
+    fn only_network_errors_and_rate_limits_stay_queued() {
+        assert!(TogglError::Network("timed out".to_string()).is_retryable());
+        assert!(TogglError::RateLimited.is_retryable());
+        assert!(!TogglError::Conflict("started after it".to_string()).is_retryable());
+        assert!(!TogglError::Rejected("400".to_string()).is_retryable());
+        assert!(!TogglError::Unauthorized.is_retryable());
     }
 }
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+// Subsets of the Toggl Track v9 API objects; fields the desk doesn't use are ignored
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeEntry {
+    pub id: i64,
+    pub workspace_id: i64,
+    #[serde(default)]
+    pub project_id: Option<i64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tag_ids: Option<Vec<i64>>,
+    pub start: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub stop: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration: i64,
+}
+
+impl TimeEntry {
+    pub fn description(&self) -> &str {
+        self.description.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub id: i64,
+    pub workspace_id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: i64,
+    pub workspace_id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Workspace {
+    pub id: i64,
+    pub name: String,
+}
@@ -0,0 +1,396 @@
+use futures::SinkExt;
+use futures::stream::{BoxStream, SplitSink, StreamExt};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_serial::{SerialPort, SerialPortBuilderExt};
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+
+use crate::config::ConfigError;
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type LineStream = BoxStream<'static, Result<String, LinesCodecError>>;
+
+#[async_trait::async_trait]
+pub trait Sink: Send {
+    async fn send(&mut self, message: String) -> Result<(), TransportError>;
+}
+
+pub struct FramedSink<T> {
+    inner: SplitSink<Framed<T, LinesCodec>, String>,
+}
+
+#[async_trait::async_trait]
+impl<T> Sink for FramedSink<T>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn send(&mut self, message: String) -> Result<(), TransportError> {
+        self.inner
+            .send(message)
+            .await
+            .map_err(|e| Box::new(e) as TransportError)
+    }
+}
+
+pub struct DummySink;
+
+#[async_trait::async_trait]
+impl Sink for DummySink {
+    async fn send(&mut self, message: String) -> Result<(), TransportError> {
+        tracing::info!("-> dummy send: {}", message);
+        Ok(())
+    }
+}
+
+pub struct ChannelSink {
+    inner: tokio::sync::mpsc::Sender<String>,
+}
+
+#[async_trait::async_trait]
+impl Sink for ChannelSink {
+    async fn send(&mut self, message: String) -> Result<(), TransportError> {
+        self.inner
+            .send(message)
+            .await
+            .map_err(|e| Box::new(e) as TransportError)
+    }
+}
+
+pub struct Connection {
+    pub transmit: Box<dyn Sink>,
+    pub receive: LineStream,
+}
+
+// What a supervised connection hands the device's actor; read errors end in a reconnect instead
+#[derive(Debug, Clone, PartialEq)]
+pub enum Received {
+    // Sent before any line from a new connection
+    Connected,
+    Line(String),
+}
+
+pub struct Link {
+    pub transmit: Box<dyn Sink>,
+    pub receive: BoxStream<'static, Received>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub name: &'static str,
+    pub baud_rate: u32,
+    pub usb_vid: u16,
+    pub usb_pid: u16,
+    pub heartbeat: &'static str,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TransportSettings {
+    pub transport: Option<String>,
+    pub serial_path: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TransportConfig {
+    Serial {
+        path: String,
+        baud_rate: u32,
+    },
+    Usb(Device),
+    Pty {
+        link_path: String,
+    },
+    Tcp {
+        address: String,
+    },
+    #[cfg(test)]
+    Channel(ChannelEnd),
+    Dummy,
+}
+
+// Hub side of an in-memory transport for tests; `TransportConfig::channel` returns the device side
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct ChannelEnd(std::sync::Arc<std::sync::Mutex<Option<tokio::io::DuplexStream>>>);
+
+impl TransportConfig {
+    // transport = "usb" | "serial:/dev/ttyACM0" | "pty:/tmp/macropad" | "tcp:127.0.0.1:7001" | "dummy"
+    pub fn resolve(settings: &TransportSettings, device: &Device) -> Result<Self, ConfigError> {
+        if let Some(value) = &settings.transport {
+            return Self::parse(value, device);
+        }
+
+        if cfg!(feature = "pi") {
+            return match &settings.serial_path {
+                Some(path) => Ok(TransportConfig::Serial {
+                    path: path.clone(),
+                    baud_rate: device.baud_rate,
+                }),
+                None => Ok(TransportConfig::Usb(device.clone())),
+            };
+        }
+
+        Ok(TransportConfig::Dummy)
+    }
+
+    pub fn parse(value: &str, device: &Device) -> Result<Self, ConfigError> {
+        let (kind, target) = value.split_once(':').unwrap_or((value, ""));
+
+        match kind {
+            "serial" => Ok(TransportConfig::Serial {
+                path: target.to_string(),
+                baud_rate: device.baud_rate,
+            }),
+            "usb" => Ok(TransportConfig::Usb(device.clone())),
+            "pty" => Ok(TransportConfig::Pty {
+                link_path: target.to_string(),
+            }),
+            "tcp" => Ok(TransportConfig::Tcp {
+                address: target.to_string(),
+            }),
+            "dummy" => Ok(TransportConfig::Dummy),
+            _ => Err(ConfigError::Invalid(
+                device.name.to_string(),
+                format!("unknown transport {:?}", value),
+            )),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn channel() -> (Self, tokio::io::DuplexStream) {
+        let (hub, device) = tokio::io::duplex(4096);
+
+        (
+            TransportConfig::Channel(ChannelEnd(std::sync::Arc::new(std::sync::Mutex::new(
+                Some(hub),
+            )))),
+            device,
+        )
+    }
+
+    pub async fn open(&self) -> Result<Connection, TransportError> {
+        match self {
+            TransportConfig::Serial { path, baud_rate } => {
+                let serial_port = tokio_serial::new(path, *baud_rate).open_native_async()?;
+                tracing::info!("serial opened: {}", path);
+
+                Ok(framed(serial_port))
+            }
+            TransportConfig::Usb(device) => discover(device).await,
+            TransportConfig::Pty { link_path } => {
+                let (master, slave) = tokio_serial::SerialStream::pair()?;
+                let slave_path = slave.name().ok_or("pty has no name")?;
+
+                let _ = tokio::fs::remove_file(link_path).await;
+                tokio::fs::symlink(&slave_path, link_path).await?;
+                tracing::info!("pty opened: {} -> {}", link_path, slave_path);
+
+                let mut connection = framed(master);
+
+                // Holding the slave open keeps the master readable while no simulator is attached
+                connection.receive = connection
+                    .receive
+                    .map(move |line| {
+                        let _ = &slave;
+                        line
+                    })
+                    .boxed();
+
+                Ok(connection)
+            }
+            TransportConfig::Tcp { address } => {
+                let stream = tokio::net::TcpStream::connect(address).await?;
+                tracing::info!("tcp connected: {}", address);
+
+                Ok(framed(stream))
+            }
+            #[cfg(test)]
+            TransportConfig::Channel(ChannelEnd(hub)) => {
+                let stream = hub.lock().unwrap().take().ok_or("channel already in use")?;
+
+                Ok(framed(stream))
+            }
+            TransportConfig::Dummy => Ok(Connection {
+                transmit: Box::new(DummySink),
+                receive: futures::stream::pending().boxed(),
+            }),
+        }
+    }
+
+    // Keeps the device connected in the background, reconnecting with backoff and buffering
+    // outbound lines while disconnected. `greeting` is sent first on every (re)connect. The task
+    // ends once the returned link is dropped.
+    pub fn connect(self, greeting: Option<String>) -> Link {
+        let (transmit, outbound) = tokio::sync::mpsc::channel::<String>(64);
+        let (inbound, receive) = futures::channel::mpsc::channel(64);
+
+        tokio::spawn(supervise(self, greeting, outbound, inbound));
+
+        Link {
+            transmit: Box::new(ChannelSink { inner: transmit }),
+            receive: receive.boxed(),
+        }
+    }
+}
+
+const OUTBOUND_BUFFER_LIMIT: usize = 256;
+const INITIAL_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+
+async fn supervise(
+    config: TransportConfig,
+    greeting: Option<String>,
+    mut outbound: tokio::sync::mpsc::Receiver<String>,
+    mut inbound: futures::channel::mpsc::Sender<Received>,
+) {
+    let mut buffer = VecDeque::new();
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let mut connection = match config.open().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!("! transport unavailable, retrying in {:?}: {}", backoff, e);
+
+                let retry_at = tokio::time::Instant::now() + backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(retry_at) => break,
+                        message = outbound.recv() => match message {
+                            Some(message) => enqueue(&mut buffer, message),
+                            None => return,
+                        },
+                    }
+                }
+
+                continue;
+            }
+        };
+
+        backoff = INITIAL_BACKOFF;
+        tracing::info!("= transport connected, flushing {} buffered", buffer.len());
+
+        if inbound.send(Received::Connected).await.is_err() {
+            return;
+        }
+
+        // A failed greeting shows up again as a failed read, which reconnects
+        if let Some(greeting) = &greeting
+            && let Err(e) = connection.transmit.send(greeting.clone()).await
+        {
+            tracing::warn!("! transport greeting failed: {}", e);
+        }
+
+        loop {
+            if let Some(message) = buffer.pop_front() {
+                if let Err(e) = connection.transmit.send(message.clone()).await {
+                    tracing::warn!("! transport send failed: {}", e);
+                    buffer.push_front(message);
+                    break;
+                }
+
+                continue;
+            }
+
+            tokio::select! {
+                message = outbound.recv() => match message {
+                    Some(message) => buffer.push_back(message),
+                    None => return,
+                },
+                line = connection.receive.next() => match line {
+                    Some(Ok(line)) => {
+                        if inbound.send(Received::Line(line)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(e)) => {
+                        tracing::warn!("! transport read failed: {}", e);
+                        break;
+                    }
+                    None => {
+                        tracing::warn!("! transport closed");
+                        break;
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn enqueue(buffer: &mut VecDeque<String>, message: String) {
+    if buffer.len() >= OUTBOUND_BUFFER_LIMIT {
+        tracing::warn!("! outbound buffer full, dropping oldest");
+        buffer.pop_front();
+    }
+
+    buffer.push_back(message);
+}
+
+async fn discover(device: &Device) -> Result<Connection, TransportError> {
+    for port in tokio_serial::available_ports()? {
+        let tokio_serial::SerialPortType::UsbPort(usb) = &port.port_type else {
+            continue;
+        };
+
+        if usb.vid != device.usb_vid || usb.pid != device.usb_pid {
+            continue;
+        }
+
+        let serial_port =
+            match tokio_serial::new(&port.port_name, device.baud_rate).open_native_async() {
+                Ok(serial_port) => serial_port,
+                Err(_) => continue,
+            };
+
+        let mut connection = framed(serial_port);
+
+        // Kept for the actor, a device that just booted sends its hello before any heartbeat
+        let mut received = Vec::new();
+
+        let handshake = tokio::time::timeout(std::time::Duration::from_secs(3), async {
+            while let Some(Ok(line)) = connection.receive.next().await {
+                let heartbeat = line == device.heartbeat;
+
+                received.push(Ok(line));
+
+                if heartbeat {
+                    return true;
+                }
+            }
+
+            false
+        })
+        .await;
+
+        if let Ok(true) = handshake {
+            tracing::info!("usb device found: {}", port.port_name);
+
+            connection.receive = futures::stream::iter(received)
+                .chain(connection.receive)
+                .boxed();
+
+            return Ok(connection);
+        }
+    }
+
+    Err(format!(
+        "no usb device {:04x}:{:04x} answered",
+        device.usb_vid, device.usb_pid
+    )
+    .into())
+}
+
+fn framed<T>(io: T) -> Connection
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (transmit, receive) = Framed::new(io, LinesCodec::new()).split::<String>();
+
+    Connection {
+        transmit: Box::new(FramedSink { inner: transmit }),
+        receive: receive.boxed(),
+    }
+}
@@ -2,22 +2,39 @@ use chrono::Timelike;
 use kameo::error::Infallible;
 use kameo::prelude::*;
 use kameo_actors::broker;
+use serde::Deserialize;
+
+use crate::inbox::{self, Inbox};
+use crate::restarting_manager::{self, RestartingManager};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnicornConfig {
+    #[serde(deserialize_with = "crate::config::deserialize_url")]
+    pub base_url: reqwest::Url,
+}
 
 pub struct Unicorn {
     client: reqwest::Client,
     base_url: reqwest::Url,
+    inbox_manager_ref: Option<ActorRef<RestartingManager<Inbox>>>,
+    inbox_synced: bool,
+    boot_id: Option<String>,
 }
 
 impl Actor for Unicorn {
-    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>,);
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        Option<ActorRef<RestartingManager<Inbox>>>,
+        UnicornConfig,
+    );
     type Error = Infallible;
 
     async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let broker_ref = state.0;
+        let (broker_ref, inbox_manager_ref, config) = state;
 
         broker_ref
             .tell(broker::Subscribe {
-                topic: "calendar".parse().unwrap(),
+                topic: "reminder".parse().unwrap(),
                 recipient: actor_ref.clone().recipient(),
             })
             .await
@@ -47,13 +64,147 @@ impl Actor for Unicorn {
             .await
             .unwrap();
 
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "idle".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        let tick_actor_ref = actor_ref.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+
+            loop {
+                interval.tick().await;
+
+                if tick_actor_ref.tell(Tick).await.is_err() {
+                    break;
+                }
+            }
+        });
+
         Ok(Self {
             client: reqwest::Client::new(),
-            base_url: reqwest::Url::parse(&std::env::var("UNICORN_BASE_URL").unwrap()).unwrap(),
+            base_url: config.base_url,
+            inbox_manager_ref,
+            inbox_synced: false,
+            boot_id: None,
         })
     }
 }
 
+pub struct Tick;
+
+impl Message<Tick> for Unicorn {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _message: Tick,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(inbox_manager_ref) = self.inbox_manager_ref.clone() else {
+            return;
+        };
+
+        let response = self
+            .client
+            .get(self.base_url.join("/").unwrap())
+            .timeout(std::time::Duration::from_secs(5))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        let Ok(response) = response else {
+            if self.inbox_synced {
+                tracing::warn!("! unicorn unreachable");
+            }
+            self.inbox_synced = false;
+            return;
+        };
+
+        // A reboot quicker than the Tick interval never shows up as unreachable
+        let boot_id = response
+            .headers()
+            .get("x-boot-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        if boot_id != self.boot_id {
+            if self.boot_id.is_some() {
+                tracing::info!("unicorn rebooted");
+            }
+            self.boot_id = boot_id;
+            self.inbox_synced = false;
+        }
+
+        if self.inbox_synced {
+            return;
+        }
+
+        match self.sync_inbox(&inbox_manager_ref).await {
+            Ok(count) => {
+                tracing::info!("unicorn inbox synced: {} pending", count);
+                self.inbox_synced = true;
+            }
+            Err(e) => tracing::warn!("! unicorn inbox sync failed: {}", e),
+        }
+    }
+}
+
+impl Unicorn {
+    // The Pico drops off the network now and then. Messages are kept in the inbox, so marking it
+    // unsynced has the next reachable Tick hand them over again.
+    async fn send(&mut self, request: reqwest::RequestBuilder) {
+        let result = request
+            .timeout(std::time::Duration::from_secs(5))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Err(e) = result {
+            tracing::warn!("! unicorn request failed: {}", e);
+            self.inbox_synced = false;
+        }
+    }
+
+    // The Unicorn forgets its inbox on reboot, so hand it the pending messages whenever it
+    // (re)appears. The endpoint replaces its inbox, which makes repeated syncs harmless.
+    async fn sync_inbox(
+        &self,
+        inbox_manager_ref: &ActorRef<RestartingManager<Inbox>>,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let entries = inbox_manager_ref
+            .ask(restarting_manager::GetChild)
+            .await?
+            .ask(inbox::GetEntries { pending_only: true })
+            .await?;
+
+        let messages: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "text": any_ascii::any_ascii(&entry.text),
+                    "effects": entry.effects,
+                    "read": false,
+                })
+            })
+            .collect();
+
+        self.client
+            .post(self.base_url.join("/sync-inbox").unwrap())
+            .json(&messages)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(messages.len())
+    }
+}
+
 impl Message<crate::BrokerMessage> for Unicorn {
     type Reply = ();
 
@@ -63,57 +214,53 @@ impl Message<crate::BrokerMessage> for Unicorn {
         _context: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         match message {
-            crate::BrokerMessage::CalendarEventUpcoming(event) => {
-                tracing::info!("unicorn message: {:?}", event);
+            crate::BrokerMessage::CalendarReminder(reminder) => {
+                tracing::info!("unicorn message: {:?}", reminder);
 
                 let now = chrono::Local::now();
 
-                let seconds = (event.start_at - now).num_seconds();
+                let seconds = (reminder.event.start_at - now).num_seconds();
 
-                self.client
-                    .get(self.base_url.join("/countdown").unwrap())
-                    .query(&[("seconds", seconds.to_string())])
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                // A reminder at the start itself has nothing left to count down
+                let request = if seconds > 0 {
+                    self.client
+                        .get(self.base_url.join("/countdown").unwrap())
+                        .query(&[("seconds", seconds.to_string())])
+                } else {
+                    self.client.get(self.base_url.join("/flash").unwrap())
+                };
+
+                self.send(request).await;
             }
             crate::BrokerMessage::StartCountdown(minutes) => {
                 tracing::info!("unicorn message: {:?}", minutes);
 
-                self.client
+                let request = self
+                    .client
                     .get(self.base_url.join("/countdown").unwrap())
-                    .query(&[("seconds", (minutes * 60).to_string())])
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                    .query(&[("seconds", (minutes * 60).to_string())]);
+
+                self.send(request).await;
             }
 
             crate::BrokerMessage::CancelAnimation => {
                 tracing::info!("unicorn: stopping countdown");
 
-                self.client
-                    .get(self.base_url.join("/cancel-animation").unwrap())
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                let request = self
+                    .client
+                    .get(self.base_url.join("/cancel-animation").unwrap());
+
+                self.send(request).await;
             }
             crate::BrokerMessage::StartTimestampCountdown(timestamp) => {
                 tracing::info!("unicorn message: {:?}", timestamp);
 
-                self.client
+                let request = self
+                    .client
                     .get(self.base_url.join("/countdown").unwrap())
-                    .query(&[("timestamp", timestamp.to_string())])
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                    .query(&[("timestamp", timestamp.to_string())]);
+
+                self.send(request).await;
             }
 
             crate::BrokerMessage::Message(message) => {
@@ -121,57 +268,52 @@ impl Message<crate::BrokerMessage> for Unicorn {
 
                 let text = any_ascii::any_ascii(&message.text);
 
-                self.client
+                let request = self
+                    .client
                     .post(self.base_url.join("/message").unwrap())
                     .json(&serde_json::json!({
                         "text": text,
                         "effects": message.effects,
                         "read": message.read,
-                    }))
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                    }));
+
+                self.send(request).await;
             }
             crate::BrokerMessage::ReadInbox => {
                 tracing::info!("read inbox");
 
-                self.client
-                    .get(self.base_url.join("/read-inbox").unwrap())
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                let request = self.client.get(self.base_url.join("/read-inbox").unwrap());
+
+                self.send(request).await;
             }
             crate::BrokerMessage::ClearInbox => {
                 tracing::info!("clear inbox");
 
-                self.client
-                    .get(self.base_url.join("/clear-inbox").unwrap())
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                let request = self.client.get(self.base_url.join("/clear-inbox").unwrap());
+
+                self.send(request).await;
             }
             crate::BrokerMessage::StartClock => {
                 tracing::info!("start clock");
 
                 let now = chrono::Local::now();
 
-                self.client
+                let request = self
+                    .client
                     .get(self.base_url.join("/start-clock").unwrap())
                     .query(&[(
                         "start_timestamp",
                         (now.hour() * 3600 + now.minute() * 60 + now.second()).to_string(),
-                    )])
-                    .send()
-                    .await
-                    .unwrap()
-                    .error_for_status()
-                    .unwrap();
+                    )]);
+
+                self.send(request).await;
+            }
+            crate::BrokerMessage::IdleTimeEntryDetected(idle) => {
+                tracing::info!("unicorn: flashing for idle {:?}", idle.description);
+
+                let request = self.client.get(self.base_url.join("/flash").unwrap());
+
+                self.send(request).await;
             }
             _ => {}
         }
@@ -1,42 +1,46 @@
 use axum::{Json, Router, http::StatusCode, routing::post};
-use chrono::TimeZone;
-use kameo::error::Infallible;
 use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UrbanConfig {
+    #[serde(
+        default = "default_listen_address",
+        deserialize_with = "crate::config::deserialize_socket_addr"
+    )]
+    pub listen_address: std::net::SocketAddr,
+    #[serde(default)]
+    pub auth: crate::webhook::AuthConfig,
+}
+
+// The keys `[urban.auth.<endpoint>]` accepts
+pub const ENDPOINTS: &[&str] = &["submit"];
+
+fn default_listen_address() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([0, 0, 0, 0], 9002))
+}
 
 pub struct Urban {
-    client: reqwest::Client,
-    readings_queue: Vec<serde_json::Value>,
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
     shutdown: Option<tokio::sync::oneshot::Sender<()>>,
 }
 
 impl Actor for Urban {
-    type Args = ();
-    type Error = Infallible;
-
-    async fn on_start(_state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
-        let mut headers = reqwest::header::HeaderMap::new();
+    type Args = (ActorRef<broker::Broker<crate::BrokerMessage>>, UrbanConfig);
+    type Error = crate::webhook::BindError;
 
-        headers.insert(
-            "DD-API-KEY",
-            reqwest::header::HeaderValue::from_str(&std::env::var("DATADOG_API_KEY").unwrap())
-                .unwrap(),
-        );
-
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .unwrap();
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
 
         let (shutdown, shutdown_receive) = tokio::sync::oneshot::channel();
 
-        let submit_actor_ref = actor_ref.clone();
-
-        tokio::spawn(async move {
-            let app = Router::new().route(
-                "/submit",
+        let app = Router::new().route(
+            "/submit",
+            crate::webhook::guard(
                 post(
                     |axum::extract::Json(payload): Json<serde_json::Value>| async move {
-                        submit_actor_ref
+                        actor_ref
                             .tell(UrbanReadings {
                                 payload: payload.clone(),
                             })
@@ -45,32 +49,29 @@ impl Actor for Urban {
                         StatusCode::OK
                     },
                 ),
-            );
-
-            let listener = tokio::net::TcpListener::bind("0.0.0.0:9002").await.unwrap();
-            axum::serve(listener, app)
-                .with_graceful_shutdown(async move {
-                    shutdown_receive.await.unwrap();
-                })
-                .await
-                .unwrap();
-        });
-
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+                "submit",
+                &config.auth,
+            ),
+        );
 
-            loop {
-                interval.tick().await;
+        let listener = crate::webhook::bind(config.listen_address).await?;
 
-                if actor_ref.tell(Tick).await.is_err() {
-                    break;
-                }
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(async move {
+                let _ = shutdown_receive.await;
+            })
+            .await
+            {
+                tracing::error!("! urban webhook stopped: {}", e);
             }
         });
 
         Ok(Self {
-            client,
-            readings_queue: Vec::new(),
+            broker_ref,
             shutdown: Some(shutdown),
         })
     }
@@ -81,7 +82,7 @@ impl Actor for Urban {
         _reason: ActorStopReason,
     ) -> Result<(), Self::Error> {
         if let Some(shutdown) = self.shutdown.take() {
-            shutdown.send(()).unwrap();
+            let _ = shutdown.send(());
         }
         Ok(())
     }
@@ -115,69 +116,65 @@ impl Message<UrbanReadings> for Urban {
         //     "tvoc": 0
         //   }
         // }
-        self.readings_queue.push(message.payload);
-    }
-}
-
-pub struct Tick;
+        let at = message.payload["timestamp"]
+            .as_str()
+            .and_then(parse_timestamp)
+            .unwrap_or_else(chrono::Utc::now);
 
-impl Message<Tick> for Urban {
-    type Reply = ();
-
-    async fn handle(
-        &mut self,
-        _message: Tick,
-        _context: &mut Context<Self, Self::Reply>,
-    ) -> Self::Reply {
-        if self.readings_queue.is_empty() {
-            return;
-        }
-
-        let keys = self.readings_queue[0]["readings"]
+        let readings: std::collections::BTreeMap<String, f64> = message.payload["readings"]
             .as_object()
-            .unwrap()
-            .keys();
-
-        let series = keys
-            .map(|key| {
-                let points = self
-                    .readings_queue
-                    .iter()
-                    .map(move |readings| {
-                        let timestamp = chrono::DateTime::parse_from_rfc3339(
-                            readings["timestamp"].as_str().unwrap(),
-                        )
-                        .unwrap()
-                        .with_timezone(&chrono::Utc)
-                        .timestamp();
-
-                        serde_json::json!({
-                            "timestamp": timestamp,
-                            "value": readings["readings"][key].as_f64().unwrap()
-                        })
-                    })
-                    .collect::<Vec<serde_json::Value>>();
-
-                serde_json::json!({
-                    "metric": format!("urban.{}", key),
-                    "type": 3,
-                    "points": points,
-                    "resources": [{ "name": "deskpi", "type": "host" }],
-                })
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, value)| Some((key.clone(), value.as_f64()?)))
+            .collect();
+
+        let points = readings
+            .iter()
+            .map(|(key, value)| {
+                crate::metrics::Point::gauge(format!("urban.{}", key), *value).at(at)
+            })
+            .collect();
+
+        crate::metrics::report(points, &self.broker_ref).await;
+
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "sensors".parse().unwrap(),
+                message: crate::BrokerMessage::SensorReadings(crate::SensorReadings {
+                    source: "urban".to_string(),
+                    readings,
+                    at,
+                }),
             })
-            .collect::<Vec<serde_json::Value>>();
-
-        self.client
-            .post("https://api.datadoghq.com/api/v2/series")
-            .json(&serde_json::json!({ "series": series }))
-            .send()
             .await
-            .unwrap()
-            .error_for_status()
             .unwrap();
+    }
+}
 
-        tracing::info!("submitted metrics: {:?}", series);
+// The Urban sends its clock's UTC time without an offset, as in the example above; RFC 3339 is
+// taken too
+fn parse_timestamp(timestamp: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&chrono::Utc))
+        .or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+                .map(|timestamp| timestamp.and_utc())
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_without_an_offset_are_utc() {
+        let expected = chrono::DateTime::parse_from_rfc3339("2025-09-20T07:32:29Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
 
-        self.readings_queue.clear();
+        assert_eq!(parse_timestamp("2025-09-20 07:32:29"), Some(expected));
+        assert_eq!(parse_timestamp("2025-09-20T09:32:29+02:00"), Some(expected));
+        assert_eq!(parse_timestamp("yesterday"), None);
     }
 }
@@ -0,0 +1,393 @@
+use kameo::prelude::*;
+use kameo_actors::broker;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::ConfigError;
+use crate::state_file;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WateringConfig {
+    #[serde(default)]
+    pub plants: Vec<PlantConfig>,
+    // Which plants need water, kept across restarts so their alerts still clear afterwards
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    "watering.json".to_string()
+}
+
+// `dry` and `wet` are the capacitance of the sensor in dry and freshly watered soil, read from
+// `GET /history/soil.a1`. Moisture is a percentage between the two.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlantConfig {
+    pub name: String,
+    // The Circuit Playground input the sensor is on, e.g. `a1`
+    pub input: String,
+    pub dry: f64,
+    pub wet: f64,
+    // Needs watering below `water_below` percent, and stops needing it once back above
+    // `watered_above`
+    #[serde(default = "default_water_below")]
+    pub water_below: f64,
+    #[serde(default = "default_watered_above")]
+    pub watered_above: f64,
+    // A rise of this many percentage points between two readings counts as being watered
+    #[serde(default = "default_jump")]
+    pub jump: f64,
+    #[serde(default)]
+    pub effects: Vec<String>,
+}
+
+fn default_water_below() -> f64 {
+    30.0
+}
+
+fn default_watered_above() -> f64 {
+    50.0
+}
+
+fn default_jump() -> f64 {
+    15.0
+}
+
+impl PlantConfig {
+    pub fn validate(plants: &[PlantConfig]) -> Result<(), ConfigError> {
+        for plant in plants {
+            if plant.dry == plant.wet {
+                return Err(ConfigError::Invalid(
+                    "watering".to_string(),
+                    format!("{} needs different dry and wet values", plant.name),
+                ));
+            }
+
+            if plant.watered_above < plant.water_below {
+                return Err(ConfigError::Invalid(
+                    "watering".to_string(),
+                    format!("{} has watered_above below water_below", plant.name),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn moisture(&self, capacitance: f64) -> f64 {
+        ((capacitance - self.dry) / (self.wet - self.dry) * 100.0).clamp(0.0, 100.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Plant {
+    moisture: Option<f64>,
+    needs_water: bool,
+}
+
+impl Plant {
+    // Returns whether the plant was just watered
+    fn update(&mut self, plant_config: &PlantConfig, moisture: f64) -> bool {
+        let watered = self
+            .moisture
+            .is_some_and(|previous| moisture - previous >= plant_config.jump);
+
+        self.moisture = Some(moisture);
+
+        if watered || moisture >= plant_config.watered_above {
+            self.needs_water = false;
+        } else if moisture < plant_config.water_below {
+            self.needs_water = true;
+        }
+
+        watered
+    }
+}
+
+// Turns the soil readings into a moisture percentage per plant, reminds on the Unicorn and the
+// ThinkInk when one gets dry and notices when it's watered
+pub struct Watering {
+    broker_ref: ActorRef<broker::Broker<crate::BrokerMessage>>,
+    config: WateringConfig,
+    plants: BTreeMap<String, Plant>,
+}
+
+impl Actor for Watering {
+    type Args = (
+        ActorRef<broker::Broker<crate::BrokerMessage>>,
+        WateringConfig,
+    );
+    type Error = state_file::StateFileError;
+
+    async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let (broker_ref, config) = state;
+
+        let mut plants: BTreeMap<String, Plant> = state_file::load(&config.path).await?;
+
+        plants.retain(|name, _| config.plants.iter().any(|plant| plant.name == *name));
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "sensors".parse().unwrap(),
+                recipient: actor_ref.clone().recipient(),
+            })
+            .await
+            .unwrap();
+
+        broker_ref
+            .tell(broker::Subscribe {
+                topic: "alert".parse().unwrap(),
+                recipient: actor_ref.recipient(),
+            })
+            .await
+            .unwrap();
+
+        let watering = Self {
+            broker_ref,
+            config,
+            plants,
+        };
+
+        watering.republish().await;
+
+        Ok(watering)
+    }
+}
+
+impl Message<crate::BrokerMessage> for Watering {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        message: crate::BrokerMessage,
+        _context: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let sensor_readings = match message {
+            crate::BrokerMessage::SensorReadings(sensor_readings) => sensor_readings,
+            crate::BrokerMessage::AlertsRequested => {
+                self.republish().await;
+                return;
+            }
+            _ => return,
+        };
+
+        // Also hears its own moisture readings
+        if sensor_readings.source != "soil" {
+            return;
+        }
+
+        let mut readings = BTreeMap::new();
+
+        for plant_config in self.config.plants.clone() {
+            let Some(capacitance) = sensor_readings.readings.get(&plant_config.input) else {
+                continue;
+            };
+
+            let moisture = plant_config.moisture(*capacitance);
+
+            self.update(&plant_config, moisture).await;
+
+            readings.insert(plant_config.name, moisture);
+        }
+
+        if readings.is_empty() {
+            return;
+        }
+
+        self.broker_ref
+            .tell(broker::Publish {
+                topic: "sensors".parse().unwrap(),
+                message: crate::BrokerMessage::SensorReadings(crate::SensorReadings {
+                    source: "plants".to_string(),
+                    readings,
+                    at: sensor_readings.at,
+                }),
+            })
+            .await
+            .unwrap();
+    }
+}
+
+impl Watering {
+    async fn update(&mut self, plant_config: &PlantConfig, moisture: f64) {
+        let plant = self.plants.entry(plant_config.name.clone()).or_default();
+
+        let was_needing_water = plant.needs_water;
+        let watered = plant.update(plant_config, moisture);
+        let needs_water = plant.needs_water;
+
+        if (watered || needs_water != was_needing_water)
+            && let Err(e) = self.save().await
+        {
+            tracing::error!("! could not save watering to {}: {}", self.config.path, e);
+        }
+
+        if watered {
+            tracing::info!("{} watered, now at {:.0}%", plant_config.name, moisture);
+
+            self.publish(
+                "plants",
+                crate::BrokerMessage::PlantWatered(crate::PlantWatered {
+                    name: plant_config.name.clone(),
+                    moisture,
+                }),
+            )
+            .await;
+
+            crate::metrics::Point::count("plants.watered", 1.0)
+                .tag(format!("plant:{}", plant_config.name))
+                .report(&self.broker_ref)
+                .await;
+        }
+
+        match (was_needing_water, needs_water) {
+            (false, true) => {
+                tracing::info!("{} needs watering at {:.0}%", plant_config.name, moisture);
+
+                // Shown on the ThinkInk like any other alert until the plant is watered
+                self.publish("alert", alert(plant_config, moisture)).await;
+
+                self.publish(
+                    "message",
+                    crate::BrokerMessage::Message(crate::Message {
+                        text: format!("Water the {}", plant_config.name),
+                        effects: plant_config.effects.clone(),
+                        read: false,
+                        source: "watering".to_string(),
+                    }),
+                )
+                .await;
+            }
+            (true, false) => {
+                self.publish(
+                    "alert",
+                    crate::BrokerMessage::AlertCleared(alert_name(plant_config)),
+                )
+                .await;
+            }
+            _ => {}
+        }
+    }
+
+    async fn republish(&self) {
+        for plant_config in &self.config.plants {
+            if let Some(plant) = self.plants.get(&plant_config.name)
+                && plant.needs_water
+            {
+                self.publish(
+                    "alert",
+                    alert(plant_config, plant.moisture.unwrap_or_default()),
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        state_file::save(&self.config.path, &self.plants).await
+    }
+
+    async fn publish(&self, topic: &str, message: crate::BrokerMessage) {
+        let _ = self
+            .broker_ref
+            .tell(broker::Publish {
+                topic: topic.parse().unwrap(),
+                message,
+            })
+            .await;
+    }
+}
+
+fn alert(plant_config: &PlantConfig, moisture: f64) -> crate::BrokerMessage {
+    crate::BrokerMessage::AlertRaised(crate::Alert {
+        name: alert_name(plant_config),
+        series: format!("plants.{}", plant_config.name),
+        value: moisture,
+    })
+}
+
+fn alert_name(plant_config: &PlantConfig) -> String {
+    format!("Water {}", plant_config.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Capacitance falls as the soil gets wetter
+    fn fern() -> PlantConfig {
+        PlantConfig {
+            name: "fern".to_string(),
+            input: "a1".to_string(),
+            dry: 900.0,
+            wet: 400.0,
+            water_below: default_water_below(),
+            watered_above: default_watered_above(),
+            jump: default_jump(),
+            effects: vec![],
+        }
+    }
+
+    #[test]
+    fn moisture_is_a_clamped_percentage_between_dry_and_wet() {
+        let fern = fern();
+
+        assert_eq!(fern.moisture(900.0), 0.0);
+        assert_eq!(fern.moisture(650.0), 50.0);
+        assert_eq!(fern.moisture(400.0), 100.0);
+        assert_eq!(fern.moisture(1000.0), 0.0);
+        assert_eq!(fern.moisture(300.0), 100.0);
+    }
+
+    #[test]
+    fn validate_rejects_equal_calibration_and_inverted_thresholds() {
+        assert!(PlantConfig::validate(&[fern()]).is_ok());
+        assert!(
+            PlantConfig::validate(&[PlantConfig {
+                wet: 900.0,
+                ..fern()
+            }])
+            .is_err()
+        );
+        assert!(
+            PlantConfig::validate(&[PlantConfig {
+                watered_above: 20.0,
+                ..fern()
+            }])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn needs_water_below_the_threshold_until_back_above() {
+        let fern = fern();
+        let mut plant = Plant::default();
+
+        plant.update(&fern, 40.0);
+        assert!(!plant.needs_water);
+
+        plant.update(&fern, 29.0);
+        assert!(plant.needs_water);
+
+        // Between the thresholds nothing changes
+        plant.update(&fern, 40.0);
+        assert!(plant.needs_water);
+
+        plant.update(&fern, 50.0);
+        assert!(!plant.needs_water);
+    }
+
+    #[test]
+    fn a_jump_counts_as_watered() {
+        let fern = fern();
+        let mut plant = Plant::default();
+
+        assert!(!plant.update(&fern, 20.0));
+        assert!(plant.needs_water);
+
+        assert!(!plant.update(&fern, 30.0));
+        assert!(plant.update(&fern, 45.0));
+        assert!(!plant.needs_water);
+    }
+}
@@ -0,0 +1,351 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+
+// Sent as `sha256=<hex>`, like GitHub's webhooks
+const SIGNATURE_HEADER: &str = "x-signature-256";
+
+const MAXIMUM_BODY_BYTES: usize = 1024 * 1024;
+
+// What desk.example.toml ships with, refused so a copied config can't go live with it
+const PLACEHOLDER: &str = "change-me";
+
+// An endpoint may require a bearer token, an HMAC-SHA256 signature of the body, or both. One
+// without either has to say so with `allow_unauthenticated`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EndpointAuth {
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    #[serde(default)]
+    pub hmac_secret: Option<String>,
+    #[serde(default)]
+    pub allow_unauthenticated: bool,
+}
+
+impl EndpointAuth {
+    fn is_open(&self) -> bool {
+        self.bearer_token.is_none() && self.hmac_secret.is_none()
+    }
+}
+
+pub type AuthConfig = std::collections::HashMap<String, EndpointAuth>;
+
+// Checked at config load: every key names one of the section's `endpoints`, and each endpoint is
+// protected, explicitly open, or only reachable from the Pi itself
+pub fn validate(
+    section: &str,
+    listen_address: std::net::SocketAddr,
+    auth: &AuthConfig,
+    endpoints: &[&str],
+) -> Result<(), ConfigError> {
+    if let Some(unknown) = auth.keys().find(|key| !endpoints.contains(&key.as_str())) {
+        return Err(ConfigError::Invalid(
+            section.to_string(),
+            format!(
+                "unknown auth endpoint {:?}, expected one of {}",
+                unknown,
+                endpoints.join(", ")
+            ),
+        ));
+    }
+
+    for endpoint in endpoints {
+        validate_endpoint(section, endpoint, listen_address, auth.get(*endpoint))?;
+    }
+
+    Ok(())
+}
+
+pub fn validate_endpoint(
+    section: &str,
+    endpoint: &str,
+    listen_address: std::net::SocketAddr,
+    auth: Option<&EndpointAuth>,
+) -> Result<(), ConfigError> {
+    let invalid = |reason: &str| {
+        Err(ConfigError::Invalid(
+            section.to_string(),
+            format!("{} {}", endpoint, reason),
+        ))
+    };
+
+    let Some(auth) = auth.filter(|auth| !auth.is_open()) else {
+        if listen_address.ip().is_loopback() || auth.is_some_and(|auth| auth.allow_unauthenticated)
+        {
+            return Ok(());
+        }
+
+        return invalid("needs bearer_token, hmac_secret or allow_unauthenticated = true");
+    };
+
+    if auth.bearer_token.as_deref() == Some(PLACEHOLDER)
+        || auth.hmac_secret.as_deref() == Some(PLACEHOLDER)
+    {
+        return invalid(&format!("still has the {:?} placeholder", PLACEHOLDER));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct Guard {
+    endpoint: String,
+    auth: EndpointAuth,
+}
+
+#[derive(Debug)]
+pub struct BindError(pub std::net::SocketAddr, pub std::io::Error);
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not listen on {}: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for BindError {}
+
+// Awaited in `on_start`, so a taken port stops the webhook's actor
+pub async fn bind(address: std::net::SocketAddr) -> Result<tokio::net::TcpListener, BindError> {
+    tokio::net::TcpListener::bind(address)
+        .await
+        .map_err(|e| BindError(address, e))
+}
+
+// Endpoints left open passed `validate`, so they're open on purpose
+pub fn guard<S>(route: MethodRouter<S>, endpoint: &str, auth: &AuthConfig) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let Some(endpoint_auth) = auth.get(endpoint).filter(|auth| !auth.is_open()) else {
+        tracing::warn!("! /{} accepts unauthenticated requests", endpoint);
+        return route;
+    };
+
+    route.layer(middleware::from_fn_with_state(
+        Guard {
+            endpoint: endpoint.to_string(),
+            auth: endpoint_auth.clone(),
+        },
+        verify,
+    ))
+}
+
+// Every route of an API behind the same credentials
+pub fn guard_router<S>(router: Router<S>, name: &str, auth: &EndpointAuth) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if auth.is_open() {
+        tracing::warn!("! {} accepts unauthenticated requests", name);
+        return router;
+    }
+
+    router.route_layer(middleware::from_fn_with_state(
+        Guard {
+            endpoint: name.to_string(),
+            auth: auth.clone(),
+        },
+        verify,
+    ))
+}
+
+async fn verify(State(guard): State<Guard>, request: Request, next: Next) -> Response {
+    let remote_address = request
+        .extensions()
+        .get::<ConnectInfo<std::net::SocketAddr>>()
+        .map(|ConnectInfo(address)| address.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    if let Some(bearer_token) = &guard.auth.bearer_token {
+        let provided = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(provided_token);
+
+        if !provided.is_some_and(|provided| constant_time_eq(&provided, bearer_token)) {
+            return reject(&guard, &remote_address, "missing or wrong bearer token");
+        }
+    }
+
+    let Some(hmac_secret) = &guard.auth.hmac_secret else {
+        return next.run(request).await;
+    };
+
+    // The body has to be read to check it, then handed on to the handler
+    let (parts, body) = request.into_parts();
+
+    let Ok(bytes) = axum::body::to_bytes(body, MAXIMUM_BODY_BYTES).await else {
+        tracing::warn!(
+            "! rejected /{} request from {}: body too large",
+            guard.endpoint,
+            remote_address
+        );
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+
+    let signature = parts
+        .headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(|value| hex::decode(value).ok());
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(hmac_secret.as_bytes()).unwrap();
+    mac.update(&bytes);
+
+    if signature.is_none_or(|signature| mac.verify_slice(&signature).is_err()) {
+        return reject(&guard, &remote_address, "missing or wrong signature");
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+fn reject(guard: &Guard, remote_address: &str, reason: &str) -> Response {
+    tracing::warn!(
+        "! rejected /{} request from {}: {}",
+        guard.endpoint,
+        remote_address,
+        reason
+    );
+
+    StatusCode::UNAUTHORIZED.into_response()
+}
+
+// Devices that only do basic auth, like the Enviro Urban's HTTP upload, send the token as the
+// password
+fn provided_token(authorization: &str) -> Option<String> {
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        return Some(token.to_string());
+    }
+
+    let credentials = BASE64_STANDARD
+        .decode(authorization.strip_prefix("Basic ")?)
+        .ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_, password) = credentials.split_once(':')?;
+
+    Some(password.to_string())
+}
+
+// Doesn't stop at the first difference, so the token can't be guessed from response times
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(entries: &[(&str, EndpointAuth)]) -> AuthConfig {
+        entries
+            .iter()
+            .map(|(endpoint, auth)| (endpoint.to_string(), auth.clone()))
+            .collect()
+    }
+
+    fn token(token: &str) -> EndpointAuth {
+        EndpointAuth {
+            bearer_token: Some(token.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_requires_every_endpoint_to_be_protected_or_opted_out() {
+        let public = "0.0.0.0:9001".parse().unwrap();
+        let endpoints = &["calendar", "message"];
+
+        let open = EndpointAuth {
+            allow_unauthenticated: true,
+            ..Default::default()
+        };
+
+        assert!(
+            validate(
+                "home_assistant",
+                public,
+                &auth(&[("calendar", token("secret")), ("message", open)]),
+                endpoints,
+            )
+            .is_ok()
+        );
+        assert!(
+            validate(
+                "home_assistant",
+                public,
+                &auth(&[("calendar", token("secret"))]),
+                endpoints,
+            )
+            .is_err()
+        );
+        // Only reachable from the Pi
+        assert!(
+            validate(
+                "home_assistant",
+                "127.0.0.1:9001".parse().unwrap(),
+                &auth(&[]),
+                endpoints
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unknown_endpoints_and_placeholders() {
+        let public = "0.0.0.0:9002".parse().unwrap();
+
+        assert!(
+            validate(
+                "urban",
+                public,
+                &auth(&[("sumbit", token("secret"))]),
+                &["submit"]
+            )
+            .is_err()
+        );
+        assert!(
+            validate(
+                "urban",
+                public,
+                &auth(&[("submit", token(PLACEHOLDER))]),
+                &["submit"]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn tokens_from_bearer_or_basic_auth() {
+        assert_eq!(provided_token("Bearer abc"), Some("abc".to_string()));
+        assert_eq!(
+            provided_token(&format!("Basic {}", BASE64_STANDARD.encode("urban:abc"))),
+            Some("abc".to_string())
+        );
+        assert_eq!(provided_token("Basic not-base64!"), None);
+        assert_eq!(provided_token("Token abc"), None);
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+    }
+}
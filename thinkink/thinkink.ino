@@ -159,6 +159,11 @@ void setup() {
     resetSand();
 
     Serial.begin(115200);
+    sendHello();
+}
+
+void sendHello() {
+    Serial.write("{\"kind\":\"hello\",\"version\":1}\n");
 }
 
 uint16_t gammaCorrected(uint16_t input) {
@@ -258,6 +263,11 @@ void loop() {
             messageReady = false;
 
             if (error == DeserializationError::Ok) {
+                // The hub greets on every connect and waits for the answer
+                if (message["kind"] == "hello") {
+                    sendHello();
+                }
+
                 if (message["kind"] == "light") {
                     targetValue = message["targetValue"];
                     speed = message["speed"];
@@ -32,6 +32,9 @@ unicorn.set_brightness(0.5)
 # 53x11
 WIDTH, HEIGHT = graphics.get_bounds()
 
+# Sent with GET / so the hub notices a reboot, and the inbox it lost, between two of its checks
+BOOT_ID = str(random.getrandbits(32))
+
 def get_pixel(x, y):
     start = (y * WIDTH + x) * 4
     return tuple(graphics_view[start:start+3])
@@ -143,7 +146,9 @@ async def run_animations():
 
 @server.route("/", methods=["GET"])
 def index(request):
-    return send_file("index.html")
+    response = send_file("index.html")
+    response.headers["X-Boot-Id"] = BOOT_ID
+    return response
 
 ENABLE_TUNE = True
 
@@ -216,6 +221,15 @@ async def message(request):
     enqueue_animation(message_animation(request.json), priority=1)
     return 'message'
 
+@server.route("/sync-inbox", methods=["POST"])
+async def sync_inbox(request):
+    global message_inbox
+
+    message_inbox.clear()
+    message_inbox.extend(request.json)
+    enqueue_animation(inbox_animation(), priority=3)
+    return 'synced'
+
 @server.route("/clear-inbox", methods=["GET"])
 async def clear_inbox(request):
     global message_inbox
@@ -241,6 +255,11 @@ async def start_clock(request):
     enqueue_animation(clock_animation(start_timestamp), priority=5)
     return 'started'
 
+@server.route("/flash", methods=["GET"])
+async def flash(request):
+    enqueue_animation(flash_animation(), priority=1)
+    return 'flashing'
+
 @server.route("/spacex", methods=["GET"])
 async def spacex(request):
     enqueue_animation(spacex_animation(), priority=2)
@@ -839,6 +858,18 @@ async def inbox_animation():
         if len(snake) == 0:
             break
 
+async def flash_animation():
+    sleep_reset()
+
+    for frame in range(6 * 20):
+        await sleep_frame()
+
+        graphics.set_pen(WHITE if (frame // 10) % 2 == 0 else BLACK)
+        graphics.clear()
+        unicorn.update(graphics)
+
+    enqueue_animation(inbox_animation(), priority=3)
+
 async def message_animation(message):
     global message_inbox
 